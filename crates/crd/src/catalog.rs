@@ -0,0 +1,377 @@
+//! Typed kinds of the spark catalogs: Iceberg, Delta, Hudi and the Hive session catalog.
+//!
+//! Each kind renders into the same flat `spark.sql.catalog.<name>.<key>` configs as the free-form
+//! `catalogConfigs`, and knows its session extensions and runtime packages of the spark and scala version.
+
+use std::collections::HashMap;
+
+use k8s_openapi::api::core::v1::{ConfigMapKeySelector, EnvVar, EnvVarSource, SecretKeySelector};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+use crate::{Error, Result};
+
+pub const SPARK_SESSION_CATALOG: &str = "spark_catalog";
+pub const SPARK_SQL_EXTENSIONS: &str = "spark.sql.extensions";
+pub const DEFAULT_SCALA_VERSION: &str = "2.12";
+pub const ICEBERG_DEFAULT_VERSION: &str = "1.4.3";
+pub const NESSIE_DEFAULT_VERSION: &str = "0.77.1";
+pub const SPARK_SUBMIT_OPTS_ENV: &str = "SPARK_SUBMIT_OPTS";
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CatalogKind {
+    Iceberg(IcebergCatalog),
+    /// Delta lake, only as the session catalog `spark_catalog`
+    Delta(DeltaCatalog),
+    /// Hudi, only as the session catalog `spark_catalog`
+    Hudi(HudiCatalog),
+    /// The Hive metastore as the session catalog
+    Hive(HiveCatalog),
+}
+
+#[derive(
+    Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum IcebergBackend {
+    #[default]
+    Hive,
+    Hadoop,
+    Rest,
+    Jdbc,
+    Nessie,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IcebergCatalog {
+    #[serde(default)]
+    pub backend: IcebergBackend,
+    /// the uri of the metastore, REST or nessie server, or the JDBC url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    /// for example: `s3a://spark-dwh/iceberg`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warehouse: Option<String>,
+    /// the branch or tag of nessie, defaults to `main`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nessie_ref: Option<String>,
+    /// defaults to `1.4.3`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// defaults to `2.12`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scala_version: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaCatalog {
+    /// defaults to the release of the spark version, for example `2.4.0` of spark 3.4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// defaults to `2.12`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scala_version: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HudiCatalog {
+    /// defaults to the release of the spark version, for example `0.14.1` of spark 3.4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// defaults to `2.12`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scala_version: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HiveCatalog {
+    /// the uri of the metastore, for example: `thrift://hive-metastore:9083`,
+    /// required unless the catalog references a `HiveMetastore`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warehouse: Option<String>,
+}
+
+/// The rendered settings of a catalog kind
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CatalogRuntime {
+    /// the implementation class of `spark.sql.catalog.<name>`, none for the Hive session catalog
+    pub impl_class: Option<String>,
+    /// the catalog configs, without the `spark.sql.catalog.<name>.` prefix
+    pub catalog_configs: HashMap<String, String>,
+    /// the spark configs applied directly
+    pub spark_configs: HashMap<String, String>,
+    /// the classes of `spark.sql.extensions`
+    pub extensions: Vec<String>,
+    /// the maven coordinates of the runtime, of the spark version
+    pub packages: Vec<String>,
+}
+
+/// A config value from a key of a Secret or ConfigMap, which is injected into the pods as an env-var
+/// and referenced by the config, rather than written into the application.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigValueFrom {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_key_ref: Option<SecretKeySelector>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_map_key_ref: Option<ConfigMapKeySelector>,
+}
+
+impl ConfigValueFrom {
+    pub fn is_secret(&self) -> bool {
+        self.secret_key_ref.is_some()
+    }
+
+    /// The env-var holding the value of the config `key`, either of the refs should be set
+    pub fn env_var(&self, key: &str) -> Option<EnvVar> {
+        let value_from = match (&self.secret_key_ref, &self.config_map_key_ref) {
+            (Some(secret_key_ref), None) => EnvVarSource {
+                secret_key_ref: Some(secret_key_ref.clone()),
+                ..EnvVarSource::default()
+            },
+            (None, Some(config_map_key_ref)) => EnvVarSource {
+                config_map_key_ref: Some(config_map_key_ref.clone()),
+                ..EnvVarSource::default()
+            },
+            _ => return None,
+        };
+        Some(EnvVar {
+            name: conf_env_var_name(key),
+            value: None,
+            value_from: Some(value_from),
+        })
+    }
+}
+
+/// The env-var of the config `key`, for example: `SPARK_CONF_SPARK_SQL_CATALOG_REST_TOKEN`
+pub fn conf_env_var_name(key: &str) -> String {
+    let key = key
+        .to_uppercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    format!("SPARK_CONF_{key}")
+}
+
+/// `3.4` of `3.4.1`
+fn spark_minor_version(spark_version: &str) -> Option<&str> {
+    let mut dots = spark_version.match_indices('.');
+    match (dots.next(), dots.next()) {
+        (Some(_), Some((i, _))) => Some(&spark_version[..i]),
+        (Some(_), None) => Some(spark_version),
+        _ => None,
+    }
+}
+
+impl CatalogKind {
+    /// Render the catalog `name` of the kind, for the applications of `spark_version`
+    pub fn runtime(&self, name: &str, spark_version: &str) -> Result<CatalogRuntime> {
+        let invalid = |reason: String| Error::InvalidCatalog {
+            name: name.to_string(),
+            reason,
+        };
+        let spark = spark_minor_version(spark_version)
+            .ok_or_else(|| invalid(format!("unknown spark version [{spark_version}]")))?;
+        let session_only = |kind: &str| {
+            if name == SPARK_SESSION_CATALOG {
+                Ok(())
+            } else {
+                Err(invalid(format!("{kind} is only supported as the session catalog [{SPARK_SESSION_CATALOG}]")))
+            }
+        };
+
+        let runtime = match self {
+            CatalogKind::Iceberg(iceberg) => {
+                let required: &[(&str, &Option<String>)] = match iceberg.backend {
+                    IcebergBackend::Hive | IcebergBackend::Rest => &[("uri", &iceberg.uri)],
+                    IcebergBackend::Hadoop => &[("warehouse", &iceberg.warehouse)],
+                    IcebergBackend::Jdbc | IcebergBackend::Nessie => {
+                        &[("uri", &iceberg.uri), ("warehouse", &iceberg.warehouse)]
+                    }
+                };
+                if let Some((key, _)) = required.iter().find(|(_, v)| v.is_none()) {
+                    return Err(invalid(format!("{key} is required by the {} backend of iceberg", iceberg.backend)));
+                }
+
+                let mut catalog_configs = HashMap::new();
+                let mut extensions = vec!["org.apache.iceberg.spark.extensions.IcebergSparkSessionExtensions".to_string()];
+                let scala = iceberg.scala_version.as_deref().unwrap_or(DEFAULT_SCALA_VERSION);
+                let mut packages = vec![format!(
+                    "org.apache.iceberg:iceberg-spark-runtime-{spark}_{scala}:{}",
+                    iceberg.version.as_deref().unwrap_or(ICEBERG_DEFAULT_VERSION)
+                )];
+                match iceberg.backend {
+                    IcebergBackend::Nessie => {
+                        catalog_configs.insert(
+                            "catalog-impl".to_string(),
+                            "org.apache.iceberg.nessie.NessieCatalog".to_string(),
+                        );
+                        catalog_configs.insert(
+                            "ref".to_string(),
+                            iceberg.nessie_ref.clone().unwrap_or_else(|| "main".to_string()),
+                        );
+                        extensions.push("org.projectnessie.spark.extensions.NessieSparkSessionExtensions".to_string());
+                        packages.push(format!(
+                            "org.projectnessie.nessie-integrations:nessie-spark-extensions-{spark}_{scala}:{NESSIE_DEFAULT_VERSION}"
+                        ));
+                    }
+                    ref backend => {
+                        catalog_configs.insert("type".to_string(), backend.to_string());
+                    }
+                }
+                if let Some(uri) = &iceberg.uri {
+                    catalog_configs.insert("uri".to_string(), uri.clone());
+                }
+                if let Some(warehouse) = &iceberg.warehouse {
+                    catalog_configs.insert("warehouse".to_string(), warehouse.clone());
+                }
+                // the session catalog falls back to the built-in one for the non iceberg tables
+                let impl_class = if name == SPARK_SESSION_CATALOG {
+                    "org.apache.iceberg.spark.SparkSessionCatalog"
+                } else {
+                    "org.apache.iceberg.spark.SparkCatalog"
+                };
+                CatalogRuntime {
+                    impl_class: Some(impl_class.to_string()),
+                    catalog_configs,
+                    spark_configs: HashMap::new(),
+                    extensions,
+                    packages,
+                }
+            }
+            CatalogKind::Delta(delta) => {
+                session_only("delta")?;
+                let (artifact, default_version) = match spark {
+                    "3.2" => ("delta-core", "2.0.2"),
+                    "3.3" => ("delta-core", "2.3.0"),
+                    "3.4" => ("delta-core", "2.4.0"),
+                    "3.5" => ("delta-spark", "3.1.0"),
+                    _ => return Err(invalid(format!("no delta release known for spark {spark}"))),
+                };
+                let scala = delta.scala_version.as_deref().unwrap_or(DEFAULT_SCALA_VERSION);
+                CatalogRuntime {
+                    impl_class: Some("org.apache.spark.sql.delta.catalog.DeltaCatalog".to_string()),
+                    extensions: vec!["io.delta.sql.DeltaSparkSessionExtension".to_string()],
+                    packages: vec![format!(
+                        "io.delta:{artifact}_{scala}:{}",
+                        delta.version.as_deref().unwrap_or(default_version)
+                    )],
+                    ..CatalogRuntime::default()
+                }
+            }
+            CatalogKind::Hudi(hudi) => {
+                session_only("hudi")?;
+                let default_version = match spark {
+                    "3.2" | "3.3" | "3.4" => "0.14.1",
+                    "3.5" => "0.15.0",
+                    _ => return Err(invalid(format!("no hudi release known for spark {spark}"))),
+                };
+                let scala = hudi.scala_version.as_deref().unwrap_or(DEFAULT_SCALA_VERSION);
+                CatalogRuntime {
+                    impl_class: Some("org.apache.spark.sql.hudi.catalog.HoodieCatalog".to_string()),
+                    spark_configs: HashMap::from([
+                        (
+                            "spark.serializer".to_string(),
+                            "org.apache.spark.serializer.KryoSerializer".to_string(),
+                        ),
+                        (
+                            "spark.kryo.registrator".to_string(),
+                            "org.apache.spark.HoodieSparkKryoRegistrar".to_string(),
+                        ),
+                    ]),
+                    extensions: vec!["org.apache.spark.sql.hudi.HoodieSparkSessionExtension".to_string()],
+                    packages: vec![format!(
+                        "org.apache.hudi:hudi-spark{spark}-bundle_{scala}:{}",
+                        hudi.version.as_deref().unwrap_or(default_version)
+                    )],
+                    ..CatalogRuntime::default()
+                }
+            }
+            CatalogKind::Hive(hive) => {
+                session_only("hive")?;
+                let uri = hive
+                    .uri
+                    .clone()
+                    .ok_or_else(|| invalid("uri is required by hive".to_string()))?;
+                let mut spark_configs = HashMap::from([
+                    ("spark.sql.catalogImplementation".to_string(), "hive".to_string()),
+                    ("spark.hadoop.hive.metastore.uris".to_string(), uri),
+                ]);
+                if let Some(warehouse) = &hive.warehouse {
+                    spark_configs.insert("spark.sql.warehouse.dir".to_string(), warehouse.clone());
+                }
+                CatalogRuntime {
+                    spark_configs,
+                    ..CatalogRuntime::default()
+                }
+            }
+        };
+        Ok(runtime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(yaml: &str) -> CatalogKind {
+        let value: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_iceberg_runtime() {
+        let iceberg = kind("
+        iceberg:
+          backend: rest
+          uri: http://iceberg-rest:8181
+          warehouse: s3a://spark-dwh/iceberg
+        ");
+        let runtime = iceberg.runtime("ib_rest_cat", "3.4.1").unwrap();
+        assert_eq!(runtime.impl_class.as_deref(), Some("org.apache.iceberg.spark.SparkCatalog"));
+        assert_eq!(runtime.catalog_configs["type"], "rest");
+        assert_eq!(runtime.catalog_configs["uri"], "http://iceberg-rest:8181");
+        assert_eq!(
+            runtime.packages,
+            vec!["org.apache.iceberg:iceberg-spark-runtime-3.4_2.12:1.4.3"]
+        );
+
+        let missing = kind("
+        iceberg:
+          backend: hadoop
+        ");
+        assert!(matches!(
+            missing.runtime("ib_hadoop_cat", "3.4.1"),
+            Err(Error::InvalidCatalog { reason, .. }) if reason.contains("warehouse")
+        ));
+    }
+
+    #[test]
+    fn test_config_value_from() {
+        let from: ConfigValueFrom = serde_yaml::from_str("
+        secretKeyRef:
+          name: rest-catalog
+          key: token
+        ").unwrap();
+        let env_var = from.env_var("spark.sql.catalog.rest.token").unwrap();
+        assert_eq!(env_var.name, "SPARK_CONF_SPARK_SQL_CATALOG_REST_TOKEN");
+        assert_eq!(env_var.value_from.unwrap().secret_key_ref.unwrap().key, "token");
+        assert!(ConfigValueFrom::default().env_var("spark.a").is_none());
+    }
+
+    #[test]
+    fn test_session_only_kinds() {
+        let delta = kind("delta: {}");
+        assert!(delta.runtime("delta_cat", "3.4.1").is_err());
+        let runtime = delta.runtime(SPARK_SESSION_CATALOG, "3.5.0").unwrap();
+        assert_eq!(runtime.packages, vec!["io.delta:delta-spark_2.12:3.1.0"]);
+        assert!(kind("hudi: {}").runtime(SPARK_SESSION_CATALOG, "3.1.3").is_err());
+    }
+}
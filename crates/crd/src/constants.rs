@@ -11,12 +11,17 @@ pub const CONTROLLER_KIND_SKO_SCHEDULED_APPLICATION: &str = "ScheduledSparkAppli
 pub const CONTROLLER_NAME_JOB: &str = "sparkjob";
 pub const CONTROLLER_NAME_SCHD_JOB: &str = "sparkscheduledjob";
 pub const CONTROLLER_NAME_SESSION: &str = "sparksession";
+pub const CONTROLLER_NAME_HIVE_METASTORE: &str = "hivemetastore";
+pub const CONTROLLER_NAME_HISTORY_SERVER: &str = "sparkhistoryserver";
 
 pub const CONTROLLER_NAME_SKO_APPLICATION: &str = "sko-application";
 
 pub const SKO_DEFAULT_SERVICE_ACCOUNT: &str = "sparkoperator-spark";
+pub const SKO_APP_NAME_LABEL: &str = "sparkoperator.k8s.io/app-name";
+pub const SPARK_ROLE_LABEL: &str = "spark-role";
 
 pub const RESOURCE_ROLE_SKO: &str = "spark-k8s-operator";
+pub const RESOURCE_ROLE_HIVE_METASTORE: &str = "metastore";
 pub const SPARK_DEFAULT_VERSION: &str = "3.1.1";
 
 pub const SKO_APPLICATION_TYPE_SCALA: &str = "Scala";
@@ -31,11 +36,9 @@ pub const SPARK_SESSION_MAIN_CLASS: &str = "org.apache.spark.sql.hive.thriftserv
 // ------------
 // spark dirver thrift constants
 pub const HIVE_SERVER2_THRIFT_DEFAULT_PORT: i32 = 10001;
-pub const HIVE_SERVER2_THRIFT_DEFAULT_NODE_PORT: i32 = 10001;
 pub const HIVE_SERVER2_THRIFT_DEFAULT_SERVICE_TYPE: &str = "NodePort";
 // spark dirver ui constants
 pub const HIVE_SERVER2_UI_DEFAULT_PORT: i32 = 8009;
-pub const HIVE_SERVER2_UI_DEFAULT_NODE_PORT: i32 = 8009;
 pub const HIVE_SERVER2_UI_DEFAULT_SERVICE_TYPE: &str = "NodePort";
 
 // config names
@@ -45,10 +48,21 @@ pub const SPARK_HIVE_SERVER2_THRIFT_BIND_HOST: &str = "spark.hive.server2.thrift
 pub const SPARK_HIVE_SERVER2_THRIFT_PORT: &str = "spark.hive.server2.thrift.port";
 pub const SPARK_HIVE_SERVER2_ENABLE_DOAS: &str = "spark.hive.server2.enable.doAs";
 
+// ------------
+// spark template constants
+pub const SPARK_TEMPLATE_MAX_DEPTH: usize = 8;
+pub const SPARK_TEMPLATE_CHAIN_ANNOTATION: &str = "spark.bytenative.com/template-chain";
+
+// ------------
+// provenance report constants
+pub const PROVENANCE_CONFIG_MAP_SUFFIX: &str = "-provenance";
+pub const PROVENANCE_REPORT_FILE_NAME: &str = "provenance.yaml";
 
 
 
-// TODO:
+
+// ------------
+// history server constants
 pub const HISTORY_ROLE_NAME: &str = "node";
 pub const HISTORY_IMAGE_BASE_NAME: &str = "spark-k8s";
 pub const HISTORY_CONFIG_FILE_NAME: &str = "spark-defaults.conf";
@@ -57,11 +71,46 @@ pub const HISTORY_CONFIG_FILE_NAME_FULL: &str = "/bytenative/spark/conf/spark-de
 pub const SPARK_CLUSTER_ROLE: &str = "spark-k8s-clusterrole";
 pub const SPARK_UID: i64 = 1000;
 
+pub const SPARK_GPU_RESOURCE_NAME: &str = "gpu";
+pub const SPARK_GPU_DISCOVERY_SCRIPT: &str = "/opt/spark/examples/src/main/scripts/getGpusResources.sh";
+
+pub const SPARK_REDACTION_REGEX: &str = "spark.redaction.regex";
+pub const SPARK_REDACTION_REGEX_DEFAULT: &str = "(?i)secret|password|token|access[.]key|keytab";
+pub const SPARK_WARNINGS_ANNOTATION: &str = "spark.bytenative.com/warnings";
+
+pub const SECURITY_PROFILE_LABEL: &str = "spark.bytenative.com/security-profile";
+pub const POD_SECURITY_ENFORCE_LABEL: &str = "pod-security.kubernetes.io/enforce";
+pub const SECCOMP_PROFILE_RUNTIME_DEFAULT: &str = "RuntimeDefault";
+pub const SCRATCH_VOLUME_NAME: &str = "spark-scratch";
+pub const SCRATCH_DIR_NAME: &str = "/tmp";
+
 
 pub const SQL_FILE_CONFIG_MAP_PREFIX: &str = "sql-statement-";
 pub const SQL_FILE_LOCAL_FILE_NAME: &str = "statement.sql";
 pub const SQL_FILE_LOCAL_DIR_NAME: &str = "/bytenative/sqls";
 
+pub const SPARK_DRIVER_POD_TEMPLATE_FILE: &str = "spark.kubernetes.driver.podTemplateFile";
+pub const SPARK_EXECUTOR_POD_TEMPLATE_FILE: &str = "spark.kubernetes.executor.podTemplateFile";
+
 pub const S3_SECRET_DIR_NAME: &str = "/bytenative/secrets";
 pub const S3_ACCESS_KEY_ID: &str = "accessKey";
-pub const S3_SECRET_ACCESS_KEY: &str = "secretKey";
\ No newline at end of file
+pub const S3_SECRET_ACCESS_KEY: &str = "secretKey";
+pub const S3_CREDENTIALS_SECRET_SUFFIX: &str = "-s3-credentials";
+
+// the same path and audience as the EKS pod identity webhook, so the images built for it keep working
+pub const AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME: &str = "aws-web-identity-token";
+pub const AWS_WEB_IDENTITY_TOKEN_DIR_NAME: &str = "/var/run/secrets/eks.amazonaws.com/serviceaccount";
+pub const AWS_WEB_IDENTITY_TOKEN_FILE_NAME: &str = "token";
+pub const AWS_WEB_IDENTITY_DEFAULT_AUDIENCE: &str = "sts.amazonaws.com";
+pub const AWS_WEB_IDENTITY_DEFAULT_EXPIRATION_SECONDS: i64 = 86400;
+
+pub const S3_TLS_DIR_NAME: &str = "/bytenative/tls/s3";
+pub const S3_TLS_CA_VOLUME_NAME: &str = "s3-tls-ca";
+pub const S3_TLS_CLIENT_VOLUME_NAME: &str = "s3-tls-client";
+pub const S3_TLS_STORES_VOLUME_NAME: &str = "s3-tls-stores";
+pub const S3_TLS_INIT_CONTAINER_NAME: &str = "s3-tls-stores";
+pub const S3_TLS_DEFAULT_CA_CERT_KEY: &str = "ca.crt";
+pub const S3_TLS_TRUSTSTORE_FILE_NAME: &str = "truststore.p12";
+pub const S3_TLS_KEYSTORE_FILE_NAME: &str = "keystore.p12";
+// the stores are generated into an emptyDir of the pod, holding no secret beyond the mounted ones
+pub const S3_TLS_STORE_PASSWORD: &str = "bytenative";
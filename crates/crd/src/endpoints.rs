@@ -0,0 +1,230 @@
+//! Connection endpoints of the sessions, read back from their Services.
+//!
+//! A Service is reached from out of the cluster by its load balancer ingress, or by its NodePort at the address
+//! of a node, and from in the cluster by its DNS name. The endpoints are written into the status of the session,
+//! and optionally into a ConfigMap the client pods mount, along with the NodePorts of the session already
+//! allocated to other Services.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{Node, Service};
+use serde::Serialize;
+
+use crate::SparkApplicationStatus;
+
+pub const CONNECTION_CONFIG_MAP_SUFFIX: &str = "-connection";
+
+/// The endpoints of a session, serialized as the fields of its status
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionEndpoints {
+    /// in the cluster
+    pub jdbc_url: String,
+    /// out of the cluster, by the load balancer or NodePort of the thrift service
+    pub external_jdbc_url: Option<String>,
+    pub beeline: String,
+    pub ui_url: Option<String>,
+    pub spark_ui_url: Option<String>,
+    /// see [`node_port_conflicts`]
+    pub node_port_conflicts: Option<Vec<String>>,
+}
+
+impl SessionEndpoints {
+    /// whether the `status` already holds the endpoints
+    pub fn published_in(&self, status: &SparkApplicationStatus) -> bool {
+        status.jdbc_url.as_ref() == Some(&self.jdbc_url)
+            && status.external_jdbc_url == self.external_jdbc_url
+            && status.beeline.as_ref() == Some(&self.beeline)
+            && status.ui_url == self.ui_url
+            && status.spark_ui_url == self.spark_ui_url
+            && status.node_port_conflicts == self.node_port_conflicts
+    }
+
+    /// the data of the connection ConfigMap
+    pub fn config_map_data(&self) -> BTreeMap<String, String> {
+        let mut data = BTreeMap::from([
+            ("jdbc-url".to_string(), self.jdbc_url.clone()),
+            ("beeline".to_string(), self.beeline.clone()),
+        ]);
+        for (key, value) in [
+            ("external-jdbc-url", &self.external_jdbc_url),
+            ("ui-url", &self.ui_url),
+            ("spark-ui-url", &self.spark_ui_url),
+        ] {
+            if let Some(value) = value {
+                data.insert(key.to_string(), value.clone());
+            }
+        }
+        data
+    }
+}
+
+/// The host and port of the `service` out of the cluster: its load balancer ingress,
+/// or its NodePort at `node_address`
+pub fn external_address(service: &Service, node_address: Option<&str>) -> Option<(String, i32)> {
+    let port = service.spec.as_ref()?.ports.as_ref()?.first()?;
+    let load_balancer = service
+        .status
+        .as_ref()
+        .and_then(|s| s.load_balancer.as_ref())
+        .and_then(|lb| lb.ingress.as_ref())
+        .and_then(|ingress| ingress.first())
+        .and_then(|ingress| ingress.ip.clone().or_else(|| ingress.hostname.clone()));
+    if let Some(host) = load_balancer {
+        return Some((host, port.port));
+    }
+    Some((node_address?.to_string(), port.node_port?))
+}
+
+/// The NodePorts of the `service` to create in `namespace` which `others` of the cluster already allocate
+pub fn node_port_conflicts(service: &Service, namespace: &str, others: &[Service]) -> Vec<String> {
+    let name = service.metadata.name.as_deref().unwrap_or_default();
+    let node_ports = |service: &Service| -> Vec<i32> {
+        let ports = service.spec.as_ref().and_then(|s| s.ports.as_ref());
+        ports.into_iter().flatten().filter_map(|p| p.node_port).collect()
+    };
+    let mut conflicts = vec![];
+    for node_port in node_ports(service) {
+        for other in others {
+            let other_namespace = other.metadata.namespace.as_deref().unwrap_or_default();
+            let other_name = other.metadata.name.as_deref().unwrap_or_default();
+            if (other_namespace, other_name) != (namespace, name) && node_ports(other).contains(&node_port) {
+                conflicts.push(format!(
+                    "nodePort {node_port} of {name} is allocated to {other_namespace}/{other_name}"
+                ));
+            }
+        }
+    }
+    conflicts
+}
+
+/// Whether the creation of a Service failed since its NodePorts are already allocated, which the API server
+/// checks atomically rather than [`node_port_conflicts`]
+pub fn is_node_port_allocated(error: &kube::Error) -> bool {
+    matches!(error, kube::Error::Api(e) if e.code == 422 && e.message.contains("provided port is already allocated"))
+}
+
+/// The external IP of the `node`, or its internal IP
+pub fn node_address(node: &Node) -> Option<String> {
+    let addresses = node.status.as_ref()?.addresses.as_ref()?;
+    ["ExternalIP", "InternalIP"].into_iter().find_map(|typ| {
+        addresses
+            .iter()
+            .find(|a| a.type_ == typ)
+            .map(|a| a.address.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_address() {
+        let mut service: Service = serde_json::from_value(serde_json::json!({
+            "spec": { "type": "NodePort", "ports": [{ "port": 10001, "nodePort": 31000 }] },
+        }))
+        .unwrap();
+        assert_eq!(external_address(&service, None), None);
+        assert_eq!(
+            external_address(&service, Some("10.0.0.5")),
+            Some(("10.0.0.5".to_string(), 31000))
+        );
+
+        service.status = serde_json::from_value(serde_json::json!({
+            "loadBalancer": { "ingress": [{ "hostname": "hs2.elb.example.com" }] },
+        }))
+        .unwrap();
+        assert_eq!(
+            external_address(&service, Some("10.0.0.5")),
+            Some(("hs2.elb.example.com".to_string(), 10001))
+        );
+    }
+
+    #[test]
+    fn test_node_port_conflicts() {
+        let service = |namespace: &str, name: &str, node_port: Option<i32>| -> Service {
+            serde_json::from_value(serde_json::json!({
+                "metadata": { "name": name, "namespace": namespace },
+                "spec": { "ports": [{ "port": 10001, "nodePort": node_port }] },
+            }))
+            .unwrap()
+        };
+        let others = [
+            service("sparkjobs", "session-hs2-thrift-svc", Some(31000)),
+            service("analytics", "other-hs2-thrift-svc", Some(31000)),
+            service("analytics", "auto-hs2-thrift-svc", Some(31001)),
+        ];
+        assert_eq!(
+            node_port_conflicts(&service("", "session-hs2-thrift-svc", Some(31000)), "sparkjobs", &others),
+            vec!["nodePort 31000 of session-hs2-thrift-svc is allocated to analytics/other-hs2-thrift-svc"]
+        );
+        assert!(node_port_conflicts(&service("", "session-hs2-thrift-svc", None), "sparkjobs", &others).is_empty());
+    }
+
+    #[test]
+    fn test_is_node_port_allocated() {
+        let error = |code: u16, message: &str| {
+            kube::Error::Api(kube::error::ErrorResponse {
+                status: "Failure".to_string(),
+                message: message.to_string(),
+                reason: "Invalid".to_string(),
+                code,
+            })
+        };
+        assert!(is_node_port_allocated(&error(
+            422,
+            "Service \"session-hs2-thrift-svc\" is invalid: spec.ports[0].nodePort: Invalid value: 31000: \
+            provided port is already allocated"
+        )));
+        assert!(!is_node_port_allocated(&error(422, "spec.ports[0].nodePort: Invalid value: 80")));
+        assert!(!is_node_port_allocated(&error(409, "already exists")));
+    }
+
+    #[test]
+    fn test_node_address() {
+        let node: Node = serde_json::from_value(serde_json::json!({
+            "status": { "addresses": [
+                { "type": "InternalIP", "address": "10.0.0.5" },
+                { "type": "Hostname", "address": "node-1" },
+            ] },
+        }))
+        .unwrap();
+        assert_eq!(node_address(&node).as_deref(), Some("10.0.0.5"));
+    }
+
+    #[test]
+    fn test_session_endpoints() {
+        let session: crate::spark_application::SparkSession = serde_json::from_value(serde_json::json!({
+            "apiVersion": "spark.bytenative.com/v1",
+            "kind": "SparkSession",
+            "metadata": { "name": "session", "namespace": "sparkjobs", "uid": "5f0c" },
+            "spec": {
+                "spark": { "sparkVersion": "3.4.1", "mode": "cluster", "image": "spark:v3.4.1" },
+                "session": { "hiveServer2ThriftOptions": { "serviceType": "NodePort", "tls": {} } },
+            },
+        }))
+        .unwrap();
+        let mut thrift_service = session.hive_server2_thrift_service().unwrap();
+        let cluster_jdbc_url = session.endpoints(None, None, None, None, None).unwrap().jdbc_url;
+        assert!(cluster_jdbc_url.starts_with("jdbc:hive2://"));
+        assert!(cluster_jdbc_url.contains(".sparkjobs.svc:"));
+        assert!(cluster_jdbc_url.ends_with(";ssl=true"));
+
+        // the NodePort allocated by the API server is read back
+        thrift_service.spec.as_mut().unwrap().ports.as_mut().unwrap()[0].node_port = Some(31000);
+        let endpoints = session
+            .endpoints(Some(&thrift_service), None, Some("10.0.0.5"), None, None)
+            .unwrap();
+        assert_eq!(endpoints.jdbc_url, cluster_jdbc_url);
+        assert_eq!(
+            endpoints.external_jdbc_url.as_deref(),
+            Some("jdbc:hive2://10.0.0.5:31000/default;ssl=true")
+        );
+        assert_eq!(endpoints.beeline, "beeline -u 'jdbc:hive2://10.0.0.5:31000/default;ssl=true'");
+        assert_eq!(
+            session.connection_config_map(&endpoints).unwrap().data.unwrap()["external-jdbc-url"],
+            "jdbc:hive2://10.0.0.5:31000/default;ssl=true"
+        );
+    }
+}
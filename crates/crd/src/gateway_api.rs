@@ -0,0 +1,62 @@
+//! The subset of the Gateway API `HTTPRoute` the operator creates to expose the UIs of the sessions,
+//! the Gateway API CRDs are installed apart from the operator.
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, CustomResource, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[kube(
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    kind = "HTTPRoute",
+    plural = "httproutes",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteSpec {
+    pub parent_refs: Vec<ParentReference>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostnames: Option<Vec<String>>,
+    pub rules: Vec<HTTPRouteRule>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentReference {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteRule {
+    pub matches: Vec<HTTPRouteMatch>,
+    pub backend_refs: Vec<HTTPBackendRef>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteMatch {
+    pub path: HTTPPathMatch,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPPathMatch {
+    /// `PathPrefix` or `Exact`
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub value: String,
+}
+
+/// A Service of the namespace of the route
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPBackendRef {
+    pub name: String,
+    pub port: i32,
+}
@@ -0,0 +1,497 @@
+//! Spark history servers deployed by the operator.
+//!
+//! A history server is a Deployment and Service of the history server reading the event logs from
+//! a S3 directory, optionally exposed by an Ingress. The applications with `eventLog.historyServer`
+//! write their event logs into the same directory with the S3 connection of the history server.
+
+use std::collections::{BTreeMap, HashMap};
+
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::api::core::v1::{
+    ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, HTTPGetAction, PodSpec,
+    PodTemplateSpec, Probe, ResourceRequirements, Secret, Service, ServicePort, ServiceSpec, Volume,
+    VolumeMount,
+};
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+    IngressServiceBackend, IngressSpec, IngressTLS, ServiceBackendPort,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::{Api, Client, CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+use crate::constants::{HISTORY_CONFIG_FILE_NAME, HISTORY_CONFIG_FILE_NAME_FULL, HISTORY_ROLE_NAME};
+use crate::metadata::{role_group_selector_labels, ObjectLabels, ObjectMetaBuilder};
+use crate::s3::{S3ConnectionDef, S3ConnectionSpec};
+use crate::{Error, Result};
+
+pub const HISTORY_UI_PORT: i32 = 18080;
+pub const HISTORY_UI_PORT_NAME: &str = "http";
+pub const HISTORY_SERVER_CLASS: &str = "org.apache.spark.deploy.history.HistoryServer";
+pub const SPARK_CLASS_SCRIPT: &str = "/opt/spark/bin/spark-class";
+
+#[derive(Clone, CustomResource, Debug, Deserialize, JsonSchema, Serialize, PartialEq)]
+#[kube(
+    group = "spark.bytenative.com",
+    version = "v1",
+    kind = "SparkHistoryServer",
+    plural = "sparkhistoryservers",
+    shortname = "shs",
+    status = "SparkHistoryServerStatus",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkHistoryServerSpec {
+    /// the spark image, with the jars of the S3A
+    pub image: String,
+    /// the directory of the event logs, for example: `s3a://spark-logs/events`
+    pub log_dir: String,
+    /// the connection to the S3 of the event logs, also used by the applications to write them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_connection: Option<S3ConnectionDef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleaner: Option<HistoryCleaner>,
+    /// the extra configs of the history server, which take precedence over the rendered ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spark_configs: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceRequirements>,
+    /// the type of the Service, defaults to `ClusterIP`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress: Option<HistoryIngress>,
+}
+
+/// The cleaner of the event logs, see `spark.history.fs.cleaner.*`
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryCleaner {
+    /// defaults to true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// how often the cleaner runs, for example: `1d`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    /// the age of the event logs to be deleted, for example: `7d`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<String>,
+    /// the max number of the event logs kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_num: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryIngress {
+    /// for example: `spark-history.example.com`
+    pub host: String,
+    /// defaults to `/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_name: Option<String>,
+    /// the `kubernetes.io/tls` Secret of the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+}
+
+/// The event logs of an application
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventLogSpec {
+    /// defaults to true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// the name of a `SparkHistoryServer` in the namespace, whose directory and S3 connection are used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_server: Option<String>,
+    /// the directory of the event logs, instead of the one of the history server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+}
+
+impl EventLogSpec {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Display, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum SparkHistoryServerPhase {
+    Starting,
+    Running,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkHistoryServerStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+    /// the URL of the UI, of the Ingress if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// The resources deployed for a history server
+#[derive(Clone, Debug)]
+pub struct SparkHistoryServerResources {
+    pub config_map: ConfigMap,
+    pub secrets: Vec<Secret>,
+    pub deployment: Deployment,
+    pub service: Service,
+    pub ingress: Option<Ingress>,
+}
+
+impl SparkHistoryServerSpec {
+    /// Convenience function to retrieve the spec of a history server resource from the K8S API service.
+    pub async fn get(resource_name: &str, client: &Client, namespace: &str) -> Result<SparkHistoryServerSpec> {
+        let resource_api = Api::<SparkHistoryServer>::namespaced(client.clone(), namespace);
+        let resource = resource_api
+            .get(resource_name)
+            .await
+            .map_err(|_| Error::MissingSparkHistoryServer {
+                name: resource_name.to_string(),
+            })?;
+
+        Ok(resource.spec)
+    }
+
+    /// `spark-logs` of `s3a://spark-logs/events`
+    pub fn log_bucket(&self) -> Option<String> {
+        let path = self.log_dir.strip_prefix("s3a://")?;
+        let bucket = path.split('/').next().filter(|b| !b.is_empty())?;
+        Some(bucket.to_string())
+    }
+
+    /// The configs of `spark-defaults.conf`
+    pub fn spark_configs(&self, connection: Option<&S3ConnectionSpec>) -> BTreeMap<String, String> {
+        let mut configs = BTreeMap::from([
+            ("spark.history.fs.logDirectory".to_string(), self.log_dir.clone()),
+            ("spark.history.ui.port".to_string(), HISTORY_UI_PORT.to_string()),
+        ]);
+        if let Some(cleaner) = &self.cleaner {
+            let prefix = "spark.history.fs.cleaner";
+            configs.insert(format!("{prefix}.enabled"), cleaner.enabled.unwrap_or(true).to_string());
+            if let Some(interval) = &cleaner.interval {
+                configs.insert(format!("{prefix}.interval"), interval.clone());
+            }
+            if let Some(max_age) = &cleaner.max_age {
+                configs.insert(format!("{prefix}.maxAge"), max_age.clone());
+            }
+            if let Some(max_num) = cleaner.max_num {
+                configs.insert(format!("{prefix}.maxNum"), max_num.to_string());
+            }
+        }
+        if let Some(connection) = connection {
+            configs.extend(connection.server_spark_configs());
+        }
+        configs.extend(self.spark_configs.clone().unwrap_or_default());
+        configs
+    }
+}
+
+impl SparkHistoryServer {
+    /// The URL of the UI, of the Ingress if any, or of the Service
+    pub fn url(&self) -> Option<String> {
+        if let Some(ingress) = &self.spec.ingress {
+            let scheme = if ingress.tls_secret.is_some() { "https" } else { "http" };
+            let path = ingress.path.as_deref().unwrap_or("/");
+            return Some(format!("{scheme}://{}{path}", ingress.host));
+        }
+        let namespace = self.namespace()?;
+        Some(format!(
+            "http://{}.{namespace}.svc:{HISTORY_UI_PORT}",
+            self.name_any()
+        ))
+    }
+
+    fn build_recommended_labels<'a>(&'a self, role: &'a str) -> ObjectLabels<'a, Self> {
+        ObjectLabels {
+            owner: self,
+            app_name: crate::constants::APP_NAME,
+            app_version: crate::metadata::image_tag(&self.spec.image),
+            operator_name: crate::constants::OPERATOR_NAME,
+            controller_name: crate::constants::CONTROLLER_NAME_HISTORY_SERVER,
+            role,
+            role_group: crate::constants::CONTROLLER_NAME_HISTORY_SERVER,
+        }
+    }
+
+    fn meta_named(&self, name: String) -> Result<ObjectMeta> {
+        Ok(ObjectMetaBuilder::new()
+            .name(common::utils::repair_resource_name(&name))
+            .ownerreference_from_resource(self, None, Some(true))
+            .map_err(|_| Error::FailedBuildOwnerReference { name })?
+            .with_recommended_labels(self.build_recommended_labels(HISTORY_ROLE_NAME))
+            .build())
+    }
+
+    fn selector_labels(&self) -> BTreeMap<String, String> {
+        role_group_selector_labels(
+            self,
+            crate::constants::APP_NAME,
+            HISTORY_ROLE_NAME,
+            crate::constants::CONTROLLER_NAME_HISTORY_SERVER,
+        )
+    }
+
+    fn config_map_name(&self) -> String {
+        format!("{}-history-conf", self.name_any())
+    }
+
+    fn s3_secret_name(&self) -> String {
+        format!("{}-s3-credentials", self.name_any())
+    }
+
+    pub async fn history_server_resources(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<SparkHistoryServerResources> {
+        let connection = match &self.spec.s3_connection {
+            Some(connection) => Some(connection.resolve(client, namespace).await?),
+            None => None,
+        };
+        self.build_resources(connection.as_ref())
+    }
+
+    pub fn build_resources(&self, connection: Option<&S3ConnectionSpec>) -> Result<SparkHistoryServerResources> {
+        let name = self.name_any();
+
+        let spark_defaults = self
+            .spec
+            .spark_configs(connection)
+            .iter()
+            .map(|(k, v)| format!("{k} {v}\n"))
+            .collect::<String>();
+        let config_map = ConfigMap {
+            metadata: self.meta_named(self.config_map_name())?,
+            data: Some(BTreeMap::from([(HISTORY_CONFIG_FILE_NAME.to_string(), spark_defaults)])),
+            ..ConfigMap::default()
+        };
+
+        let secrets = connection
+            .and_then(|c| c.credentials.as_ref())
+            .and_then(|c| c.managed_secret_data())
+            .map(|data| -> Result<Secret> {
+                Ok(Secret {
+                    metadata: self.meta_named(self.s3_secret_name())?,
+                    string_data: Some(data),
+                    ..Secret::default()
+                })
+            })
+            .transpose()?
+            .into_iter()
+            .collect();
+
+        let conf_dir = HISTORY_CONFIG_FILE_NAME_FULL
+            .rsplit_once('/')
+            .map_or(HISTORY_CONFIG_FILE_NAME_FULL, |(dir, _)| dir);
+        let credentials = connection.and_then(|c| c.credentials.as_ref());
+        let mut volumes = vec![Volume {
+            name: "history-conf".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(self.config_map_name()),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        }];
+        let mut volume_mounts = vec![VolumeMount {
+            name: "history-conf".to_string(),
+            mount_path: conf_dir.to_string(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        }];
+        volumes.extend(credentials.and_then(|c| c.token_volume()));
+        volume_mounts.extend(credentials.and_then(|c| c.token_volume_mount()));
+
+        let probe = Probe {
+            http_get: Some(HTTPGetAction {
+                path: Some("/".to_string()),
+                port: IntOrString::String(HISTORY_UI_PORT_NAME.to_string()),
+                ..HTTPGetAction::default()
+            }),
+            initial_delay_seconds: Some(10),
+            period_seconds: Some(10),
+            ..Probe::default()
+        };
+        let container = Container {
+            name: "history-server".to_string(),
+            image: Some(self.spec.image.clone()),
+            command: Some(vec![
+                SPARK_CLASS_SCRIPT.to_string(),
+                HISTORY_SERVER_CLASS.to_string(),
+                "--properties-file".to_string(),
+                HISTORY_CONFIG_FILE_NAME_FULL.to_string(),
+            ]),
+            env: connection.map(|c| c.server_env_vars(&self.s3_secret_name())),
+            ports: Some(vec![ContainerPort {
+                name: Some(HISTORY_UI_PORT_NAME.to_string()),
+                container_port: HISTORY_UI_PORT,
+                ..ContainerPort::default()
+            }]),
+            readiness_probe: Some(probe.clone()),
+            liveness_probe: Some(Probe {
+                initial_delay_seconds: Some(30),
+                ..probe
+            }),
+            volume_mounts: Some(volume_mounts),
+            resources: self.spec.resources.clone(),
+            ..Container::default()
+        };
+        let deployment = Deployment {
+            metadata: self.meta_named(name.clone())?,
+            spec: Some(DeploymentSpec {
+                // the history server doesn't scale out
+                replicas: Some(1),
+                selector: LabelSelector {
+                    match_labels: Some(self.selector_labels()),
+                    ..LabelSelector::default()
+                },
+                template: PodTemplateSpec {
+                    metadata: Some(ObjectMeta {
+                        labels: Some(self.selector_labels()),
+                        ..ObjectMeta::default()
+                    }),
+                    spec: Some(PodSpec {
+                        containers: vec![container],
+                        volumes: Some(volumes),
+                        ..PodSpec::default()
+                    }),
+                },
+                ..DeploymentSpec::default()
+            }),
+            ..Deployment::default()
+        };
+
+        let service = Service {
+            metadata: self.meta_named(name.clone())?,
+            spec: Some(ServiceSpec {
+                type_: self.spec.service_type.clone(),
+                selector: Some(self.selector_labels()),
+                ports: Some(vec![ServicePort {
+                    name: Some(HISTORY_UI_PORT_NAME.to_string()),
+                    port: HISTORY_UI_PORT,
+                    target_port: Some(IntOrString::String(HISTORY_UI_PORT_NAME.to_string())),
+                    ..ServicePort::default()
+                }]),
+                ..ServiceSpec::default()
+            }),
+            ..Service::default()
+        };
+
+        let ingress = self
+            .spec
+            .ingress
+            .as_ref()
+            .map(|ingress| -> Result<Ingress> {
+                let mut metadata = self.meta_named(name.clone())?;
+                metadata.annotations = ingress.annotations.clone();
+                Ok(Ingress {
+                    metadata,
+                    spec: Some(IngressSpec {
+                        ingress_class_name: ingress.class_name.clone(),
+                        rules: Some(vec![IngressRule {
+                            host: Some(ingress.host.clone()),
+                            http: Some(HTTPIngressRuleValue {
+                                paths: vec![HTTPIngressPath {
+                                    path: Some(ingress.path.clone().unwrap_or_else(|| "/".to_string())),
+                                    path_type: "Prefix".to_string(),
+                                    backend: IngressBackend {
+                                        service: Some(IngressServiceBackend {
+                                            name: name.clone(),
+                                            port: Some(ServiceBackendPort {
+                                                name: Some(HISTORY_UI_PORT_NAME.to_string()),
+                                                number: None,
+                                            }),
+                                        }),
+                                        resource: None,
+                                    },
+                                }],
+                            }),
+                        }]),
+                        tls: ingress.tls_secret.as_ref().map(|secret| {
+                            vec![IngressTLS {
+                                hosts: Some(vec![ingress.host.clone()]),
+                                secret_name: Some(secret.clone()),
+                            }]
+                        }),
+                        ..IngressSpec::default()
+                    }),
+                    ..Ingress::default()
+                })
+            })
+            .transpose()?;
+
+        Ok(SparkHistoryServerResources {
+            config_map,
+            secrets,
+            deployment,
+            service,
+            ingress,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_server() -> SparkHistoryServer {
+        serde_yaml::from_str(
+            "
+            apiVersion: spark.bytenative.com/v1
+            kind: SparkHistoryServer
+            metadata:
+              name: shs
+              namespace: spark
+              uid: 2f1e0d9c-0000-0000-0000-000000000000
+            spec:
+              image: bnp.me/bn-spark-operator/spark:v3.4.1
+              logDir: s3a://spark-logs/events
+              cleaner:
+                maxAge: 7d
+              ingress:
+                host: spark-history.example.com
+                tlsSecret: spark-history-tls
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_spark_configs() {
+        let history_server = history_server();
+        assert_eq!(history_server.spec.log_bucket().as_deref(), Some("spark-logs"));
+        let configs = history_server.spec.spark_configs(None);
+        assert_eq!(configs["spark.history.fs.logDirectory"], "s3a://spark-logs/events");
+        assert_eq!(configs["spark.history.fs.cleaner.enabled"], "true");
+        assert_eq!(configs["spark.history.fs.cleaner.maxAge"], "7d");
+        assert!(!configs.contains_key("spark.history.fs.cleaner.maxNum"));
+    }
+
+    #[test]
+    fn test_resources() {
+        let history_server = history_server();
+        assert_eq!(
+            history_server.url().as_deref(),
+            Some("https://spark-history.example.com/")
+        );
+        let resources = history_server.build_resources(None).unwrap();
+        let container = &resources.deployment.spec.unwrap().template.spec.unwrap().containers[0];
+        assert_eq!(
+            container.command.as_ref().unwrap()[1..],
+            [HISTORY_SERVER_CLASS, "--properties-file", HISTORY_CONFIG_FILE_NAME_FULL]
+        );
+        assert!(resources.config_map.data.unwrap()[HISTORY_CONFIG_FILE_NAME]
+            .contains("spark.history.ui.port 18080\n"));
+        let ingress_spec = resources.ingress.unwrap().spec.unwrap();
+        assert_eq!(ingress_spec.tls.unwrap()[0].secret_name.as_deref(), Some("spark-history-tls"));
+    }
+}
@@ -0,0 +1,582 @@
+//! Hive metastores deployed by the operator.
+//!
+//! A metastore is a Deployment and Service of the metastore server on a JDBC database, whose schema
+//! is initialized or upgraded by a Job of the schema tool first. The warehouse is accessed through a
+//! S3 connection, and the published `thrift://` URI could be referenced by the catalogs by name.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
+use k8s_openapi::api::batch::v1::{Job, JobSpec};
+use k8s_openapi::api::core::v1::{
+    ConfigMap, ConfigMapVolumeSource, Container, ContainerPort, EnvVar, EnvVarSource, PodSpec,
+    PodTemplateSpec, Probe, ResourceRequirements, Secret, SecretKeySelector, Service, ServicePort,
+    ServiceSpec, TCPSocketAction, Volume, VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
+use kube::{Api, Client, CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+use crate::jdbc::{JdbcCredentials, JDBC_DEFAULT_PASSWORD_KEY, JDBC_DEFAULT_USER_KEY};
+use crate::metadata::{role_group_selector_labels, ObjectLabels, ObjectMetaBuilder};
+use crate::s3::{S3ConnectionDef, S3ConnectionSpec};
+use crate::{Error, Result};
+
+pub const HIVE_METASTORE_PORT: i32 = 9083;
+pub const HIVE_METASTORE_PORT_NAME: &str = "thrift";
+pub const HIVE_CONF_DIR: &str = "/opt/hive/conf";
+pub const HIVE_SITE_FILE_NAME: &str = "hive-site.xml";
+pub const HIVE_SCHEMA_TOOL: &str = "/opt/hive/bin/schematool";
+pub const HIVE_METASTORE_DB_USER_ENV: &str = "HIVE_METASTORE_DB_USER";
+pub const HIVE_METASTORE_DB_PASSWORD_ENV: &str = "HIVE_METASTORE_DB_PASSWORD";
+
+#[derive(Clone, CustomResource, Debug, Deserialize, JsonSchema, Serialize, PartialEq)]
+#[kube(
+    group = "spark.bytenative.com",
+    version = "v1",
+    kind = "HiveMetastore",
+    plural = "hivemetastores",
+    shortname = "hms",
+    status = "HiveMetastoreStatus",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct HiveMetastoreSpec {
+    /// the image of the metastore, with the JDBC driver of the database and the jars of the S3A,
+    /// which the `apache/hive` images don't ship
+    pub image: String,
+    /// defaults to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replicas: Option<i32>,
+    pub database: MetastoreDatabase,
+    /// for example: `s3a://spark-dwh/warehouse`
+    pub warehouse: String,
+    /// the connection to the S3 of the warehouse, the TLS of it is expected to be trusted by the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_connection: Option<S3ConnectionDef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceRequirements>,
+    /// the extra properties of `hive-site.xml`, which take precedence over the rendered ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hive_site: Option<BTreeMap<String, String>>,
+}
+
+/// The JDBC database of the metastore
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetastoreDatabase {
+    /// the type of the database, as the `-dbType` of the schema tool
+    pub db_type: MetastoreDbType,
+    /// for example: `jdbc:postgresql://postgres:5432/metastore`
+    pub url: String,
+    /// the class of the JDBC driver, defaults to the one of the type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    pub credentials: JdbcCredentials,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum MetastoreDbType {
+    Postgres,
+    Mysql,
+    Mssql,
+    Oracle,
+}
+
+impl MetastoreDbType {
+    pub fn default_driver(&self) -> &'static str {
+        match self {
+            MetastoreDbType::Postgres => "org.postgresql.Driver",
+            MetastoreDbType::Mysql => "com.mysql.cj.jdbc.Driver",
+            MetastoreDbType::Mssql => "com.microsoft.sqlserver.jdbc.SQLServerDriver",
+            MetastoreDbType::Oracle => "oracle.jdbc.OracleDriver",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Display, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum HiveMetastorePhase {
+    /// the schema tool is initializing or upgrading the schema of the database
+    InitializingSchema,
+    /// the schema is ready, waiting for the metastore to be available
+    Starting,
+    Running,
+    Failed,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HiveMetastoreStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+    /// the URI of the metastore, for example: `thrift://hms.spark.svc:9083`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+/// The resources deployed for a metastore, the schema Job is expected to succeed before the
+/// Deployment is applied.
+#[derive(Clone, Debug)]
+pub struct HiveMetastoreResources {
+    pub config_map: ConfigMap,
+    pub secrets: Vec<Secret>,
+    pub schema_job: Job,
+    pub deployment: Deployment,
+    pub service: Service,
+}
+
+impl HiveMetastoreSpec {
+    pub fn image_tag(&self) -> &str {
+        crate::metadata::image_tag(&self.image)
+    }
+}
+
+impl HiveMetastore {
+    /// Convenience function to retrieve a metastore resource from the K8S API service.
+    pub async fn get(resource_name: &str, client: &Client, namespace: &str) -> Result<HiveMetastore> {
+        let resource_api = Api::<HiveMetastore>::namespaced(client.clone(), namespace);
+        resource_api
+            .get(resource_name)
+            .await
+            .map_err(|_| Error::MissingHiveMetastore {
+                name: resource_name.to_string(),
+            })
+    }
+
+    /// The URI published in the status, of the Service of the metastore
+    pub fn uri(&self) -> Option<String> {
+        let namespace = self.namespace()?;
+        Some(format!(
+            "thrift://{}.{namespace}.svc:{HIVE_METASTORE_PORT}",
+            self.name_any()
+        ))
+    }
+
+    /// The published URI, or the one of the Service before it is published
+    pub fn published_uri(&self) -> Option<String> {
+        self.status
+            .as_ref()
+            .and_then(|s| s.uri.clone())
+            .or_else(|| self.uri())
+    }
+
+    /// The schema Job is named after the tag of the image, so an upgrade of the image runs it again
+    pub fn schema_job_name(&self) -> String {
+        let tag = self
+            .spec
+            .image_tag()
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "-");
+        format!("{}-schema-{tag}", self.name_any())
+    }
+
+    fn build_recommended_labels<'a>(&'a self, role: &'a str) -> ObjectLabels<'a, Self> {
+        ObjectLabels {
+            owner: self,
+            app_name: crate::constants::APP_NAME,
+            app_version: self.spec.image_tag(),
+            operator_name: crate::constants::OPERATOR_NAME,
+            controller_name: crate::constants::CONTROLLER_NAME_HIVE_METASTORE,
+            role,
+            role_group: crate::constants::CONTROLLER_NAME_HIVE_METASTORE,
+        }
+    }
+
+    fn meta_named(&self, name: String, role: &str) -> Result<ObjectMeta> {
+        Ok(ObjectMetaBuilder::new()
+            .name(common::utils::repair_resource_name(&name))
+            .ownerreference_from_resource(self, None, Some(true))
+            .map_err(|_| Error::FailedBuildOwnerReference { name })?
+            .with_recommended_labels(self.build_recommended_labels(role))
+            .build())
+    }
+
+    fn selector_labels(&self) -> BTreeMap<String, String> {
+        role_group_selector_labels(
+            self,
+            crate::constants::APP_NAME,
+            crate::constants::RESOURCE_ROLE_HIVE_METASTORE,
+            crate::constants::CONTROLLER_NAME_HIVE_METASTORE,
+        )
+    }
+
+    fn config_map_name(&self) -> String {
+        format!("{}-hive-site", self.name_any())
+    }
+
+    fn s3_secret_name(&self) -> String {
+        format!("{}-s3-credentials", self.name_any())
+    }
+
+    pub async fn metastore_resources(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<HiveMetastoreResources> {
+        let connection = match &self.spec.s3_connection {
+            Some(connection) => Some(connection.resolve(client, namespace).await?),
+            None => None,
+        };
+        self.build_resources(connection.as_ref())
+    }
+
+    /// The properties of `hive-site.xml`, the credentials are expanded from the env-vars by hadoop
+    pub fn hive_site(&self, connection: Option<&S3ConnectionSpec>) -> BTreeMap<String, String> {
+        let database = &self.spec.database;
+        let mut properties = BTreeMap::from([
+            ("javax.jdo.option.ConnectionURL".to_string(), database.url.clone()),
+            (
+                "javax.jdo.option.ConnectionDriverName".to_string(),
+                database
+                    .driver
+                    .clone()
+                    .unwrap_or_else(|| database.db_type.default_driver().to_string()),
+            ),
+            (
+                "javax.jdo.option.ConnectionUserName".to_string(),
+                format!("${{env.{HIVE_METASTORE_DB_USER_ENV}}}"),
+            ),
+            (
+                "javax.jdo.option.ConnectionPassword".to_string(),
+                format!("${{env.{HIVE_METASTORE_DB_PASSWORD_ENV}}}"),
+            ),
+            ("metastore.warehouse.dir".to_string(), self.spec.warehouse.clone()),
+            ("metastore.thrift.port".to_string(), HIVE_METASTORE_PORT.to_string()),
+        ]);
+
+        if let Some(connection) = connection {
+            // the spark configs of the connection without the `spark.hadoop.` prefix
+            for (key, value) in connection.server_spark_configs() {
+                if let Some(key) = key.strip_prefix("spark.hadoop.") {
+                    properties.insert(key.to_string(), value);
+                }
+            }
+        }
+        properties.extend(self.spec.hive_site.clone().unwrap_or_default());
+        properties
+    }
+
+    fn env_vars(&self, connection: Option<&S3ConnectionSpec>) -> Vec<EnvVar> {
+        let credentials = &self.spec.database.credentials;
+        let secret_env_var = |name: &str, key: &str| EnvVar {
+            name: name.to_string(),
+            value: None,
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    key: key.to_string(),
+                    name: Some(credentials.secret.clone()),
+                    optional: Some(false),
+                }),
+                ..EnvVarSource::default()
+            }),
+        };
+        let mut env = vec![
+            secret_env_var(
+                HIVE_METASTORE_DB_USER_ENV,
+                credentials.user_key.as_deref().unwrap_or(JDBC_DEFAULT_USER_KEY),
+            ),
+            secret_env_var(
+                HIVE_METASTORE_DB_PASSWORD_ENV,
+                credentials.password_key.as_deref().unwrap_or(JDBC_DEFAULT_PASSWORD_KEY),
+            ),
+        ];
+        if let Some(connection) = connection {
+            env.extend(connection.server_env_vars(&self.s3_secret_name()));
+        }
+        env
+    }
+
+    fn hive_site_volume(&self) -> (Volume, VolumeMount) {
+        let volume = Volume {
+            name: "hive-site".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(self.config_map_name()),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        };
+        let mount = VolumeMount {
+            name: "hive-site".to_string(),
+            mount_path: format!("{HIVE_CONF_DIR}/{HIVE_SITE_FILE_NAME}"),
+            sub_path: Some(HIVE_SITE_FILE_NAME.to_string()),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        };
+        (volume, mount)
+    }
+
+    fn pod_spec(&self, container: Container, connection: Option<&S3ConnectionSpec>) -> PodSpec {
+        let (volume, mount) = self.hive_site_volume();
+        let credentials = connection.and_then(|c| c.credentials.as_ref());
+        let mut volumes = vec![volume];
+        let mut volume_mounts = vec![mount];
+        volumes.extend(credentials.and_then(|c| c.token_volume()));
+        volume_mounts.extend(credentials.and_then(|c| c.token_volume_mount()));
+        PodSpec {
+            containers: vec![Container {
+                image: Some(self.spec.image.clone()),
+                env: Some(self.env_vars(connection)),
+                volume_mounts: Some(volume_mounts),
+                resources: self.spec.resources.clone(),
+                ..container
+            }],
+            volumes: Some(volumes),
+            ..PodSpec::default()
+        }
+    }
+
+    pub fn build_resources(&self, connection: Option<&S3ConnectionSpec>) -> Result<HiveMetastoreResources> {
+        let name = self.name_any();
+        let role = crate::constants::RESOURCE_ROLE_HIVE_METASTORE;
+
+        let config_map = ConfigMap {
+            metadata: self.meta_named(self.config_map_name(), role)?,
+            data: Some(BTreeMap::from([(
+                HIVE_SITE_FILE_NAME.to_string(),
+                hive_site_xml(&self.hive_site(connection)),
+            )])),
+            ..ConfigMap::default()
+        };
+
+        let secrets = connection
+            .and_then(|c| c.credentials.as_ref())
+            .and_then(|c| c.managed_secret_data())
+            .map(|data| -> Result<Secret> {
+                Ok(Secret {
+                    metadata: self.meta_named(self.s3_secret_name(), role)?,
+                    string_data: Some(data),
+                    ..Secret::default()
+                })
+            })
+            .transpose()?
+            .into_iter()
+            .collect();
+
+        let db_type = self.spec.database.db_type.to_string();
+        let mut schema_pod = self.pod_spec(
+            Container {
+                name: "schema-tool".to_string(),
+                command: Some(vec![
+                    HIVE_SCHEMA_TOOL.to_string(),
+                    "-dbType".to_string(),
+                    db_type.clone(),
+                    "-initOrUpgradeSchema".to_string(),
+                ]),
+                ..Container::default()
+            },
+            connection,
+        );
+        schema_pod.restart_policy = Some("OnFailure".to_string());
+        let schema_job = Job {
+            metadata: self.meta_named(self.schema_job_name(), role)?,
+            spec: Some(JobSpec {
+                backoff_limit: Some(3),
+                template: PodTemplateSpec {
+                    metadata: None,
+                    spec: Some(schema_pod),
+                },
+                ..JobSpec::default()
+            }),
+            ..Job::default()
+        };
+
+        let probe = Probe {
+            tcp_socket: Some(TCPSocketAction {
+                port: IntOrString::String(HIVE_METASTORE_PORT_NAME.to_string()),
+                ..TCPSocketAction::default()
+            }),
+            initial_delay_seconds: Some(10),
+            period_seconds: Some(10),
+            ..Probe::default()
+        };
+        let metastore_container = Container {
+            name: "metastore".to_string(),
+            ports: Some(vec![ContainerPort {
+                name: Some(HIVE_METASTORE_PORT_NAME.to_string()),
+                container_port: HIVE_METASTORE_PORT,
+                ..ContainerPort::default()
+            }]),
+            readiness_probe: Some(probe.clone()),
+            liveness_probe: Some(Probe {
+                initial_delay_seconds: Some(30),
+                ..probe
+            }),
+            ..Container::default()
+        };
+        let mut metastore_pod = self.pod_spec(metastore_container, connection);
+        // the schema is left to the Job, rather than the entrypoint of the image
+        metastore_pod.containers[0].env.get_or_insert_with(Vec::new).extend([
+            EnvVar {
+                name: "SERVICE_NAME".to_string(),
+                value: Some("metastore".to_string()),
+                value_from: None,
+            },
+            EnvVar {
+                name: "DB_DRIVER".to_string(),
+                value: Some(db_type),
+                value_from: None,
+            },
+            EnvVar {
+                name: "IS_RESUME".to_string(),
+                value: Some("true".to_string()),
+                value_from: None,
+            },
+        ]);
+        let deployment = Deployment {
+            metadata: self.meta_named(name.clone(), role)?,
+            spec: Some(DeploymentSpec {
+                replicas: Some(self.spec.replicas.unwrap_or(1)),
+                selector: LabelSelector {
+                    match_labels: Some(self.selector_labels()),
+                    ..LabelSelector::default()
+                },
+                template: PodTemplateSpec {
+                    metadata: Some(ObjectMeta {
+                        labels: Some(self.selector_labels()),
+                        ..ObjectMeta::default()
+                    }),
+                    spec: Some(metastore_pod),
+                },
+                ..DeploymentSpec::default()
+            }),
+            ..Deployment::default()
+        };
+
+        let service = Service {
+            metadata: self.meta_named(name, role)?,
+            spec: Some(ServiceSpec {
+                selector: Some(self.selector_labels()),
+                ports: Some(vec![ServicePort {
+                    name: Some(HIVE_METASTORE_PORT_NAME.to_string()),
+                    port: HIVE_METASTORE_PORT,
+                    target_port: Some(IntOrString::String(HIVE_METASTORE_PORT_NAME.to_string())),
+                    ..ServicePort::default()
+                }]),
+                ..ServiceSpec::default()
+            }),
+            ..Service::default()
+        };
+
+        Ok(HiveMetastoreResources {
+            config_map,
+            secrets,
+            schema_job,
+            deployment,
+            service,
+        })
+    }
+}
+
+/// Render the `properties` into the hadoop configuration XML
+pub fn hive_site_xml(properties: &BTreeMap<String, String>) -> String {
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<configuration>\n");
+    for (name, value) in properties {
+        xml.push_str(&format!(
+            "  <property>\n    <name>{}</name>\n    <value>{}</value>\n  </property>\n",
+            escape(name),
+            escape(value)
+        ));
+    }
+    xml.push_str("</configuration>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metastore() -> HiveMetastore {
+        serde_yaml::from_str(
+            "
+            apiVersion: spark.bytenative.com/v1
+            kind: HiveMetastore
+            metadata:
+              name: hms
+              namespace: spark
+              uid: 7d5b4c3a-0000-0000-0000-000000000000
+            spec:
+              image: bnp.me/bn-spark-operator/hive:4.0.0
+              database:
+                dbType: postgres
+                url: jdbc:postgresql://postgres:5432/metastore
+                credentials:
+                  secret: metastore-db
+              warehouse: s3a://spark-dwh/warehouse
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hive_site() {
+        let metastore = metastore();
+        // the externally tagged credentials go through json, which serde_yaml expects as a YAML tag
+        let connection: serde_json::Value = serde_yaml::from_str(
+            "
+            host: minio
+            port: 9000
+            accessStyle: Path
+            credentials:
+              inline:
+                accessKey: minio
+                secretKey: minio123
+            ",
+        )
+        .unwrap();
+        let connection: S3ConnectionSpec = serde_json::from_value(connection).unwrap();
+        let properties = metastore.hive_site(Some(&connection));
+        assert_eq!(properties["javax.jdo.option.ConnectionDriverName"], "org.postgresql.Driver");
+        assert_eq!(
+            properties["javax.jdo.option.ConnectionPassword"],
+            "${env.HIVE_METASTORE_DB_PASSWORD}"
+        );
+        assert_eq!(properties["fs.s3a.endpoint"], "http://minio:9000");
+        assert_eq!(
+            properties["fs.s3a.aws.credentials.provider"],
+            "com.amazonaws.auth.EnvironmentVariableCredentialsProvider"
+        );
+        assert!(hive_site_xml(&properties).contains("<name>fs.s3a.path.style.access</name>"));
+
+        // the inline keys are kept in the managed secret
+        let resources = metastore.build_resources(Some(&connection)).unwrap();
+        assert_eq!(resources.secrets[0].metadata.name.as_deref(), Some("hms-s3-credentials"));
+        let env = resources.deployment.spec.unwrap().template.spec.unwrap().containers[0]
+            .env
+            .clone()
+            .unwrap();
+        assert!(env.iter().any(|e| e.name == "AWS_SECRET_ACCESS_KEY"));
+    }
+
+    #[test]
+    fn test_resources() {
+        let metastore = metastore();
+        assert_eq!(metastore.uri().as_deref(), Some("thrift://hms.spark.svc:9083"));
+        assert_eq!(metastore.schema_job_name(), "hms-schema-4-0-0");
+
+        let resources = metastore.build_resources(None).unwrap();
+        let job_spec = resources.schema_job.spec.unwrap().template.spec.unwrap();
+        assert_eq!(
+            job_spec.containers[0].command.as_ref().unwrap()[1..],
+            ["-dbType", "postgres", "-initOrUpgradeSchema"]
+        );
+        let service_spec = resources.service.spec.unwrap();
+        assert_eq!(service_spec.ports.unwrap()[0].port, HIVE_METASTORE_PORT);
+        assert_eq!(
+            service_spec.selector,
+            resources.deployment.spec.unwrap().selector.match_labels
+        );
+        let owner = &resources.config_map.metadata.owner_references.unwrap()[0];
+        assert_eq!((owner.kind.as_str(), owner.controller), ("HiveMetastore", Some(true)));
+    }
+}
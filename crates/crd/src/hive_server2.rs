@@ -0,0 +1,365 @@
+//! Authentication and TLS of the hive server2 endpoints of the sessions.
+//!
+//! Without an authentication anyone reaching the thrift endpoint runs SQL as the service account of the
+//! session. The credentials are kept in Secrets: the files are mounted into the driver, and the single values
+//! are env-vars of the driver expanded by the hadoop configuration.
+//! The TLS keystore is either mounted from a Secret, or a self-signed one generated by an init-container.
+
+use std::collections::HashMap;
+
+use k8s_openapi::api::core::v1::{
+    Container, EmptyDirVolumeSource, EnvVar, EnvVarSource, SecretKeySelector, Volume, VolumeMount,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::spark_application::SecretInfo;
+
+pub const HIVE_SERVER2_SECRET_DIR_NAME: &str = "/bytenative/hive-server2";
+pub const HIVE_SERVER2_DEFAULT_KEYTAB_KEY: &str = "hive.keytab";
+pub const PASSWORD_FILE_DEFAULT_KEY: &str = "passwords";
+/// the provider of the operator's thrift server jar, see the `java` folder
+pub const PASSWORD_FILE_PROVIDER_CLASS: &str =
+    "org.apache.spark.sql.hive.thriftserver.PasswordFileAuthenticationProvider";
+pub const LDAP_BIND_USER_ENV: &str = "HIVE_SERVER2_LDAP_BIND_USER";
+pub const LDAP_BIND_PASSWORD_ENV: &str = "HIVE_SERVER2_LDAP_BIND_PASSWORD";
+pub const TLS_DEFAULT_KEYSTORE_KEY: &str = "keystore.p12";
+pub const TLS_DEFAULT_PASSWORD_KEY: &str = "password";
+pub const TLS_KEYSTORE_PASSWORD_ENV: &str = "HIVE_SERVER2_KEYSTORE_PASSWORD";
+pub const TLS_SELF_SIGNED_VOLUME_NAME: &str = "hs2-tls-keystore";
+pub const TLS_SELF_SIGNED_INIT_CONTAINER_NAME: &str = "hs2-tls-keystore";
+pub const TLS_SELF_SIGNED_KEYSTORE_FILE_NAME: &str = "keystore.p12";
+// the self-signed keystore is generated into an emptyDir of the driver, holding no secret beyond the pod
+pub const TLS_SELF_SIGNED_STORE_PASSWORD: &str = "bytenative";
+
+// config names
+pub const SPARK_HIVE_SERVER2_AUTHENTICATION: &str = "spark.hive.server2.authentication";
+pub const SPARK_HIVE_SERVER2_LDAP_URL: &str = "spark.hive.server2.authentication.ldap.url";
+pub const SPARK_HIVE_SERVER2_LDAP_BASE_DN: &str = "spark.hive.server2.authentication.ldap.baseDN";
+pub const SPARK_HIVE_SERVER2_LDAP_DOMAIN: &str = "spark.hive.server2.authentication.ldap.Domain";
+pub const SPARK_HIVE_SERVER2_LDAP_USER_DN_PATTERN: &str = "spark.hive.server2.authentication.ldap.userDNPattern";
+pub const SPARK_HIVE_SERVER2_LDAP_BIND_DN: &str = "spark.hive.server2.authentication.ldap.binddn";
+pub const SPARK_HIVE_SERVER2_LDAP_BIND_PASSWORD: &str = "spark.hive.server2.authentication.ldap.bindpw";
+pub const SPARK_HIVE_SERVER2_KERBEROS_PRINCIPAL: &str = "spark.hive.server2.authentication.kerberos.principal";
+pub const SPARK_HIVE_SERVER2_KERBEROS_KEYTAB: &str = "spark.hive.server2.authentication.kerberos.keytab";
+pub const SPARK_HIVE_SERVER2_CUSTOM_CLASS: &str = "spark.hive.server2.custom.authentication.class";
+pub const SPARK_HIVE_SERVER2_CUSTOM_FILE: &str = "spark.hive.server2.custom.authentication.file";
+pub const SPARK_HIVE_SERVER2_USE_SSL: &str = "spark.hive.server2.use.SSL";
+pub const SPARK_HIVE_SERVER2_KEYSTORE_PATH: &str = "spark.hive.server2.keystore.path";
+pub const SPARK_HIVE_SERVER2_KEYSTORE_PASSWORD: &str = "spark.hive.server2.keystore.password";
+
+/// How the clients of the hive server2 are authenticated, none (`NONE`) if not set
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HiveServer2Authentication {
+    /// bind to the LDAP server with the user and password of the client
+    Ldap(LdapAuthentication),
+    /// the clients connect with their kerberos tickets, requires the `kerberos` of the spark spec for the krb5.conf
+    Kerberos(HiveServer2Kerberos),
+    /// the users of a file in a Secret, one `<user>:<password>` or `<user>:sha256:<hex digest>` per line
+    PasswordFile(PasswordFileAuthentication),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdapAuthentication {
+    /// for example: `ldaps://ldap.example.com:636`
+    pub url: String,
+    /// for example: `ou=people,dc=example,dc=com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_dn: Option<String>,
+    /// the domain of the Active Directory, appended to the user names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// for example: `uid=%s,ou=people,dc=example,dc=com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_dn_pattern: Option<String>,
+    /// the Secret with the `user` and `password` keys to bind and search the users with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_secret: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HiveServer2Kerberos {
+    /// the service principal of the hive server2, for example: `hive/_HOST@EXAMPLE.COM`
+    pub principal: String,
+    /// the Secret holding the keytab of the principal
+    pub keytab_secret: String,
+    /// the key of the keytab in the Secret, defaults to `hive.keytab`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keytab_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordFileAuthentication {
+    pub secret: String,
+    /// the key of the file in the Secret, defaults to `passwords`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// The TLS of the thrift endpoint, with a self-signed certificate if no keystore Secret is set
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HiveServer2Tls {
+    /// the Secret holding the keystore of the server certificate and its password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keystore_secret: Option<String>,
+    /// the key of the keystore in the Secret, defaults to `keystore.p12`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keystore_key: Option<String>,
+    /// the key of the keystore password in the Secret, defaults to `password`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_key: Option<String>,
+}
+
+/// The configs of an authentication or TLS, with what it adds into the driver
+#[derive(Clone, Debug, Default)]
+pub struct HiveServer2Security {
+    pub spark_configs: HashMap<String, String>,
+    pub secrets: Vec<SecretInfo>,
+    pub env: Vec<EnvVar>,
+    pub volumes: Vec<Volume>,
+    pub volume_mounts: Vec<VolumeMount>,
+    pub init_containers: Vec<Container>,
+}
+
+impl HiveServer2Authentication {
+    /// the `hive.server2.authentication` of the authentication
+    pub fn mode(&self) -> &'static str {
+        match self {
+            HiveServer2Authentication::Ldap(_) => "LDAP",
+            HiveServer2Authentication::Kerberos(_) => "KERBEROS",
+            HiveServer2Authentication::PasswordFile(_) => "CUSTOM",
+        }
+    }
+
+    pub fn security(&self) -> HiveServer2Security {
+        let mut security = HiveServer2Security {
+            spark_configs: HashMap::from([(SPARK_HIVE_SERVER2_AUTHENTICATION.to_string(), self.mode().to_string())]),
+            ..HiveServer2Security::default()
+        };
+        let configs = &mut security.spark_configs;
+        match self {
+            HiveServer2Authentication::Ldap(ldap) => {
+                configs.insert(SPARK_HIVE_SERVER2_LDAP_URL.to_string(), ldap.url.clone());
+                let optional = [
+                    (SPARK_HIVE_SERVER2_LDAP_BASE_DN, &ldap.base_dn),
+                    (SPARK_HIVE_SERVER2_LDAP_DOMAIN, &ldap.domain),
+                    (SPARK_HIVE_SERVER2_LDAP_USER_DN_PATTERN, &ldap.user_dn_pattern),
+                ];
+                for (key, value) in optional {
+                    if let Some(value) = value {
+                        configs.insert(key.to_string(), value.clone());
+                    }
+                }
+                if let Some(secret) = &ldap.bind_secret {
+                    for (key, env, secret_key) in [
+                        (SPARK_HIVE_SERVER2_LDAP_BIND_DN, LDAP_BIND_USER_ENV, "user"),
+                        (SPARK_HIVE_SERVER2_LDAP_BIND_PASSWORD, LDAP_BIND_PASSWORD_ENV, "password"),
+                    ] {
+                        configs.insert(key.to_string(), format!("${{env.{env}}}"));
+                        security.env.push(secret_env_var(env, secret, secret_key));
+                    }
+                }
+            }
+            HiveServer2Authentication::Kerberos(kerberos) => {
+                let dir = secret_dir(&kerberos.keytab_secret);
+                let key = kerberos.keytab_key.as_deref().unwrap_or(HIVE_SERVER2_DEFAULT_KEYTAB_KEY);
+                configs.insert(SPARK_HIVE_SERVER2_KERBEROS_PRINCIPAL.to_string(), kerberos.principal.clone());
+                configs.insert(SPARK_HIVE_SERVER2_KERBEROS_KEYTAB.to_string(), format!("{dir}/{key}"));
+                security.secrets.push(secret_mount(&kerberos.keytab_secret));
+            }
+            HiveServer2Authentication::PasswordFile(file) => {
+                let dir = secret_dir(&file.secret);
+                let key = file.key.as_deref().unwrap_or(PASSWORD_FILE_DEFAULT_KEY);
+                configs.insert(SPARK_HIVE_SERVER2_CUSTOM_CLASS.to_string(), PASSWORD_FILE_PROVIDER_CLASS.to_string());
+                configs.insert(SPARK_HIVE_SERVER2_CUSTOM_FILE.to_string(), format!("{dir}/{key}"));
+                security.secrets.push(secret_mount(&file.secret));
+            }
+        }
+        security
+    }
+}
+
+impl HiveServer2Tls {
+    /// the security of the TLS, the self-signed keystore is generated by the `image`, which needs the `keytool`
+    pub fn security(&self, image: &str) -> HiveServer2Security {
+        let mut security = HiveServer2Security::default();
+        let keystore = match &self.keystore_secret {
+            Some(secret) => {
+                let key = self.keystore_key.as_deref().unwrap_or(TLS_DEFAULT_KEYSTORE_KEY);
+                let password_key = self.password_key.as_deref().unwrap_or(TLS_DEFAULT_PASSWORD_KEY);
+                security.secrets.push(secret_mount(secret));
+                security.env.push(secret_env_var(TLS_KEYSTORE_PASSWORD_ENV, secret, password_key));
+                format!("{}/{key}", secret_dir(secret))
+            }
+            None => {
+                let dir = secret_dir(TLS_SELF_SIGNED_VOLUME_NAME);
+                let keystore = format!("{dir}/{TLS_SELF_SIGNED_KEYSTORE_FILE_NAME}");
+                let volume_mount = VolumeMount {
+                    name: TLS_SELF_SIGNED_VOLUME_NAME.to_string(),
+                    mount_path: dir,
+                    ..VolumeMount::default()
+                };
+                security.volumes.push(Volume {
+                    name: TLS_SELF_SIGNED_VOLUME_NAME.to_string(),
+                    empty_dir: Some(EmptyDirVolumeSource::default()),
+                    ..Volume::default()
+                });
+                security.init_containers.push(Container {
+                    name: TLS_SELF_SIGNED_INIT_CONTAINER_NAME.to_string(),
+                    image: Some(image.to_string()),
+                    command: Some(vec![
+                        "/bin/sh".to_string(),
+                        "-c".to_string(),
+                        format!(
+                            "keytool -genkeypair -noprompt -alias hive-server2 -keyalg RSA -keysize 2048 \
+                            -validity 3650 -dname CN=hive-server2 -keystore {keystore} -storetype PKCS12 \
+                            -storepass {TLS_SELF_SIGNED_STORE_PASSWORD}"
+                        ),
+                    ]),
+                    volume_mounts: Some(vec![volume_mount.clone()]),
+                    ..Container::default()
+                });
+                security.volume_mounts.push(VolumeMount {
+                    read_only: Some(true),
+                    ..volume_mount
+                });
+                security.env.push(EnvVar {
+                    name: TLS_KEYSTORE_PASSWORD_ENV.to_string(),
+                    value: Some(TLS_SELF_SIGNED_STORE_PASSWORD.to_string()),
+                    value_from: None,
+                });
+                keystore
+            }
+        };
+        security.spark_configs = HashMap::from([
+            (SPARK_HIVE_SERVER2_USE_SSL.to_string(), "true".to_string()),
+            (SPARK_HIVE_SERVER2_KEYSTORE_PATH.to_string(), keystore),
+            (
+                SPARK_HIVE_SERVER2_KEYSTORE_PASSWORD.to_string(),
+                format!("${{env.{TLS_KEYSTORE_PASSWORD_ENV}}}"),
+            ),
+        ]);
+        security
+    }
+}
+
+/// The JDBC URL of the hive server2 at `host`:`port`, with the session parameters the clients need
+pub fn jdbc_url(
+    host: &str,
+    port: i32,
+    authentication: Option<&HiveServer2Authentication>,
+    tls: Option<&HiveServer2Tls>,
+) -> String {
+    let mut url = format!("jdbc:hive2://{host}:{port}/default");
+    if let Some(HiveServer2Authentication::Kerberos(kerberos)) = authentication {
+        url.push_str(&format!(";principal={}", kerberos.principal));
+    }
+    if tls.is_some() {
+        url.push_str(";ssl=true");
+    }
+    url
+}
+
+fn secret_dir(secret: &str) -> String {
+    format!("{HIVE_SERVER2_SECRET_DIR_NAME}/{secret}")
+}
+
+fn secret_mount(secret: &str) -> SecretInfo {
+    SecretInfo {
+        name: secret.to_string(),
+        path: secret_dir(secret),
+        typ: "Generic".to_string(),
+    }
+}
+
+fn secret_env_var(name: &str, secret: &str, key: &str) -> EnvVar {
+    EnvVar {
+        name: name.to_string(),
+        value: None,
+        value_from: Some(EnvVarSource {
+            secret_key_ref: Some(SecretKeySelector {
+                key: key.to_string(),
+                name: Some(secret.to_string()),
+                optional: Some(false),
+            }),
+            ..EnvVarSource::default()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ldap_security() {
+        let auth: HiveServer2Authentication = serde_json::from_value(serde_json::json!({
+            "ldap": {
+                "url": "ldaps://ldap.example.com:636",
+                "baseDn": "ou=people,dc=example,dc=com",
+                "bindSecret": "ldap-bind",
+            }
+        }))
+        .unwrap();
+        let security = auth.security();
+
+        assert_eq!(security.spark_configs[SPARK_HIVE_SERVER2_AUTHENTICATION], "LDAP");
+        assert_eq!(security.spark_configs[SPARK_HIVE_SERVER2_LDAP_BASE_DN], "ou=people,dc=example,dc=com");
+        assert!(!security.spark_configs.contains_key(SPARK_HIVE_SERVER2_LDAP_DOMAIN));
+        assert_eq!(
+            security.spark_configs[SPARK_HIVE_SERVER2_LDAP_BIND_PASSWORD],
+            "${env.HIVE_SERVER2_LDAP_BIND_PASSWORD}"
+        );
+        assert_eq!(security.env.len(), 2);
+        assert!(security.secrets.is_empty());
+    }
+
+    #[test]
+    fn test_password_file_security() {
+        let auth = HiveServer2Authentication::PasswordFile(PasswordFileAuthentication {
+            secret: "hs2-users".to_string(),
+            key: None,
+        });
+        let security = auth.security();
+
+        assert_eq!(security.spark_configs[SPARK_HIVE_SERVER2_AUTHENTICATION], "CUSTOM");
+        assert_eq!(
+            security.spark_configs[SPARK_HIVE_SERVER2_CUSTOM_FILE],
+            "/bytenative/hive-server2/hs2-users/passwords"
+        );
+        assert_eq!(security.secrets[0].path, "/bytenative/hive-server2/hs2-users");
+    }
+
+    #[test]
+    fn test_tls_security() {
+        let security = HiveServer2Tls::default().security("spark:3.4.1");
+        assert_eq!(
+            security.spark_configs[SPARK_HIVE_SERVER2_KEYSTORE_PATH],
+            "/bytenative/hive-server2/hs2-tls-keystore/keystore.p12"
+        );
+        assert_eq!(security.init_containers[0].image, Some("spark:3.4.1".to_string()));
+        assert_eq!(security.volume_mounts[0].read_only, Some(true));
+        assert!(security.secrets.is_empty());
+
+        let tls = HiveServer2Tls {
+            keystore_secret: Some("hs2-tls".to_string()),
+            ..HiveServer2Tls::default()
+        };
+        let security = tls.security("spark:3.4.1");
+        assert_eq!(
+            security.spark_configs[SPARK_HIVE_SERVER2_KEYSTORE_PASSWORD],
+            "${env.HIVE_SERVER2_KEYSTORE_PASSWORD}"
+        );
+        assert_eq!(security.secrets[0].name, "hs2-tls");
+        assert!(security.init_containers.is_empty());
+        assert_eq!(
+            jdbc_url("s-hs2-thrift-svc.ns.svc", 10001, None, Some(&tls)),
+            "jdbc:hive2://s-hs2-thrift-svc.ns.svc:10001/default;ssl=true"
+        );
+    }
+}
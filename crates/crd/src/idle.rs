@@ -0,0 +1,191 @@
+//! Inactivity of the sessions, which are suspended once idle for their `idleTimeout`.
+//!
+//! The activity is probed from the Spark UI of the driver by default: the sessions online on the thrift
+//! server, which keep the session active even when they don't run any jobs, then the start of the application,
+//! and the submission and completion of its jobs from the monitoring REST API. A custom probe could be any HTTP
+//! endpoint of a Service returning `{"lastActivityTime": "<RFC 3339>"}`.
+//! The endpoints are reached through the service proxy of the K8S API service.
+
+use std::time::Duration;
+
+use k8s_openapi::chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// the UI service created by the spark-on-k8s-operator for the driver
+pub const SPARK_UI_SERVICE_SUFFIX: &str = "-ui-svc";
+pub const SPARK_UI_PORT: i32 = 4040;
+/// the JDBC/ODBC server page of the Spark UI, with the sessions of the thrift server
+pub const SPARK_THRIFT_SERVER_UI_PATH: &str = "sqlserver/";
+pub const SESSION_PHASE_SUSPENDED: &str = "Suspended";
+pub const SESSION_PHASE_RESUMING: &str = "Resuming";
+/// set on a suspended session to resume it, removed once it is resumed
+pub const SESSION_RESUME_ANNOTATION: &str = "spark.bytenative.com/resume";
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IdleProbe {
+    /// the online thrift server sessions and the jobs of the Spark UI of the driver
+    SparkUi(SparkUiIdleProbe),
+    Http(HttpIdleProbe),
+}
+
+impl Default for IdleProbe {
+    fn default() -> Self {
+        IdleProbe::SparkUi(SparkUiIdleProbe::default())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkUiIdleProbe {
+    /// the `spark.ui.port` of the driver, defaults to 4040
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<i32>,
+}
+
+/// An HTTP endpoint of a Service returning `{"lastActivityTime": "<RFC 3339>"}`
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpIdleProbe {
+    pub service: String,
+    pub port: i32,
+    /// for example: `/activity`
+    pub path: String,
+}
+
+/// Parse a duration of a single unit, for example: `90s`, `30m`, `2h` or `1d`, seconds without a unit
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidDuration {
+        value: value.to_string(),
+    };
+    let value = value.trim();
+    let (amount, unit) = value
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or((value, ""), |i| value.split_at(i));
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    amount.checked_mul(seconds).map(Duration::from_secs).ok_or_else(invalid)
+}
+
+/// The path of the `path` of the `service` through the service proxy of the K8S API service
+pub fn service_proxy_path(namespace: &str, service: &str, port: i32, path: &str) -> String {
+    format!(
+        "/api/v1/namespaces/{namespace}/services/{service}:{port}/proxy/{}",
+        path.trim_start_matches('/')
+    )
+}
+
+/// The time of the Spark REST API, for example: `2023-08-10T09:13:21.613GMT`
+fn parse_spark_time(value: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(value.trim_end_matches("GMT"), "%Y-%m-%dT%H:%M:%S%.3f").ok()?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// The id of the first application of `/api/v1/applications`
+pub fn spark_application_id(applications: &serde_json::Value) -> Option<String> {
+    applications
+        .as_array()?
+        .first()?
+        .get("id")?
+        .as_str()
+        .map(|id| id.to_string())
+}
+
+/// The last activity of the application from `/api/v1/applications` and its `/jobs`,
+/// now if any job is running
+pub fn spark_last_activity(
+    applications: &serde_json::Value,
+    jobs: &serde_json::Value,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let jobs = jobs.as_array()?;
+    if jobs.iter().any(|j| j.get("status").and_then(|s| s.as_str()) == Some("RUNNING")) {
+        return Some(now);
+    }
+    let started = applications
+        .as_array()?
+        .first()?
+        .get("attempts")?
+        .as_array()?
+        .iter()
+        .filter_map(|a| a.get("startTime")?.as_str());
+    let jobs = jobs.iter().flat_map(|j| {
+        ["submissionTime", "completionTime"]
+            .into_iter()
+            .filter_map(|key| j.get(key)?.as_str())
+    });
+    started.chain(jobs).filter_map(parse_spark_time).max()
+}
+
+/// The number of the sessions online on the thrift server, from its page of the Spark UI which has
+/// `<n> session(s) are online`
+pub fn thrift_server_online_sessions(page: &str) -> Option<u32> {
+    let before = page[..page.find("session(s) are online")?].trim_end();
+    let digits = before.len() - before.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    before[before.len() - digits..].parse().ok()
+}
+
+/// The last activity of the response of an HTTP probe
+pub fn http_last_activity(response: &serde_json::Value) -> Option<DateTime<Utc>> {
+    let value = response.get("lastActivityTime")?.as_str()?;
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(24 * 60 * 60));
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_thrift_server_online_sessions() {
+        let page = "<h4>\n          2\n          session(s) are online,\n          running\n          0\n          \
+                    SQL statement(s)\n        </h4>";
+        assert_eq!(thrift_server_online_sessions(page), Some(2));
+        assert_eq!(thrift_server_online_sessions("<h4>0 session(s) are online, running 0</h4>"), Some(0));
+        assert_eq!(thrift_server_online_sessions("<h4>Session Statistics (3)</h4>"), None);
+    }
+
+    #[test]
+    fn test_spark_last_activity() {
+        let applications = serde_json::json!([{
+            "id": "spark-1a2b",
+            "attempts": [{ "startTime": "2023-08-10T09:00:00.000GMT" }],
+        }]);
+        let now = DateTime::parse_from_rfc3339("2023-08-10T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(spark_application_id(&applications).as_deref(), Some("spark-1a2b"));
+
+        let jobs = serde_json::json!([
+            { "status": "SUCCEEDED", "submissionTime": "2023-08-10T10:00:00.000GMT", "completionTime": "2023-08-10T10:05:30.250GMT" },
+            { "status": "FAILED", "submissionTime": "2023-08-10T09:30:00.000GMT" },
+        ]);
+        let last = spark_last_activity(&applications, &jobs, now).unwrap();
+        assert_eq!(last.to_rfc3339(), "2023-08-10T10:05:30.250+00:00");
+
+        // no job yet, idle since the start
+        let last = spark_last_activity(&applications, &serde_json::json!([]), now).unwrap();
+        assert_eq!(last.to_rfc3339(), "2023-08-10T09:00:00+00:00");
+
+        let running = serde_json::json!([{ "status": "RUNNING" }]);
+        assert_eq!(spark_last_activity(&applications, &running, now), Some(now));
+    }
+}
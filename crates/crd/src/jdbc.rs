@@ -0,0 +1,189 @@
+//! JDBC data sources shared by the spark applications.
+//!
+//! A source adds its driver jars to the deps, exposes its connection as spark configs under
+//! `spark.bytenative.jdbc.<name>` and its credentials as env-vars referencing the Secret,
+//! and could be registered as a JDBC catalog of the v2 catalog API.
+
+use std::collections::HashMap;
+
+use k8s_openapi::api::core::v1::{EnvVar, EnvVarSource, SecretKeySelector};
+use kube::{Api, Client, CustomResource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+pub const SPARK_JDBC_SOURCE_CONF_PREFIX: &str = "spark.bytenative.jdbc";
+pub const JDBC_TABLE_CATALOG_CLASS: &str = "org.apache.spark.sql.execution.datasources.v2.jdbc.JDBCTableCatalog";
+pub const JDBC_DEFAULT_USER_KEY: &str = "user";
+pub const JDBC_DEFAULT_PASSWORD_KEY: &str = "password";
+
+#[derive(Clone, CustomResource, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq)]
+#[kube(
+    group = "spark.bytenative.com",
+    version = "v1",
+    kind = "SparkJdbcSource",
+    plural = "sparkjdbcsources",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct SparkJdbcSourceSpec {
+    /// the name of the source in the spark configs, env-vars and catalog, for example: `sales`
+    pub name: String,
+    /// for example: `jdbc:postgresql://postgres:5432/sales`
+    pub url: String,
+    /// the class of the JDBC driver, for example: `org.postgresql.Driver`
+    pub driver: String,
+    /// the jars of the JDBC driver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jars: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<JdbcCredentials>,
+    /// the default options of reading, for example: `fetchsize: "1000"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_options: Option<HashMap<String, String>>,
+    /// the default options of writing, for example: `batchsize: "1000"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_options: Option<HashMap<String, String>>,
+    /// Register the source as a JDBC catalog with its name, defaults to false. The catalog authenticates by the
+    /// credentials as the system properties of the driver, so their values can't contain a single quote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog: Option<bool>,
+}
+
+/// The user and password of the source in a Secret
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JdbcCredentials {
+    pub secret: String,
+    /// the key of the user in the Secret, defaults to `user`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_key: Option<String>,
+    /// the key of the password in the Secret, defaults to `password`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_key: Option<String>,
+}
+
+impl SparkJdbcSourceSpec {
+    /// Convenience function to retrieve the spec of a JDBC source resource from the K8S API service.
+    pub async fn get(resource_name: &str, client: &Client, namespace: &str) -> Result<SparkJdbcSourceSpec> {
+        let resource_api = Api::<SparkJdbcSource>::namespaced(client.clone(), namespace);
+        let resource = resource_api
+            .get(resource_name)
+            .await
+            .map_err(|_| Error::MissingSparkJdbcSource {
+                name: resource_name.to_string(),
+            })?;
+
+        Ok(resource.spec)
+    }
+
+    pub fn catalog_enabled(&self) -> bool {
+        self.catalog.unwrap_or(false)
+    }
+
+    /// The env-var `name` of this source, for example: `JDBC_SALES_PASSWORD`
+    pub fn env_var_name(&self, name: &str) -> String {
+        let source = self
+            .name
+            .to_uppercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        format!("JDBC_{source}_{name}")
+    }
+
+    pub fn spark_configs(&self) -> HashMap<String, String> {
+        let prefix = format!("{SPARK_JDBC_SOURCE_CONF_PREFIX}.{}", self.name);
+        let mut configs = HashMap::from([
+            (format!("{prefix}.url"), self.url.clone()),
+            (format!("{prefix}.driver"), self.driver.clone()),
+        ]);
+        if self.credentials.is_some() {
+            configs.insert(format!("{prefix}.user.env"), self.env_var_name("USER"));
+            configs.insert(format!("{prefix}.password.env"), self.env_var_name("PASSWORD"));
+        }
+        for (options, typ) in [(&self.read_options, "read"), (&self.write_options, "write")] {
+            for (k, v) in options.iter().flatten() {
+                configs.insert(format!("{prefix}.{typ}.{k}"), v.clone());
+            }
+        }
+
+        if self.catalog_enabled() {
+            let catalog = format!("spark.sql.catalog.{}", self.name);
+            configs.insert(catalog.clone(), JDBC_TABLE_CATALOG_CLASS.to_string());
+            configs.insert(format!("{catalog}.url"), self.url.clone());
+            configs.insert(format!("{catalog}.driver"), self.driver.clone());
+            // the catalog takes the options of both reading and writing
+            for (k, v) in self.read_options.iter().chain(self.write_options.iter()).flatten() {
+                configs.insert(format!("{catalog}.{k}"), v.clone());
+            }
+        }
+        configs
+    }
+
+    /// The credentials env-vars referencing the keys of the Secret
+    pub fn env_vars(&self) -> Option<Vec<EnvVar>> {
+        let credentials = self.credentials.as_ref()?;
+        let env_var = |name: &str, key: &str| EnvVar {
+            name: self.env_var_name(name),
+            value: None,
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    key: key.to_string(),
+                    name: Some(credentials.secret.clone()),
+                    optional: Some(false),
+                }),
+                ..EnvVarSource::default()
+            }),
+        };
+        Some(vec![
+            env_var(
+                "USER",
+                credentials.user_key.as_deref().unwrap_or(JDBC_DEFAULT_USER_KEY),
+            ),
+            env_var(
+                "PASSWORD",
+                credentials.password_key.as_deref().unwrap_or(JDBC_DEFAULT_PASSWORD_KEY),
+            ),
+        ])
+    }
+}
+
+/// Operators are expected to define fields for this type in order to work with JDBC sources.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SparkJdbcSourceDef {
+    Inline(Box<SparkJdbcSourceSpec>),
+    Reference(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spark_configs() {
+        let source: SparkJdbcSourceSpec = serde_yaml::from_str(
+            "
+            name: sales
+            url: jdbc:postgresql://postgres:5432/sales
+            driver: org.postgresql.Driver
+            credentials:
+              secret: sales-db
+            readOptions:
+              fetchsize: '1000'
+            catalog: true
+            ",
+        )
+        .unwrap();
+        let configs = source.spark_configs();
+        assert_eq!(configs["spark.bytenative.jdbc.sales.read.fetchsize"], "1000");
+        assert_eq!(configs["spark.bytenative.jdbc.sales.password.env"], "JDBC_SALES_PASSWORD");
+        assert_eq!(configs["spark.sql.catalog.sales"], JDBC_TABLE_CATALOG_CLASS);
+        assert_eq!(configs["spark.sql.catalog.sales.fetchsize"], "1000");
+
+        let env_vars = source.env_vars().unwrap();
+        let secret_ref = env_vars[1].value_from.as_ref().unwrap().secret_key_ref.as_ref().unwrap();
+        assert_eq!(env_vars[1].name, "JDBC_SALES_PASSWORD");
+        assert_eq!(secret_ref.key, "password");
+    }
+}
@@ -0,0 +1,158 @@
+//! Kerberos authentication to the secured HDFS and Hive metastore.
+//!
+//! The keytab Secret is mounted into the driver, which logs in and distributes the delegation tokens
+//! to the executors, the krb5.conf ConfigMap is mounted into both of them by spark.
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::spark_application::SecretInfo;
+
+pub const KERBEROS_SECRET_DIR_NAME: &str = "/bytenative/kerberos";
+pub const KERBEROS_DEFAULT_KEYTAB_KEY: &str = "krb5.keytab";
+
+pub const SPARK_KERBEROS_PRINCIPAL: &str = "spark.kerberos.principal";
+pub const SPARK_KERBEROS_KEYTAB: &str = "spark.kerberos.keytab";
+pub const SPARK_KERBEROS_RELOGIN_PERIOD: &str = "spark.kerberos.relogin.period";
+pub const SPARK_KERBEROS_ACCESS_FILE_SYSTEMS: &str = "spark.kerberos.access.hadoopFileSystems";
+pub const SPARK_KERBEROS_KRB5_CONFIG_MAP: &str = "spark.kubernetes.kerberos.krb5.configMapName";
+pub const SPARK_CREDENTIALS_RENEWAL_RATIO: &str = "spark.security.credentials.renewalRatio";
+pub const SPARK_CREDENTIALS_RETRY_WAIT: &str = "spark.security.credentials.retryWait";
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KerberosSpec {
+    /// the principal to login with, for example: `spark/_HOST@EXAMPLE.COM`
+    pub principal: String,
+    /// the Secret holding the keytab of the principal
+    pub keytab_secret: String,
+    /// the key of the keytab in the Secret, defaults to `krb5.keytab`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keytab_key: Option<String>,
+    /// the ConfigMap holding the `krb5.conf` file
+    pub krb5_config_map: String,
+    /// the principal of the Hive metastore, enables the SASL to the metastore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hive_metastore_principal: Option<String>,
+    /// the secured Hadoop file systems accessed besides the default one, for example: `hdfs://nn2:8020`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_systems: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_renewal: Option<TokenRenewalSpec>,
+}
+
+/// The renewal of the delegation tokens, which matters for the long-running applications
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenRenewalSpec {
+    /// the ratio of the token lifetime after which the tokens are renewed, for example: 0.75
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renewal_ratio: Option<f64>,
+    /// how long to wait before retrying a failed renewal, for example: 1h
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_wait: Option<String>,
+    /// how often to check whether the kerberos TGT should be renewed, for example: 1m
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relogin_period: Option<String>,
+}
+
+impl TokenRenewalSpec {
+    pub fn spark_configs(&self) -> HashMap<String, String> {
+        let mut configs = HashMap::new();
+        if let Some(ratio) = self.renewal_ratio {
+            configs.insert(SPARK_CREDENTIALS_RENEWAL_RATIO.to_string(), ratio.to_string());
+        }
+        if let Some(retry_wait) = &self.retry_wait {
+            configs.insert(SPARK_CREDENTIALS_RETRY_WAIT.to_string(), retry_wait.clone());
+        }
+        if let Some(relogin_period) = &self.relogin_period {
+            configs.insert(SPARK_KERBEROS_RELOGIN_PERIOD.to_string(), relogin_period.clone());
+        }
+        configs
+    }
+}
+
+impl KerberosSpec {
+    fn keytab_dir(&self) -> String {
+        format!("{KERBEROS_SECRET_DIR_NAME}/{}", self.keytab_secret)
+    }
+
+    pub fn spark_configs(&self) -> HashMap<String, String> {
+        let keytab_key = self
+            .keytab_key
+            .as_deref()
+            .unwrap_or(KERBEROS_DEFAULT_KEYTAB_KEY);
+        let mut configs = HashMap::from([
+            (SPARK_KERBEROS_PRINCIPAL.to_string(), self.principal.clone()),
+            // the keytab is in the driver container, not uploaded by the submission client
+            (
+                SPARK_KERBEROS_KEYTAB.to_string(),
+                format!("local://{}/{keytab_key}", self.keytab_dir()),
+            ),
+            (SPARK_KERBEROS_KRB5_CONFIG_MAP.to_string(), self.krb5_config_map.clone()),
+            (
+                "spark.hadoop.hadoop.security.authentication".to_string(),
+                "kerberos".to_string(),
+            ),
+            (
+                "spark.hadoop.hadoop.security.authorization".to_string(),
+                "true".to_string(),
+            ),
+        ]);
+        if let Some(principal) = &self.hive_metastore_principal {
+            configs.insert("spark.hadoop.hive.metastore.sasl.enabled".to_string(), "true".to_string());
+            configs.insert(
+                "spark.hadoop.hive.metastore.kerberos.principal".to_string(),
+                principal.clone(),
+            );
+        }
+        if let Some(file_systems) = &self.file_systems {
+            configs.insert(SPARK_KERBEROS_ACCESS_FILE_SYSTEMS.to_string(), file_systems.join(","));
+        }
+        if let Some(renewal) = &self.token_renewal {
+            configs.extend(renewal.spark_configs());
+        }
+        configs
+    }
+
+    /// the keytab secret, only mounted into the driver
+    pub fn secret_mount(&self) -> SecretInfo {
+        SecretInfo {
+            name: self.keytab_secret.clone(),
+            path: self.keytab_dir(),
+            typ: "Generic".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spark_configs() {
+        let kerberos: KerberosSpec = serde_yaml::from_str(
+            "
+            principal: spark@EXAMPLE.COM
+            keytabSecret: spark-keytab
+            krb5ConfigMap: krb5
+            hiveMetastorePrincipal: hive/_HOST@EXAMPLE.COM
+            tokenRenewal:
+              renewalRatio: 0.5
+            ",
+        )
+        .unwrap();
+        let configs = kerberos.spark_configs();
+        assert_eq!(
+            configs[SPARK_KERBEROS_KEYTAB],
+            "local:///bytenative/kerberos/spark-keytab/krb5.keytab"
+        );
+        assert_eq!(configs[SPARK_KERBEROS_KRB5_CONFIG_MAP], "krb5");
+        assert_eq!(configs["spark.hadoop.hive.metastore.sasl.enabled"], "true");
+        assert_eq!(configs[SPARK_CREDENTIALS_RENEWAL_RATIO], "0.5");
+        assert!(!configs.contains_key(SPARK_KERBEROS_ACCESS_FILE_SYSTEMS));
+        assert_eq!(kerberos.secret_mount().path, "/bytenative/kerberos/spark-keytab");
+    }
+}
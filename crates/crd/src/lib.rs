@@ -6,10 +6,23 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub mod metadata;
+pub mod catalog;
 pub mod constants;
+pub mod endpoints;
+pub mod gateway_api;
+pub mod hive_metastore;
+pub mod hive_server2;
+pub mod history_server;
+pub mod idle;
+pub mod jdbc;
+pub mod kerberos;
+pub mod merge;
+pub mod provenance;
+pub(crate) mod render;
 pub mod s3;
 pub mod sko_spark_application;
 pub mod spark_application;
+pub mod ui_exposure;
 
 // error definitions for crd
 #[derive(Debug, thiserror::Error)]
@@ -23,12 +36,33 @@ pub enum Error {
     #[error("Missing Spark Catalog [{name}]")]
     MissingSparkCatalog { name: String },
 
+    #[error("Invalid Spark Catalog [{name}]: {reason}")]
+    InvalidCatalog { name: String, reason: String },
+
     #[error("Missing Spark EnvSet [{name}]")]
     MissingSparEnvSet { name: String },
 
+    #[error("Missing Spark JDBC source [{name}]")]
+    MissingSparkJdbcSource { name: String },
+
+    #[error("Invalid Spark JDBC source [{name}]: {reason}")]
+    InvalidJdbcSource { name: String, reason: String },
+
+    #[error("Missing Hive metastore [{name}]")]
+    MissingHiveMetastore { name: String },
+
+    #[error("Missing Spark history server [{name}]")]
+    MissingSparkHistoryServer { name: String },
+
     #[error("Missing Spark template [{name}]")]
     MissingSparkTemplate { name: String },
 
+    #[error("Spark template inheritance is cyclic [{chain}]")]
+    SparkTemplateCycle { chain: String },
+
+    #[error("Spark template inheritance from [{name}] exceeds the max depth {max_depth}")]
+    SparkTemplateChainTooDeep { name: String, max_depth: usize },
+
     #[error("Failed to serialize resource [{internal}]")]
     FailedSerializeResource { internal: String },
 
@@ -58,6 +92,24 @@ pub enum Error {
     
     #[error("Resource namespace not exists [{name}]")]
     ResourceNamespaceNotExists { name: String },
+
+    #[error("Invalid authentication of the hive server2: {reason}")]
+    InvalidSessionAuthentication { reason: String },
+
+    #[error("Invalid duration [{value}], expected a number with the unit s, m, h or d")]
+    InvalidDuration { value: String },
+
+    #[error("Unsupported pod template: {reason}")]
+    UnsupportedPodTemplate { reason: String },
+
+    #[error("Invalid priority class: {reason}")]
+    InvalidPriorityClass { reason: String },
+
+    #[error("Invalid S3 TLS: {reason}")]
+    InvalidS3Tls { reason: String },
+
+    #[error("Invalid amount of resource [{name}]: {reason}")]
+    InvalidResourceAmount { name: String, reason: String },
 }
 
 
@@ -75,6 +127,33 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 #[serde(rename_all = "camelCase")]
 pub struct SparkApplicationStatus {
     pub phase: String,
+    /// the resolved `SparkTemplate` chain, from the referenced template to its root ancestor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_chain: Option<Vec<String>>,
+    /// the warnings of rendering the application, such as the plaintext secrets in the spark configs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<Vec<String>>,
+    /// the JDBC URL of the hive server2 of a session, in the cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jdbc_url: Option<String>,
+    /// the JDBC URL of the hive server2 of a session out of the cluster, by its load balancer or NodePort
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_jdbc_url: Option<String>,
+    /// the beeline command to connect to the hive server2 of a session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beeline: Option<String>,
+    /// the URL of the hive server2 UI of a session, by its Ingress or HTTPRoute, or its Service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ui_url: Option<String>,
+    /// the URL of the Spark UI of the driver of a session, by its Ingress or HTTPRoute, or its Service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spark_ui_url: Option<String>,
+    /// the NodePorts of a session already allocated to other Services, its Services are created once they're free
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_port_conflicts: Option<Vec<String>>,
+    /// the last activity of the session probed for its idle timeout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity_time: Option<String>,
 }
 
 // --------------------
@@ -102,6 +181,25 @@ pub struct SparkCatalogSpec {
     /// directly applied to the spark application
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub spark_configs: Option<HashMap<String, String>>,
+    /// the kerberos authentication to the catalog, for example a secured Hive metastore
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kerberos: Option<kerberos::KerberosSpec>,
+    /// the typed catalog, rendered into the impl class, catalog configs and spark configs above,
+    /// which still take precedence over the rendered ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<catalog::CatalogKind>,
+    /// the catalog configs from the keys of Secrets or ConfigMaps, such as the token of a REST catalog,
+    /// with the same prefix as `catalogConfigs`. They are passed as system properties of the driver, so
+    /// their values can't contain a single quote, and the ones from Secrets are added to `spark.redaction.regex`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog_configs_from: Option<HashMap<String, catalog::ConfigValueFrom>>,
+    /// the spark configs from the keys of Secrets or ConfigMaps, such as the password of the metastore database,
+    /// the ones without the `spark.hadoop.` prefix are passed as system properties like `catalogConfigsFrom`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spark_configs_from: Option<HashMap<String, catalog::ConfigValueFrom>>,
+    /// the name of a `HiveMetastore` in the namespace, whose URI is used unless one is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hive_metastore: Option<String>,
 }
 
 impl SparkCatalogSpec {
@@ -134,21 +232,95 @@ impl SparkCatalogSpec {
             .map(|(k, v)| (format!("spark.sql.catalog.{}.{}", name, k), v.clone()))
             .collect::<HashMap<String, String>>();
 
-        all_configs.extend([(
-            format!("spark.sql.catalog.{}", name),
-            self.impl_class.clone().unwrap_or_default(),
-        )]);
+        // not set for the built-in session catalog, such as the hive one
+        if let Some(impl_class) = &self.impl_class {
+            all_configs.insert(format!("spark.sql.catalog.{}", name), impl_class.clone());
+        }
         all_configs.extend(self.spark_configs.clone().unwrap_or_default());
         // Ok(ret)
         all_configs
     }
+
+    /// The spark configs from the keys of Secrets or ConfigMaps, sorted by the key
+    pub fn spark_configs_from(&self) -> std::collections::BTreeMap<String, catalog::ConfigValueFrom> {
+        let name = self.name.as_str();
+        let mut all_configs = self
+            .catalog_configs_from
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (format!("spark.sql.catalog.{}.{}", name, k), v))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        all_configs.extend(self.spark_configs_from.clone().unwrap_or_default());
+        all_configs
+    }
+
+    /// Flatten the typed kind into the free-form spec of the applications of `spark_version`,
+    /// along with the extensions and packages the kind requires
+    pub fn flattened(&self, spark_version: &str) -> Result<(SparkCatalogSpec, Option<catalog::CatalogRuntime>)> {
+        let Some(kind) = &self.kind else {
+            return Ok((self.clone(), None));
+        };
+        let runtime = kind.runtime(&self.name, spark_version)?;
+        let mut catalog_configs = runtime.catalog_configs.clone();
+        catalog_configs.extend(self.catalog_configs.clone().unwrap_or_default());
+        let mut spark_configs = runtime.spark_configs.clone();
+        spark_configs.extend(self.spark_configs.clone().unwrap_or_default());
+        let flattened = SparkCatalogSpec {
+            impl_class: self.impl_class.clone().or_else(|| runtime.impl_class.clone()),
+            catalog_configs: Some(catalog_configs),
+            spark_configs: Some(spark_configs),
+            kind: None,
+            ..self.clone()
+        };
+        Ok((flattened, Some(runtime)))
+    }
+
+    /// Point the catalog to the metastore `uri`, a given one is kept
+    pub fn with_metastore_uri(mut self, uri: String) -> Result<SparkCatalogSpec> {
+        let session_configs = |spark_configs: &mut Option<HashMap<String, String>>| {
+            let spark_configs = spark_configs.get_or_insert_with(HashMap::new);
+            spark_configs
+                .entry("spark.sql.catalogImplementation".to_string())
+                .or_insert_with(|| "hive".to_string());
+            spark_configs
+                .entry("spark.hadoop.hive.metastore.uris".to_string())
+                .or_insert_with(|| uri.clone());
+        };
+        match &mut self.kind {
+            Some(catalog::CatalogKind::Iceberg(iceberg)) => {
+                if iceberg.backend != catalog::IcebergBackend::Hive {
+                    return Err(Error::InvalidCatalog {
+                        name: self.name.clone(),
+                        reason: format!("a metastore is not supported by the {} backend of iceberg", iceberg.backend),
+                    });
+                }
+                iceberg.uri.get_or_insert(uri);
+            }
+            Some(catalog::CatalogKind::Hive(hive)) => {
+                hive.uri.get_or_insert(uri);
+            }
+            // the delta and hudi tables are kept in the metastore of the session catalog
+            Some(catalog::CatalogKind::Delta(_)) | Some(catalog::CatalogKind::Hudi(_)) => {
+                session_configs(&mut self.spark_configs)
+            }
+            None if self.name == catalog::SPARK_SESSION_CATALOG => session_configs(&mut self.spark_configs),
+            None => {
+                self.catalog_configs
+                    .get_or_insert_with(HashMap::new)
+                    .entry("uri".to_string())
+                    .or_insert(uri);
+            }
+        }
+        Ok(self)
+    }
 }
 
 /// Operators are expected to define fields for this type in order to work with Spark catalog.
 #[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum SparkCatalogDef {
-    Inline(SparkCatalogSpec),
+    Inline(Box<SparkCatalogSpec>),
     Reference(String),
 }
 
@@ -156,7 +328,7 @@ impl SparkCatalogDef {
     /// Return an [SparkCatalogSpec]
     pub async fn resolve(&self, client: &Client, namespace: &str) -> Result<SparkCatalogSpec> {
         match self {
-            SparkCatalogDef::Inline(spec) => Ok(spec.clone()),
+            SparkCatalogDef::Inline(spec) => Ok(*spec.clone()),
             SparkCatalogDef::Reference(resource_name) => {
                 SparkCatalogSpec::get(resource_name, client, namespace).await
             }
@@ -186,6 +358,9 @@ pub struct SparkEnvSetSpec {
     /// the deps including jars, files ...
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub deps: Option<spark_application::Dependencies>,
+    /// the kerberos authentication shared by the applications of this env set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kerberos: Option<kerberos::KerberosSpec>,
 }
 
 impl SparkEnvSetSpec {
@@ -212,7 +387,7 @@ impl SparkEnvSetSpec {
 #[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum SparkEnvSetDef {
-    Inline(SparkEnvSetSpec),
+    Inline(Box<SparkEnvSetSpec>),
     Reference(String),
 }
 
@@ -220,7 +395,7 @@ impl SparkEnvSetDef {
     /// resolve the spec
     pub async fn resolve(&self, client: &Client, namespace: &str) -> Result<SparkEnvSetSpec> {
         match self {
-            SparkEnvSetDef::Inline(spec) => Ok(spec.clone()),
+            SparkEnvSetDef::Inline(spec) => Ok(*spec.clone()),
             SparkEnvSetDef::Reference(resource_name) => {
                 SparkEnvSetSpec::get(resource_name, client, namespace).await
             }
@@ -303,6 +478,9 @@ pub fn serialize_crds_to_file(file: &str) -> Result<(), Error> {
     let s3b = serialize_crd_to_string::<crate::s3::S3Bucket>()?;
     let scl = serialize_crd_to_string::<SparkCatalog>()?;
     let ses = serialize_crd_to_string::<SparkEnvSet>()?;
+    let sjs = serialize_crd_to_string::<crate::jdbc::SparkJdbcSource>()?;
+    let hms = serialize_crd_to_string::<crate::hive_metastore::HiveMetastore>()?;
+    let shs = serialize_crd_to_string::<crate::history_server::SparkHistoryServer>()?;
 
     let contents = format!("
 ---
@@ -329,7 +507,16 @@ pub fn serialize_crds_to_file(file: &str) -> Result<(), Error> {
 ---
 {}
 
-    ", scj, ssj, scs, sct, s3c, s3b, scl, ses);
+---
+{}
+
+---
+{}
+
+---
+{}
+
+    ", scj, ssj, scs, sct, s3c, s3b, scl, ses, sjs, hms, shs);
     std::fs::write(file, contents).unwrap_or_else(|e| {
         println!("Write CRDs Error {:?}", e);
     });
@@ -368,41 +555,56 @@ where
 }
 
 /// generate SKO application from a specific type of Job/Session and input file, then print ...
-pub async fn print_sko_object_from(typ: ObjectType, file: String, client: &Client) -> Result<()> {
-    let yaml = match typ {
+pub async fn print_sko_object_from(
+    typ: ObjectType,
+    file: String,
+    provenance: bool,
+    client: &Client,
+) -> Result<()> {
+    let (yaml, config_maps, report) = match typ {
         ObjectType::Job => {
             let resource = resource_from_yaml_file::<crate::spark_application::SparkJob>(file)?;
             let name = resource.name_any();
             let namespace: String = resource.namespace().ok_or(Error::ResourceNamespaceNotExists { name })?;
-            let sko = resource.sko_application(client, namespace.as_str()).await?;
-            serde_yaml::to_string(&sko).map_err(|e| {
-                Error::FailedSerializeResource { internal: e.to_string() }
-            })?
+            let sko = resource.sko_resources(client, namespace.as_str()).await?;
+            (to_yaml(&sko.application)?, sko.config_maps, sko.report)
         }
         ObjectType::Session => {
             let resource = resource_from_yaml_file::<crate::spark_application::SparkSession>(file)?;
             let name = resource.name_any();
             let namespace: String = resource.namespace().ok_or(Error::ResourceNamespaceNotExists { name })?;
-            let sko = resource.sko_application(client, namespace.as_str()).await?;
-            serde_yaml::to_string(&sko).map_err(|e| {
-                Error::FailedSerializeResource { internal: e.to_string() }
-            })?          
+            let sko = resource.sko_resources(client, namespace.as_str()).await?;
+            (to_yaml(&sko.application)?, sko.config_maps, sko.report)
         }
         ObjectType::ScheduledJob => {
             let resource = resource_from_yaml_file::<crate::spark_application::SparkScheduledJob>(file)?;
             let name = resource.name_any();
             let namespace: String = resource.namespace().ok_or(Error::ResourceNamespaceNotExists { name })?;
-            let sko = resource.sko_application(client, namespace.as_str()).await?;
-            serde_yaml::to_string(&sko).map_err(|e| {
-                Error::FailedSerializeResource { internal: e.to_string() }
-            })?
+            let sko = resource.sko_resources(client, namespace.as_str()).await?;
+            (to_yaml(&sko.application)?, sko.config_maps, sko.report)
         }
     };
+    // the config maps are created before the application
+    for config_map in config_maps {
+        println!("---");
+        println!("{}", to_yaml(&config_map)?);
+    }
     println!("---");
-    println!("{}", yaml);          
+    println!("{}", yaml);
+    if provenance {
+        // the report as a separate document
+        println!("---");
+        println!("{}", report.to_yaml()?);
+    }
     Ok(())
 }
 
+fn to_yaml<T: Serialize>(value: &T) -> Result<String> {
+    serde_yaml::to_string(value).map_err(|e| {
+        Error::FailedSerializeResource { internal: e.to_string() }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use schemars::{JsonSchema, gen::SchemaGenerator};
@@ -0,0 +1,463 @@
+//! Strategic merge of `SparkSpec`s, used to merge a job/session spec onto its `SparkTemplate` chain.
+//!
+//! Unlike the json merge patch (RFC 7386), lists are merged with the knowledge of `SparkSpec`:
+//! - volumes, env, ports, containers, secrets and config maps are merged by their name
+//! - topology spread constraints are merged by their topology key, tolerations are unions
+//! - dependencies, env sets and image pull secrets are unions of both sides
+//! - catalogs, JDBC sources and S3 buckets with the same name or reference are replaced by the overlay
+//!
+//! The overlay could have explicit directives in the `$patch` field, keyed by the field path:
+//! ```yaml
+//! $patch:
+//!   volumes: replace                     # use the volumes of the overlay only
+//!   driver.sidecars[istio-proxy]: delete # remove a named element from the base
+//!   sparkConf[spark.ui.enabled]: delete  # remove a key from the base
+//! ```
+//! Both directives drop the matched value from the base before merging,
+//! so `replace` takes the overlay value as is, and `delete` just removes the base value if the overlay has none.
+
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use strum::{Display, EnumString};
+
+pub const PATCH_DIRECTIVES_KEY: &str = "$patch";
+
+#[derive(
+    Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString,
+)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum PatchDirective {
+    /// the overlay value replaces the base value, instead of being merged into it
+    Replace,
+    /// the base value is removed
+    Delete,
+}
+
+/// How the lists of a field are merged
+enum ListStrategy {
+    /// merge the elements with the same key, and append the others
+    MergeByKey(fn(&Value) -> Option<String>),
+    /// replace the elements with the same key, and append the others
+    ReplaceByKey(fn(&Value) -> Option<String>),
+    /// append the elements which are not in the base yet
+    Union,
+    /// the overlay list replaces the base list
+    Replace,
+}
+
+fn by_name(v: &Value) -> Option<String> {
+    v.get("name").and_then(Value::as_str).map(str::to_string)
+}
+
+fn by_mount_path(v: &Value) -> Option<String> {
+    v.get("mountPath").and_then(Value::as_str).map(str::to_string)
+}
+
+fn by_topology_key(v: &Value) -> Option<String> {
+    v.get("topologyKey").and_then(Value::as_str).map(str::to_string)
+}
+
+/// `{"reference": name}` or `{"inline": {"name": name, ...}}`
+fn by_catalog_name(v: &Value) -> Option<String> {
+    v.get("reference")
+        .or_else(|| v.get("inline").and_then(|i| i.get("name")))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+/// `{"reference": name}` or `{"inline": {"bucketName": name, ...}}`
+fn by_bucket_name(v: &Value) -> Option<String> {
+    v.get("reference")
+        .or_else(|| v.get("inline").and_then(|i| i.get("bucketName")))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+fn list_strategy(path: &str) -> ListStrategy {
+    match path {
+        "volumes" => ListStrategy::MergeByKey(by_name),
+        "imagePullSecrets" | "envSets" => ListStrategy::Union,
+        "catalogs" | "jdbcSources" => ListStrategy::ReplaceByKey(by_catalog_name),
+        "s3Buckets" => ListStrategy::ReplaceByKey(by_bucket_name),
+        "deps.jars" | "deps.files" | "deps.pyFiles" | "deps.packages" | "deps.excludePackages"
+        | "deps.repositories" => ListStrategy::Union,
+        _ => {
+            // fields of driver, executor and their containers
+            let field = path.rsplit('.').next().unwrap_or(path);
+            match field {
+                "env" | "ports" | "sidecars" | "initContainers" | "secrets" | "configMaps" => {
+                    ListStrategy::MergeByKey(by_name)
+                }
+                "volumeMounts" => ListStrategy::MergeByKey(by_mount_path),
+                "topologySpreadConstraints" => ListStrategy::MergeByKey(by_topology_key),
+                "envFrom" | "tolerations" => ListStrategy::Union,
+                _ => ListStrategy::Replace,
+            }
+        }
+    }
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// Merge the serialized `overlay` spec onto the serialized `base` spec,
+/// the `$patch` directives of the overlay are applied to the base at first.
+pub fn merge_spark_spec(base: &mut Value, overlay: &Value) {
+    let mut overlay = overlay.clone();
+    let directives = overlay
+        .as_object_mut()
+        .and_then(|o| o.remove(PATCH_DIRECTIVES_KEY))
+        .and_then(|d| serde_json::from_value::<BTreeMap<String, PatchDirective>>(d).ok())
+        .unwrap_or_default();
+    for path in directives.keys() {
+        remove_path(base, path);
+    }
+    merge_value("", base, &overlay);
+}
+
+/// Remove the value at `path`, or the element `[key]` of the list or map at `path`
+fn remove_path(base: &mut Value, path: &str) {
+    let (path, element) = match path.strip_suffix(']').and_then(|p| p.split_once('[')) {
+        Some((path, element)) => (path, Some(element)),
+        None => (path, None),
+    };
+    let mut segments = path.split('.').collect::<Vec<&str>>();
+    let last = match element {
+        Some(_) => None,
+        None => segments.pop(),
+    };
+    let mut target = base;
+    for segment in segments {
+        match target.get_mut(segment) {
+            Some(next) => target = next,
+            None => return,
+        }
+    }
+    match (last, element) {
+        (Some(last), _) => {
+            if let Value::Object(map) = target {
+                map.remove(last);
+            }
+        }
+        (None, Some(element)) => match target {
+            Value::Object(map) => {
+                map.remove(element);
+            }
+            Value::Array(list) => {
+                let key_fn = match list_strategy(path) {
+                    ListStrategy::MergeByKey(key_fn) | ListStrategy::ReplaceByKey(key_fn) => Some(key_fn),
+                    _ => None,
+                };
+                list.retain(|v| match key_fn {
+                    Some(key_fn) => key_fn(v).as_deref() != Some(element),
+                    None => v.as_str() != Some(element),
+                });
+            }
+            _ => {}
+        },
+        (None, None) => {}
+    }
+}
+
+fn merge_value(path: &str, base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => merge_object(path, base, overlay),
+        (Value::Array(base), Value::Array(overlay)) => merge_list(path, base, overlay),
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+fn merge_object(path: &str, base: &mut Map<String, Value>, overlay: &Map<String, Value>) {
+    for (key, value) in overlay {
+        // unset fields of the typed specs are serialized as null, they are inherited from the base,
+        // the base values are removed by the `delete` directive only
+        if value.is_null() {
+            continue;
+        } else if let Some(current) = base.get_mut(key) {
+            merge_value(&child_path(path, key), current, value);
+        } else {
+            base.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+fn merge_list(path: &str, base: &mut Vec<Value>, overlay: &[Value]) {
+    match list_strategy(path) {
+        ListStrategy::MergeByKey(key_fn) => {
+            for value in overlay {
+                let key = key_fn(value);
+                match base.iter_mut().find(|v| key.is_some() && key_fn(v) == key) {
+                    // elements of keyed lists are merged as objects, the path stays the field path
+                    Some(current) => merge_value(path, current, value),
+                    None => base.push(value.clone()),
+                }
+            }
+        }
+        ListStrategy::ReplaceByKey(key_fn) => {
+            for value in overlay {
+                let key = key_fn(value);
+                match base.iter_mut().find(|v| key.is_some() && key_fn(v) == key) {
+                    Some(current) => *current = value.clone(),
+                    None => base.push(value.clone()),
+                }
+            }
+        }
+        ListStrategy::Union => {
+            for value in overlay {
+                if !base.contains(value) {
+                    base.push(value.clone());
+                }
+            }
+        }
+        ListStrategy::Replace => *base = overlay.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn merged(mut base: Value, overlay: Value) -> Value {
+        merge_spark_spec(&mut base, &overlay);
+        base
+    }
+
+    #[test]
+    fn test_merge_maps_by_key() {
+        let v = merged(
+            json!({"sparkConf": {"a": "1", "b": "2"}, "driver": {"labels": {"x": "1"}}}),
+            json!({"sparkConf": {"b": "3", "c": "4"}, "driver": {"labels": {"y": "2"}}}),
+        );
+        assert_eq!(v["sparkConf"], json!({"a": "1", "b": "3", "c": "4"}));
+        assert_eq!(v["driver"]["labels"], json!({"x": "1", "y": "2"}));
+    }
+
+    #[test]
+    fn test_merge_volumes_by_name() {
+        let v = merged(
+            json!({"volumes": [{"name": "a", "emptyDir": {}}, {"name": "b", "emptyDir": {}}]}),
+            json!({"volumes": [{"name": "b", "hostPath": {"path": "/tmp"}}, {"name": "c", "emptyDir": {}}]}),
+        );
+        assert_eq!(
+            v["volumes"],
+            json!([
+                {"name": "a", "emptyDir": {}},
+                {"name": "b", "emptyDir": {}, "hostPath": {"path": "/tmp"}},
+                {"name": "c", "emptyDir": {}},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_env_by_name() {
+        for role in ["driver", "executor"] {
+            let v = merged(
+                json!({role: {"env": [{"name": "A", "value": "1"}, {"name": "B", "value": "2"}]}}),
+                json!({role: {"env": [{"name": "B", "value": "3"}, {"name": "C", "value": "4"}]}}),
+            );
+            assert_eq!(
+                v[role]["env"],
+                json!([
+                    {"name": "A", "value": "1"},
+                    {"name": "B", "value": "3"},
+                    {"name": "C", "value": "4"},
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_ports_by_name() {
+        let v = merged(
+            json!({"driver": {"ports": [{"name": "ui", "protocol": "TCP", "containerPort": 4040}]}}),
+            json!({"driver": {"ports": [
+                {"name": "ui", "protocol": "TCP", "containerPort": 4041},
+                {"name": "jmx", "protocol": "TCP", "containerPort": 8090},
+            ]}}),
+        );
+        assert_eq!(
+            v["driver"]["ports"],
+            json!([
+                {"name": "ui", "protocol": "TCP", "containerPort": 4041},
+                {"name": "jmx", "protocol": "TCP", "containerPort": 8090},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_scheduling_fields() {
+        let spot = json!({"key": "spot", "operator": "Exists", "effect": "NoSchedule"});
+        let gpu = json!({"key": "gpu", "operator": "Exists", "effect": "NoSchedule"});
+        let v = merged(
+            json!({"executor": {
+                "tolerations": [spot.clone()],
+                "topologySpreadConstraints": [
+                    {"topologyKey": "zone", "maxSkew": 1, "whenUnsatisfiable": "ScheduleAnyway"},
+                ],
+            }}),
+            json!({"executor": {
+                "tolerations": [spot.clone(), gpu.clone()],
+                "topologySpreadConstraints": [
+                    {"topologyKey": "zone", "maxSkew": 2},
+                    {"topologyKey": "hostname", "maxSkew": 1, "whenUnsatisfiable": "DoNotSchedule"},
+                ],
+            }}),
+        );
+        assert_eq!(v["executor"]["tolerations"], json!([spot, gpu]));
+        assert_eq!(
+            v["executor"]["topologySpreadConstraints"],
+            json!([
+                {"topologyKey": "zone", "maxSkew": 2, "whenUnsatisfiable": "ScheduleAnyway"},
+                {"topologyKey": "hostname", "maxSkew": 1, "whenUnsatisfiable": "DoNotSchedule"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_containers_by_name() {
+        for field in ["sidecars", "initContainers"] {
+            let v = merged(
+                json!({"executor": {field: [
+                    {"name": "proxy", "image": "proxy:1", "env": [{"name": "A", "value": "1"}]},
+                ]}}),
+                json!({"executor": {field: [
+                    {"name": "proxy", "image": "proxy:2", "env": [{"name": "B", "value": "2"}]},
+                    {"name": "agent", "image": "agent:1"},
+                ]}}),
+            );
+            assert_eq!(
+                v["executor"][field],
+                json!([
+                    {"name": "proxy", "image": "proxy:2", "env": [{"name": "A", "value": "1"}, {"name": "B", "value": "2"}]},
+                    {"name": "agent", "image": "agent:1"},
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_volume_mounts_by_path() {
+        let v = merged(
+            json!({"driver": {"volumeMounts": [{"name": "a", "mountPath": "/a"}]}}),
+            json!({"driver": {"volumeMounts": [{"name": "b", "mountPath": "/a"}, {"name": "c", "mountPath": "/c"}]}}),
+        );
+        assert_eq!(
+            v["driver"]["volumeMounts"],
+            json!([{"name": "b", "mountPath": "/a"}, {"name": "c", "mountPath": "/c"}])
+        );
+    }
+
+    #[test]
+    fn test_merge_secrets_and_config_maps_by_name() {
+        let v = merged(
+            json!({"driver": {
+                "secrets": [{"name": "s", "path": "/s", "secretType": "Generic"}],
+                "configMaps": [{"name": "c", "path": "/c"}],
+            }}),
+            json!({"driver": {
+                "secrets": [{"name": "s", "path": "/t", "secretType": "Generic"}],
+                "configMaps": [{"name": "d", "path": "/d"}],
+            }}),
+        );
+        assert_eq!(v["driver"]["secrets"], json!([{"name": "s", "path": "/t", "secretType": "Generic"}]));
+        assert_eq!(v["driver"]["configMaps"], json!([{"name": "c", "path": "/c"}, {"name": "d", "path": "/d"}]));
+    }
+
+    #[test]
+    fn test_merge_deps_as_union() {
+        let v = merged(
+            json!({"deps": {"jars": ["a.jar", "b.jar"], "packages": ["g:a:1"]}}),
+            json!({"deps": {"jars": ["b.jar", "c.jar"], "files": ["f.txt"]}}),
+        );
+        assert_eq!(v["deps"]["jars"], json!(["a.jar", "b.jar", "c.jar"]));
+        assert_eq!(v["deps"]["packages"], json!(["g:a:1"]));
+        assert_eq!(v["deps"]["files"], json!(["f.txt"]));
+    }
+
+    #[test]
+    fn test_merge_env_sets_and_image_pull_secrets_as_union() {
+        let v = merged(
+            json!({"envSets": [{"reference": "mysql"}], "imagePullSecrets": ["a"]}),
+            json!({"envSets": [{"reference": "mysql"}, {"inline": {"configs": {"k": "v"}}}], "imagePullSecrets": ["b"]}),
+        );
+        assert_eq!(v["envSets"], json!([{"reference": "mysql"}, {"inline": {"configs": {"k": "v"}}}]));
+        assert_eq!(v["imagePullSecrets"], json!(["a", "b"]));
+    }
+
+    #[test]
+    fn test_merge_catalogs_by_name() {
+        let v = merged(
+            json!({"catalogs": [{"reference": "ib_hive_cat"}, {"inline": {"name": "cat_a", "implClass": "A"}}]}),
+            json!({"catalogs": [{"inline": {"name": "ib_hive_cat", "implClass": "B"}}, {"reference": "cat_c"}]}),
+        );
+        assert_eq!(
+            v["catalogs"],
+            json!([
+                {"inline": {"name": "ib_hive_cat", "implClass": "B"}},
+                {"inline": {"name": "cat_a", "implClass": "A"}},
+                {"reference": "cat_c"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_unknown_lists_are_replaced() {
+        let v = merged(
+            json!({"driver": {"lifecycle": {"preStop": {"exec": {"command": ["a", "b"]}}}}}),
+            json!({"driver": {"lifecycle": {"preStop": {"exec": {"command": ["c"]}}}}}),
+        );
+        assert_eq!(v["driver"]["lifecycle"]["preStop"]["exec"]["command"], json!(["c"]));
+    }
+
+    #[test]
+    fn test_patch_replace() {
+        let v = merged(
+            json!({"volumes": [{"name": "a"}], "deps": {"jars": ["a.jar"]}, "driver": {"env": [{"name": "A"}]}}),
+            json!({
+                "$patch": {"volumes": "replace", "deps.jars": "replace"},
+                "volumes": [{"name": "b"}],
+                "deps": {"jars": ["b.jar"]},
+                "driver": {"env": [{"name": "B"}]},
+            }),
+        );
+        assert_eq!(v["volumes"], json!([{"name": "b"}]));
+        assert_eq!(v["deps"]["jars"], json!(["b.jar"]));
+        assert_eq!(v["driver"]["env"], json!([{"name": "A"}, {"name": "B"}]));
+        assert!(v.get(PATCH_DIRECTIVES_KEY).is_none());
+    }
+
+    #[test]
+    fn test_patch_delete() {
+        let v = merged(
+            json!({
+                "monitoring": {"exposeDriverMetrics": true},
+                "sparkConf": {"spark.a": "1", "spark.b": "2"},
+                "executor": {"sidecars": [{"name": "proxy"}, {"name": "agent"}]},
+                "deps": {"jars": ["a.jar", "b.jar"]},
+            }),
+            json!({
+                "$patch": {
+                    "monitoring": "delete",
+                    "sparkConf[spark.a]": "delete",
+                    "executor.sidecars[proxy]": "delete",
+                    "deps.jars[a.jar]": "delete",
+                },
+            }),
+        );
+        assert!(v.get("monitoring").is_none());
+        assert_eq!(v["sparkConf"], json!({"spark.b": "2"}));
+        assert_eq!(v["executor"]["sidecars"], json!([{"name": "agent"}]));
+        assert_eq!(v["deps"]["jars"], json!(["b.jar"]));
+    }
+}
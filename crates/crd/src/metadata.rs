@@ -361,6 +361,14 @@ pub fn role_selector_labels<T: Resource>(
     labels
 }
 
+/// The tag of the `image` as the version label, `4.0.0` of `apache/hive:4.0.0`
+pub fn image_tag(image: &str) -> &str {
+    image
+        .rsplit_once(':')
+        .filter(|(_, tag)| !tag.contains('/'))
+        .map_or("latest", |(_, tag)| tag)
+}
+
 pub fn build_common_labels_for_all_managed_resources(
     app_name: &str,
     owner_name: &str,
@@ -0,0 +1,236 @@
+//! Provenance of the effective spark configs and dependencies of an application.
+//!
+//! The configs are collected from the templates, the spec itself, S3 connections, catalogs, env sets ...
+//! and a later source overwrites the keys of the former ones, the report records who set each key and
+//! who was overwritten, and a warning is added when a key is overwritten with a different value.
+
+use std::collections::{BTreeMap, HashMap};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::spark_application::Dependencies;
+
+pub const PROVENANCE_SOURCE_SPEC: &str = "spec";
+pub const PROVENANCE_SOURCE_SESSION: &str = "session";
+pub const PROVENANCE_SOURCE_RESOURCES: &str = "resources";
+pub const PROVENANCE_SOURCE_REDACTION: &str = "redaction";
+
+pub fn template_source(name: &str) -> String {
+    format!("template/{name}")
+}
+
+/// name of the ConfigMap holding the report of application `app_name`
+pub fn config_map_name(app_name: &str) -> String {
+    format!("{app_name}{}", crate::constants::PROVENANCE_CONFIG_MAP_SUFFIX)
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfProvenance {
+    pub value: String,
+    pub source: String,
+    /// the values overwritten by this one, in the order they were set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overwritten: Vec<OverwrittenConf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverwrittenConf {
+    pub value: String,
+    pub source: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvenanceReport {
+    /// effective spark config key -> where it came from
+    #[serde(default)]
+    pub spark_conf: BTreeMap<String, ConfProvenance>,
+    /// dependency (jar, file, package ...) -> the sources requiring it
+    #[serde(default)]
+    pub deps: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl ProvenanceReport {
+    /// Extend `conf` with `configs` from `source`, and record it
+    pub fn extend_conf(
+        &mut self,
+        conf: &mut HashMap<String, String>,
+        source: &str,
+        configs: HashMap<String, String>,
+    ) {
+        // sorted to make the warnings deterministic
+        let configs = configs.into_iter().collect::<BTreeMap<String, String>>();
+        for (key, value) in configs {
+            self.record_conf(&key, &value, source);
+            conf.insert(key, value);
+        }
+    }
+
+    /// Record `source` set the config `key` to `value`
+    pub fn record_conf(&mut self, key: &str, value: &str, source: &str) {
+        match self.spark_conf.get_mut(key) {
+            Some(current) => {
+                if current.value != value {
+                    let warning = format!(
+                        "Spark config [{key}] set by [{}] is overwritten by [{source}] with a different value",
+                        current.source
+                    );
+                    tracing::warn!("{}", warning);
+                    self.warnings.push(warning);
+                }
+                let overwritten = OverwrittenConf {
+                    value: std::mem::replace(&mut current.value, value.to_string()),
+                    source: std::mem::replace(&mut current.source, source.to_string()),
+                };
+                current.overwritten.push(overwritten);
+            }
+            None => {
+                self.spark_conf.insert(
+                    key.to_string(),
+                    ConfProvenance {
+                        value: value.to_string(),
+                        source: source.to_string(),
+                        overwritten: vec![],
+                    },
+                );
+            }
+        }
+    }
+
+    /// Append the `items` from `source` to the comma separated list config `key`, such as `spark.sql.extensions`,
+    /// the existing items are kept without a warning
+    pub fn append_list_conf(
+        &mut self,
+        conf: &mut HashMap<String, String>,
+        source: &str,
+        key: &str,
+        items: &[String],
+    ) {
+        let mut merged = conf
+            .get(key)
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|i| !i.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        for item in items {
+            if !merged.contains(item) {
+                merged.push(item.clone());
+            }
+        }
+        self.amend_conf(conf, source, key, merged.join(","));
+    }
+
+    /// Set the config `key` to `value` amending the current one, which is recorded as overwritten without
+    /// a warning since the current value is kept in the amended one
+    pub fn amend_conf(&mut self, conf: &mut HashMap<String, String>, source: &str, key: &str, value: String) {
+        match self.spark_conf.get_mut(key) {
+            Some(current) if current.value != value => {
+                let overwritten = OverwrittenConf {
+                    value: std::mem::replace(&mut current.value, value.clone()),
+                    source: std::mem::replace(&mut current.source, source.to_string()),
+                };
+                current.overwritten.push(overwritten);
+            }
+            Some(_) => {}
+            None => self.record_conf(key, &value, source),
+        }
+        conf.insert(key.to_string(), value);
+    }
+
+    /// Extend `deps` with `other` from `source`, and record it
+    pub fn extend_deps(&mut self, deps: &mut Dependencies, source: &str, other: Dependencies) {
+        self.record_deps(&other, source);
+        deps.extend(other);
+    }
+
+    /// Record `source` requires all the dependencies in `deps`
+    pub fn record_deps(&mut self, deps: &Dependencies, source: &str) {
+        for dep in deps.iter() {
+            self.record_dep(dep, source);
+        }
+    }
+
+    /// Record `source` requires the dependency `dep`
+    pub fn record_dep(&mut self, dep: &str, source: &str) {
+        let sources = self.deps.entry(dep.to_string()).or_default();
+        if !sources.iter().any(|s| s == source) {
+            sources.push(source.to_string());
+        }
+    }
+
+    pub fn to_yaml(&self) -> crate::Result<String> {
+        serde_yaml::to_string(self).map_err(|e| crate::Error::FailedSerializeResource {
+            internal: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conf_overwritten_with_warning() {
+        let mut report = ProvenanceReport::default();
+        let mut conf = HashMap::new();
+        report.extend_conf(
+            &mut conf,
+            PROVENANCE_SOURCE_SPEC,
+            HashMap::from([("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())]),
+        );
+        report.extend_conf(
+            &mut conf,
+            "catalog/c",
+            HashMap::from([("a".to_string(), "1".to_string()), ("b".to_string(), "3".to_string())]),
+        );
+
+        assert_eq!(conf.get("b"), Some(&"3".to_string()));
+        assert_eq!(report.spark_conf["a"].source, "catalog/c");
+        assert_eq!(
+            report.spark_conf["b"].overwritten,
+            vec![OverwrittenConf { value: "2".to_string(), source: "spec".to_string() }]
+        );
+        // only the key with a different value is warned
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("[b]"));
+    }
+
+    #[test]
+    fn test_append_list_conf() {
+        let mut report = ProvenanceReport::default();
+        let mut conf = HashMap::new();
+        report.extend_conf(
+            &mut conf,
+            PROVENANCE_SOURCE_SPEC,
+            HashMap::from([("ext".to_string(), "a.Ext, b.Ext".to_string())]),
+        );
+        report.append_list_conf(&mut conf, "catalog/c", "ext", &["b.Ext".to_string(), "c.Ext".to_string()]);
+
+        assert_eq!(conf["ext"], "a.Ext,b.Ext,c.Ext");
+        assert_eq!(report.spark_conf["ext"].source, "catalog/c");
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_deps_sources() {
+        let mut report = ProvenanceReport::default();
+        let mut deps = Dependencies::default();
+        let jars = Dependencies {
+            jars: Some(vec!["a.jar".to_string()]),
+            ..Dependencies::default()
+        };
+        report.extend_deps(&mut deps, "envSet/a", jars.clone());
+        report.extend_deps(&mut deps, "envSet/b", jars);
+
+        assert_eq!(report.deps["a.jar"], vec!["envSet/a", "envSet/b"]);
+    }
+}
@@ -0,0 +1,2848 @@
+//! Rendering of the SKO application spec from a spark spec.
+//!
+//! The rendering is a pipeline of stages applied in order onto the SKO spec:
+//! template, S3 connection and buckets, kerberos, catalogs, JDBC sources, event logs, env sets,
+//! security contexts, resources, redaction, then the job type or session specific stage.
+//! The referenced resources are resolved up front through a [`ResourceLookup`],
+//! so that every stage is a plain function of its inputs.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use k8s_openapi::api::core::v1::{
+    Affinity, Capabilities, Container, EmptyDirVolumeSource, EnvVar, Namespace, PodAffinityTerm,
+    PodSecurityContext, SeccompProfile, SecurityContext, TopologySpreadConstraint, Volume, VolumeMount,
+    WeightedPodAffinityTerm,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::{Api, Client, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::catalog::{SPARK_SQL_EXTENSIONS, SPARK_SUBMIT_OPTS_ENV};
+use crate::hive_metastore::HiveMetastore;
+use crate::hive_server2::{HiveServer2Authentication, HiveServer2Security};
+use crate::history_server::SparkHistoryServerSpec;
+use crate::jdbc::{SparkJdbcSourceDef, SparkJdbcSourceSpec};
+use crate::kerberos::KerberosSpec;
+use crate::provenance::{
+    ProvenanceReport, PROVENANCE_SOURCE_REDACTION, PROVENANCE_SOURCE_RESOURCES,
+    PROVENANCE_SOURCE_SESSION, PROVENANCE_SOURCE_SPEC,
+};
+use crate::s3::{
+    bucket_env_var_name, S3BucketDef, S3BucketSpec, S3ConnectionDef, S3ConnectionSpec, S3Credentials, S3TlsStores,
+};
+use crate::sko_spark_application::SKOSparkApplicationSpec;
+use crate::spark_application::{
+    Dependencies, DriverSpec, ExtendedResource, GPUSpec, JobSpec, Port, SecurityProfile,
+    SessionSpec, SparkJobType, SparkSpec, SparkTemplate, SparkTemplateChain,
+};
+use crate::{Error, Result};
+use crate::{SparkCatalogDef, SparkCatalogSpec, SparkEnvSetDef, SparkEnvSetSpec};
+
+/// Looks up the resources referenced by a spark spec.
+pub(crate) trait ResourceLookup {
+    async fn spark_template(&self, name: &str) -> Result<SparkSpec>;
+    async fn s3_connection(&self, name: &str) -> Result<S3ConnectionSpec>;
+    async fn s3_bucket(&self, name: &str) -> Result<S3BucketSpec>;
+    async fn catalog(&self, name: &str) -> Result<SparkCatalogSpec>;
+    async fn env_set(&self, name: &str) -> Result<SparkEnvSetSpec>;
+    async fn jdbc_source(&self, name: &str) -> Result<SparkJdbcSourceSpec>;
+    /// the URI of the `HiveMetastore`
+    async fn hive_metastore_uri(&self, name: &str) -> Result<String>;
+    async fn history_server(&self, name: &str) -> Result<SparkHistoryServerSpec>;
+    /// labels of the namespace the spark spec is rendered in
+    async fn namespace_labels(&self) -> Result<BTreeMap<String, String>>;
+}
+
+/// Looks up the resources from the K8S API service.
+pub(crate) struct KubeLookup<'a> {
+    client: &'a Client,
+    namespace: &'a str,
+}
+
+impl<'a> KubeLookup<'a> {
+    pub(crate) fn new(client: &'a Client, namespace: &'a str) -> Self {
+        KubeLookup { client, namespace }
+    }
+}
+
+impl ResourceLookup for KubeLookup<'_> {
+    async fn spark_template(&self, name: &str) -> Result<SparkSpec> {
+        let st_api = Api::<SparkTemplate>::namespaced(self.client.clone(), self.namespace);
+        let st = st_api.get(name).await.map_err(|_| Error::MissingSparkTemplate {
+            name: name.to_string(),
+        })?;
+        Ok(st.spec)
+    }
+
+    async fn s3_connection(&self, name: &str) -> Result<S3ConnectionSpec> {
+        S3ConnectionSpec::get(name, self.client, self.namespace).await
+    }
+
+    async fn s3_bucket(&self, name: &str) -> Result<S3BucketSpec> {
+        S3BucketSpec::get(name, self.client, self.namespace).await
+    }
+
+    async fn catalog(&self, name: &str) -> Result<SparkCatalogSpec> {
+        SparkCatalogSpec::get(name, self.client, self.namespace).await
+    }
+
+    async fn env_set(&self, name: &str) -> Result<SparkEnvSetSpec> {
+        SparkEnvSetSpec::get(name, self.client, self.namespace).await
+    }
+
+    async fn jdbc_source(&self, name: &str) -> Result<SparkJdbcSourceSpec> {
+        SparkJdbcSourceSpec::get(name, self.client, self.namespace).await
+    }
+
+    async fn hive_metastore_uri(&self, name: &str) -> Result<String> {
+        HiveMetastore::get(name, self.client, self.namespace)
+            .await?
+            .published_uri()
+            .ok_or(Error::MissingHiveMetastore {
+                name: name.to_string(),
+            })
+    }
+
+    async fn history_server(&self, name: &str) -> Result<SparkHistoryServerSpec> {
+        SparkHistoryServerSpec::get(name, self.client, self.namespace).await
+    }
+
+    async fn namespace_labels(&self) -> Result<BTreeMap<String, String>> {
+        let ns_api = Api::<Namespace>::all(self.client.clone());
+        let namespace = ns_api
+            .get(self.namespace)
+            .await
+            .map_err(|_| Error::ResourceNamespaceNotExists {
+                name: self.namespace.to_string(),
+            })?;
+        Ok(namespace.labels().clone())
+    }
+}
+
+/// Looks up the resources from memory, used to render without a cluster.
+#[cfg(test)]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InMemoryLookup {
+    pub templates: BTreeMap<String, SparkSpec>,
+    pub s3_connections: BTreeMap<String, S3ConnectionSpec>,
+    pub s3_buckets: BTreeMap<String, S3BucketSpec>,
+    pub catalogs: BTreeMap<String, SparkCatalogSpec>,
+    pub env_sets: BTreeMap<String, SparkEnvSetSpec>,
+    pub jdbc_sources: BTreeMap<String, SparkJdbcSourceSpec>,
+    pub hive_metastore_uris: BTreeMap<String, String>,
+    pub history_servers: BTreeMap<String, SparkHistoryServerSpec>,
+    pub namespace_labels: BTreeMap<String, String>,
+    /// fails the lookup of the namespace, like without the permission to get it
+    pub namespace_unreadable: bool,
+}
+
+#[cfg(test)]
+impl ResourceLookup for InMemoryLookup {
+    async fn spark_template(&self, name: &str) -> Result<SparkSpec> {
+        self.templates.get(name).cloned().ok_or(Error::MissingSparkTemplate {
+            name: name.to_string(),
+        })
+    }
+
+    async fn s3_connection(&self, name: &str) -> Result<S3ConnectionSpec> {
+        self.s3_connections.get(name).cloned().ok_or(Error::MissingS3Connection {
+            name: name.to_string(),
+        })
+    }
+
+    async fn s3_bucket(&self, name: &str) -> Result<S3BucketSpec> {
+        self.s3_buckets.get(name).cloned().ok_or(Error::MissingS3Bucket {
+            name: name.to_string(),
+        })
+    }
+
+    async fn catalog(&self, name: &str) -> Result<SparkCatalogSpec> {
+        self.catalogs.get(name).cloned().ok_or(Error::MissingSparkCatalog {
+            name: name.to_string(),
+        })
+    }
+
+    async fn env_set(&self, name: &str) -> Result<SparkEnvSetSpec> {
+        self.env_sets.get(name).cloned().ok_or(Error::MissingSparEnvSet {
+            name: name.to_string(),
+        })
+    }
+
+    async fn jdbc_source(&self, name: &str) -> Result<SparkJdbcSourceSpec> {
+        self.jdbc_sources.get(name).cloned().ok_or(Error::MissingSparkJdbcSource {
+            name: name.to_string(),
+        })
+    }
+
+    async fn hive_metastore_uri(&self, name: &str) -> Result<String> {
+        self.hive_metastore_uris.get(name).cloned().ok_or(Error::MissingHiveMetastore {
+            name: name.to_string(),
+        })
+    }
+
+    async fn history_server(&self, name: &str) -> Result<SparkHistoryServerSpec> {
+        self.history_servers.get(name).cloned().ok_or(Error::MissingSparkHistoryServer {
+            name: name.to_string(),
+        })
+    }
+
+    async fn namespace_labels(&self) -> Result<BTreeMap<String, String>> {
+        if self.namespace_unreadable {
+            return Err(Error::ResourceNamespaceNotExists {
+                name: "default".to_string(),
+            });
+        }
+        Ok(self.namespace_labels.clone())
+    }
+}
+
+/// The SKO spec being rendered, with the provenance of its configs.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Rendered {
+    pub spec: SKOSparkApplicationSpec,
+    pub report: ProvenanceReport,
+    /// the data of the Secrets managed for the application, keyed by the Secret name
+    pub secrets: BTreeMap<String, BTreeMap<String, String>>,
+    /// the TLS settings of all the S3 connections, applied to the JVMs by the [`S3TlsStage`]
+    pub s3_tls: S3TlsStores,
+    /// the configs from Secrets passed as system properties of the driver, which Spark redacts by their name
+    pub redacted_conf: BTreeSet<String>,
+}
+
+/// A single step of the rendering.
+pub(crate) trait RenderStage: Send {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()>;
+}
+
+/// Renders the spark spec merged onto its templates into the base SKO spec.
+pub(crate) struct TemplateStage {
+    typ: SparkJobType,
+    spark: SparkSpec,
+    merged: SparkSpec,
+    chain: SparkTemplateChain,
+}
+
+impl TemplateStage {
+    pub(crate) fn new(spark: &SparkSpec, typ: &SparkJobType, chain: &SparkTemplateChain) -> Result<Self> {
+        Ok(TemplateStage {
+            typ: typ.clone(),
+            spark: spark.clone(),
+            merged: chain.merge(spark)?,
+            chain: chain.clone(),
+        })
+    }
+
+    /// the spark spec merged onto the templates
+    pub(crate) fn merged(&self) -> &SparkSpec {
+        &self.merged
+    }
+}
+
+impl RenderStage for TemplateStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        self.chain.record_provenance(&self.spark, &self.merged, &mut rendered.report);
+        let SparkSpec {
+            spark_version,
+            mode,
+            proxy_user,
+            image,
+            image_pull_policy,
+            image_pull_secrets,
+            spark_conf,
+            hadoop_conf,
+            spark_config_map,
+            hadoop_config_map,
+            volumes,
+            driver,
+            executor,
+            deps,
+            restart_policy,
+            node_selector,
+            failure_retries,
+            retry_interval,
+            memory_overhead_factor,
+            monitoring,
+            batch_scheduler,
+            batch_scheduler_options,
+            time_to_live_seconds,
+            spark_uioptions,
+            dynamic_allocation,
+            // resolved by the following stages
+            s3_connection: _,
+            s3_buckets: _,
+            s3_connections: _,
+            catalogs: _,
+            env_sets: _,
+            jdbc_sources: _,
+            event_log: _,
+            kerberos: _,
+            parent_template: _,
+            patch: _,
+            provenance_report: _,
+            security_profile: _,
+        } = self.merged.clone();
+
+        rendered.spec = SKOSparkApplicationSpec {
+            typ: sko_application_type(&self.typ),
+            spark_version,
+            mode,
+            proxy_user,
+            image,
+            image_pull_policy,
+            image_pull_secrets,
+            main_application_file: None,
+            main_class: None,
+            python_verison: None,
+            arguments: None,
+            spark_conf: Some(spark_conf.unwrap_or_default()),
+            hadoop_conf,
+            spark_config_map,
+            hadoop_config_map,
+            volumes,
+            driver: sko_spec_driver(driver)?,
+            executor: Some(executor.unwrap_or_default()),
+            deps: Some(deps.unwrap_or_default()),
+            restart_policy,
+            node_selector,
+            failure_retries,
+            retry_interval,
+            memory_overhead_factor,
+            monitoring,
+            batch_scheduler,
+            batch_scheduler_options,
+            time_to_live_seconds,
+            spark_uioptions,
+            dynamic_allocation,
+        };
+        Ok(())
+    }
+}
+
+/// Moves the scheduling fields of the driver and executor SKO doesn't mirror into the ones it has: the priority
+/// classes into the batch scheduler options, and the topology spread constraints into pod anti-affinities.
+pub(crate) struct SchedulingStage {
+    app_name: String,
+}
+
+impl SchedulingStage {
+    pub(crate) fn new(app_name: &str) -> Self {
+        SchedulingStage {
+            app_name: app_name.to_string(),
+        }
+    }
+
+    /// Adds the preferred pod anti-affinities of the `constraints` of the `role` pods, which spread them like
+    /// the constraints with `ScheduleAnyway`
+    fn spread(
+        &self,
+        role: &str,
+        affinity: &mut Option<Affinity>,
+        constraints: Vec<TopologySpreadConstraint>,
+        report: &mut ProvenanceReport,
+    ) {
+        for constraint in constraints {
+            if constraint.when_unsatisfiable == "DoNotSchedule" {
+                let warning = format!(
+                    "Topology spread of the {role} over [{}] is only preferred, SKO can't enforce DoNotSchedule",
+                    constraint.topology_key
+                );
+                tracing::warn!("{}", warning);
+                report.warnings.push(warning);
+            }
+            let label_selector = constraint.label_selector.unwrap_or_else(|| LabelSelector {
+                match_labels: Some(BTreeMap::from([
+                    (crate::constants::SKO_APP_NAME_LABEL.to_string(), self.app_name.clone()),
+                    (crate::constants::SPARK_ROLE_LABEL.to_string(), role.to_string()),
+                ])),
+                ..LabelSelector::default()
+            });
+            affinity
+                .get_or_insert_with(Default::default)
+                .pod_anti_affinity
+                .get_or_insert_with(Default::default)
+                .preferred_during_scheduling_ignored_during_execution
+                .get_or_insert(vec![])
+                .push(WeightedPodAffinityTerm {
+                    weight: 100,
+                    pod_affinity_term: PodAffinityTerm {
+                        label_selector: Some(label_selector),
+                        topology_key: constraint.topology_key,
+                        ..PodAffinityTerm::default()
+                    },
+                });
+        }
+    }
+}
+
+impl RenderStage for SchedulingStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered { spec, report, .. } = rendered;
+        let executor = spec.executor.get_or_insert_with(Default::default);
+
+        let mut priority_class_name: Option<(&str, String)> = spec
+            .batch_scheduler_options
+            .as_ref()
+            .and_then(|o| o.priority_class_name.clone())
+            .map(|name| ("batchSchedulerOptions", name));
+        let mut roles = vec![];
+        for (role, name) in [
+            ("driver", spec.driver.priority_class_name.take()),
+            ("executor", executor.priority_class_name.take()),
+        ] {
+            let Some(name) = name else { continue };
+            roles.push(role);
+            match &priority_class_name {
+                Some((other, current)) if current != &name => {
+                    return Err(Error::InvalidPriorityClass {
+                        reason: format!(
+                            "[{name}] of the {role} differs from [{current}] of the {other}, \
+                             SKO applies a single one to both the driver and executors"
+                        ),
+                    })
+                }
+                Some(_) => {}
+                None => priority_class_name = Some((role, name)),
+            }
+        }
+        if let Some((_, name)) = priority_class_name {
+            if roles.len() == 1 {
+                let warning = format!(
+                    "Priority class [{name}] of the {} applies to the driver and executors, SKO sets a single one",
+                    roles[0]
+                );
+                tracing::warn!("{}", warning);
+                report.warnings.push(warning);
+            }
+            spec.batch_scheduler_options
+                .get_or_insert_with(Default::default)
+                .priority_class_name = Some(name);
+        }
+
+        if let Some(constraints) = spec.driver.topology_spread_constraints.take() {
+            self.spread("driver", &mut spec.driver.affinity, constraints, report);
+        }
+        if let Some(constraints) = executor.topology_spread_constraints.take() {
+            self.spread("executor", &mut executor.affinity, constraints, report);
+        }
+        Ok(())
+    }
+}
+
+/// Adds the configs, credentials env-vars and secrets of a S3 connection,
+/// and the TLS stores with the JVM options to use them.
+/// The connection of a bucket is rendered into the per-bucket S3A settings.
+pub(crate) struct S3Stage {
+    app_name: String,
+    source: String,
+    bucket: Option<String>,
+    connection: S3ConnectionSpec,
+}
+
+impl S3Stage {
+    pub(crate) fn new(app_name: &str, source: String, connection: S3ConnectionSpec) -> Self {
+        S3Stage {
+            app_name: app_name.to_string(),
+            source,
+            bucket: None,
+            connection,
+        }
+    }
+
+    pub(crate) fn for_bucket(app_name: &str, source: String, bucket: String, connection: S3ConnectionSpec) -> Self {
+        S3Stage {
+            bucket: Some(bucket),
+            ..S3Stage::new(app_name, source, connection)
+        }
+    }
+
+    /// the managed secret of the inline credentials is per bucket
+    fn secret_name(&self) -> String {
+        match &self.bucket {
+            Some(bucket) => s3_credentials_secret_name(&format!("{}-{bucket}", self.app_name)),
+            None => s3_credentials_secret_name(&self.app_name),
+        }
+    }
+}
+
+/// name of the Secret holding the inline S3 credentials of application `app_name`
+pub(crate) fn s3_credentials_secret_name(app_name: &str) -> String {
+    common::utils::repair_resource_name(&format!(
+        "{app_name}{}",
+        crate::constants::S3_CREDENTIALS_SECRET_SUFFIX
+    ))
+}
+
+impl RenderStage for S3Stage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered {
+            spec,
+            report,
+            secrets,
+            s3_tls,
+            ..
+        } = rendered;
+        let configs = match &self.bucket {
+            Some(bucket) => self.connection.bucket_spark_configs(bucket),
+            None => self.connection.spark_configs(),
+        };
+        report.extend_conf(spec.spark_conf.get_or_insert(HashMap::new()), &self.source, configs);
+
+        if let Some(credentials) = &self.connection.credentials {
+            let executor = spec.executor.get_or_insert_with(Default::default);
+            // move the inline keys into the managed secret, rather than the pod spec
+            let mut env_vars = match credentials.managed_secret_data() {
+                Some(data) => {
+                    let secret = self.secret_name();
+                    secrets.insert(secret.clone(), data);
+                    Some(S3Credentials::secret_env_vars(&secret))
+                }
+                None => credentials.env_vars(),
+            };
+            if let (Some(bucket), Some(env_vars)) = (&self.bucket, env_vars.as_mut()) {
+                if credentials.has_keys() {
+                    for env_var in env_vars.iter_mut() {
+                        env_var.name = bucket_env_var_name(&env_var.name, bucket);
+                    }
+                }
+            }
+            // add credentials env-var for driver and executor
+            if let Some(env_vars) = env_vars {
+                for env_var in env_vars {
+                    push_env_var(&mut spec.driver.env, env_var.clone(), report, &self.source);
+                    push_env_var(&mut executor.env, env_var, report, &self.source);
+                }
+            }
+            // add credentials volume and volume mount for driver and executor
+            if let Some(secret) = credentials.secret_mount() {
+                for secrets in [&mut spec.driver.secrets, &mut executor.secrets] {
+                    let secrets = secrets.get_or_insert(vec![]);
+                    if !secrets.iter().any(|s| s.name == secret.name) {
+                        secrets.push(secret.clone());
+                    }
+                }
+            }
+            let provider = match &self.bucket {
+                Some(bucket) => Some(credentials.bucket_spark_configs(bucket)),
+                None => credentials.provider_configs(),
+            };
+            if let Some(provider) = provider {
+                report.extend_conf(spec.spark_conf.get_or_insert(HashMap::new()), &self.source, provider);
+            }
+            if let Some(java_options) = credentials.java_options() {
+                append_java_options(&mut spec.driver.java_options, &java_options);
+                append_java_options(&mut executor.java_options, &java_options);
+            }
+            if let (Some(volume), Some(volume_mount)) =
+                (credentials.token_volume(), credentials.token_volume_mount())
+            {
+                let volumes = spec.volumes.get_or_insert(vec![]);
+                if !volumes.iter().any(|v| v.name == volume.name) {
+                    volumes.push(volume);
+                }
+                for volume_mounts in [&mut spec.driver.volume_mounts, &mut executor.volume_mounts] {
+                    let volume_mounts = volume_mounts.get_or_insert(vec![]);
+                    if !volume_mounts.iter().any(|m| m.name == volume_mount.name) {
+                        volume_mounts.push(volume_mount.clone());
+                    }
+                }
+            }
+        }
+
+        if let Some(tls) = &self.connection.tls {
+            s3_tls.push(&self.source, tls)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the TLS of all the S3 connections, merged into the stores of the JVMs.
+pub(crate) struct S3TlsStage;
+
+impl RenderStage for S3TlsStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered { spec, s3_tls, .. } = rendered;
+        let executor = spec.executor.get_or_insert_with(Default::default);
+        if let Some(java_options) = s3_tls.java_options() {
+            append_java_options(&mut spec.driver.java_options, &java_options);
+            append_java_options(&mut executor.java_options, &java_options);
+        }
+        let volumes = s3_tls.volumes();
+        if !volumes.is_empty() {
+            let spec_volumes = spec.volumes.get_or_insert(vec![]);
+            for volume in volumes {
+                if !spec_volumes.iter().any(|v| v.name == volume.name) {
+                    spec_volumes.push(volume);
+                }
+            }
+        }
+        if let Some((init_container, volume_mount)) = s3_tls.stores(spec.image.as_deref())? {
+            spec.driver.init_containers.get_or_insert(vec![]).push(init_container.clone());
+            spec.driver.volume_mounts.get_or_insert(vec![]).push(volume_mount.clone());
+            executor.init_containers.get_or_insert(vec![]).push(init_container);
+            executor.volume_mounts.get_or_insert(vec![]).push(volume_mount);
+        }
+        Ok(())
+    }
+}
+
+fn append_java_options(java_options: &mut Option<String>, options: &str) {
+    *java_options = Some(match java_options.take() {
+        Some(current) if current.contains(options) => current,
+        Some(current) if !current.is_empty() => format!("{current} {options}"),
+        _ => options.to_string(),
+    });
+}
+
+/// Adds `env_var` unless it's already set, warns if it's set to another value by a former connection
+fn push_env_var(env: &mut Option<Vec<EnvVar>>, env_var: EnvVar, report: &mut ProvenanceReport, source: &str) {
+    let env = env.get_or_insert(vec![]);
+    match env.iter().find(|e| e.name == env_var.name) {
+        Some(current) if *current != env_var => {
+            let warning = format!(
+                "Env-var [{}] of [{source}] is ignored, it's already set to another value",
+                env_var.name
+            );
+            tracing::warn!("{}", warning);
+            report.warnings.push(warning);
+        }
+        Some(_) => {}
+        None => env.push(env_var),
+    }
+}
+
+/// Adds the kerberos configs, and mounts the keytab into the driver.
+pub(crate) struct KerberosStage {
+    source: String,
+    kerberos: KerberosSpec,
+}
+
+impl KerberosStage {
+    pub(crate) fn new(source: String, kerberos: KerberosSpec) -> Self {
+        KerberosStage { source, kerberos }
+    }
+}
+
+impl RenderStage for KerberosStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered { spec, report, .. } = rendered;
+        report.extend_conf(
+            spec.spark_conf.get_or_insert(HashMap::new()),
+            &self.source,
+            self.kerberos.spark_configs(),
+        );
+        let secret = self.kerberos.secret_mount();
+        let secrets = spec.driver.secrets.get_or_insert(vec![]);
+        if !secrets.iter().any(|s| s.name == secret.name) {
+            secrets.push(secret);
+        }
+        Ok(())
+    }
+}
+
+/// Adds the configs and jars of a catalog.
+pub(crate) struct CatalogStage {
+    source: String,
+    catalog: SparkCatalogSpec,
+}
+
+impl CatalogStage {
+    pub(crate) fn new(catalog: SparkCatalogSpec) -> Self {
+        CatalogStage {
+            source: format!("catalog/{}", catalog.name),
+            catalog,
+        }
+    }
+}
+
+impl RenderStage for CatalogStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered {
+            spec,
+            report,
+            redacted_conf,
+            ..
+        } = rendered;
+        let (catalog, runtime) = self.catalog.flattened(&spec.spark_version)?;
+        let configs_from = catalog.spark_configs_from();
+        let mut configs = catalog.spark_configs();
+        configs.retain(|k, _| !configs_from.contains_key(k));
+        let spark_conf = spec.spark_conf.get_or_insert(HashMap::new());
+        report.extend_conf(spark_conf, &self.source, configs);
+        if let Some(runtime) = runtime {
+            // the extensions of all the catalogs are kept
+            report.append_list_conf(spark_conf, &self.source, SPARK_SQL_EXTENSIONS, &runtime.extensions);
+            let packages = Dependencies {
+                packages: Some(runtime.packages),
+                ..Dependencies::default()
+            };
+            report.extend_deps(spec.deps.get_or_insert_with(Default::default), &self.source, packages);
+        }
+        if let Some(jars) = &catalog.jars {
+            let jars = Dependencies {
+                jars: Some(jars.clone()),
+                ..Dependencies::default()
+            };
+            report.extend_deps(spec.deps.get_or_insert_with(Default::default), &self.source, jars);
+        }
+
+        for (key, from) in configs_from {
+            let env_var = from.env_var(&key).ok_or_else(|| Error::InvalidCatalog {
+                name: catalog.name.clone(),
+                reason: format!("either secretKeyRef or configMapKeyRef of [{key}] should be set"),
+            })?;
+            let name = env_var.name.clone();
+            if key.starts_with("spark.hadoop.") {
+                // expanded by the hadoop configuration, in the driver and executors
+                let executor = spec.executor.get_or_insert_with(Default::default);
+                push_env_var(&mut spec.driver.env, env_var.clone(), report, &self.source);
+                push_env_var(&mut executor.env, env_var, report, &self.source);
+                report.extend_conf(
+                    spec.spark_conf.get_or_insert(HashMap::new()),
+                    &self.source,
+                    HashMap::from([(key, format!("${{env.{name}}}"))]),
+                );
+            } else {
+                // spark reads the catalog options as is, so the value is passed as a system property
+                if !is_system_property_key(&key) {
+                    return Err(Error::InvalidCatalog {
+                        name: catalog.name.clone(),
+                        reason: format!("[{key}] can't be passed as a system property of the driver"),
+                    });
+                }
+                if from.is_secret() {
+                    redacted_conf.insert(key.clone());
+                }
+                push_env_var(&mut spec.driver.env, env_var, report, &self.source);
+                append_system_property(&mut spec.driver.env, report, &self.source, &key, &name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// whether the config `key` can be passed as a system property, which the launcher reads unquoted
+fn is_system_property_key(key: &str) -> bool {
+    key.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+/// Passes the config `key` as a system property of the driver, which kubernetes expands from the env-var `name`
+/// defined before it. The launcher takes the single-quoted value as is.
+fn append_system_property(
+    env: &mut Option<Vec<EnvVar>>,
+    report: &mut ProvenanceReport,
+    source: &str,
+    key: &str,
+    name: &str,
+) {
+    append_submit_opts(env, &format!("-D{key}='$({name})'"));
+    report.record_conf(key, &format!("$({name})"), source);
+}
+
+/// Append the java `options` to `SPARK_SUBMIT_OPTS` of the driver, which is kept as the last env-var
+/// to reference all the former ones
+fn append_submit_opts(env: &mut Option<Vec<EnvVar>>, options: &str) {
+    let env = env.get_or_insert(vec![]);
+    let mut submit_opts = match env.iter().position(|e| e.name == SPARK_SUBMIT_OPTS_ENV) {
+        Some(i) => env.remove(i),
+        None => EnvVar {
+            name: SPARK_SUBMIT_OPTS_ENV.to_string(),
+            ..EnvVar::default()
+        },
+    };
+    append_java_options(&mut submit_opts.value, options);
+    env.push(submit_opts);
+}
+
+/// Adds the configs, driver jars and credentials env-vars of a JDBC source.
+pub(crate) struct JdbcSourceStage {
+    source: String,
+    jdbc_source: SparkJdbcSourceSpec,
+}
+
+impl JdbcSourceStage {
+    pub(crate) fn new(jdbc_source: SparkJdbcSourceSpec) -> Self {
+        JdbcSourceStage {
+            source: format!("jdbcSource/{}", jdbc_source.name),
+            jdbc_source,
+        }
+    }
+}
+
+impl RenderStage for JdbcSourceStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered {
+            spec,
+            report,
+            redacted_conf,
+            ..
+        } = rendered;
+        report.extend_conf(
+            spec.spark_conf.get_or_insert(HashMap::new()),
+            &self.source,
+            self.jdbc_source.spark_configs(),
+        );
+        if let Some(jars) = &self.jdbc_source.jars {
+            let jars = Dependencies {
+                jars: Some(jars.clone()),
+                ..Dependencies::default()
+            };
+            report.extend_deps(spec.deps.get_or_insert_with(Default::default), &self.source, jars);
+        }
+        if let Some(env_vars) = self.jdbc_source.env_vars() {
+            let executor = spec.executor.get_or_insert_with(Default::default);
+            for env_var in env_vars {
+                push_env_var(&mut spec.driver.env, env_var.clone(), report, &self.source);
+                push_env_var(&mut executor.env, env_var, report, &self.source);
+            }
+            // the catalog options are read as is, so the credentials are passed as system properties like
+            // the ones of the catalogs
+            if self.jdbc_source.catalog_enabled() {
+                let catalog = format!("spark.sql.catalog.{}", self.jdbc_source.name);
+                if !is_system_property_key(&catalog) {
+                    return Err(Error::InvalidJdbcSource {
+                        name: self.jdbc_source.name.clone(),
+                        reason: format!("[{catalog}] can't be passed as a system property of the driver"),
+                    });
+                }
+                for (option, name) in [("user", "USER"), ("password", "PASSWORD")] {
+                    let key = format!("{catalog}.{option}");
+                    let name = self.jdbc_source.env_var_name(name);
+                    append_system_property(&mut spec.driver.env, report, &self.source, &key, &name);
+                    redacted_conf.insert(key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enables the event logs into a directory, such as the one of a history server.
+pub(crate) struct EventLogStage {
+    source: String,
+    dir: String,
+}
+
+impl EventLogStage {
+    pub(crate) fn new(source: String, dir: String) -> Self {
+        EventLogStage { source, dir }
+    }
+}
+
+impl RenderStage for EventLogStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered { spec, report, .. } = rendered;
+        report.extend_conf(
+            spec.spark_conf.get_or_insert(HashMap::new()),
+            &self.source,
+            HashMap::from([
+                ("spark.eventLog.enabled".to_string(), "true".to_string()),
+                ("spark.eventLog.dir".to_string(), self.dir.clone()),
+            ]),
+        );
+        Ok(())
+    }
+}
+
+/// Adds the configs and deps of an env set.
+pub(crate) struct EnvSetStage {
+    source: String,
+    env_set: SparkEnvSetSpec,
+}
+
+impl EnvSetStage {
+    pub(crate) fn new(source: String, env_set: SparkEnvSetSpec) -> Self {
+        EnvSetStage { source, env_set }
+    }
+}
+
+impl RenderStage for EnvSetStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered { spec, report, .. } = rendered;
+        report.extend_conf(
+            spec.spark_conf.get_or_insert(HashMap::new()),
+            &self.source,
+            self.env_set.configs.clone().unwrap_or_default(),
+        );
+        report.extend_deps(
+            spec.deps.get_or_insert_with(Default::default),
+            &self.source,
+            self.env_set.deps.clone().unwrap_or_default(),
+        );
+        Ok(())
+    }
+}
+
+/// Fills the security contexts of the driver and executor pods by the profile,
+/// the containers' one is the default of their sidecars and init-containers too.
+pub(crate) struct SecurityContextStage {
+    profile: Option<SecurityProfile>,
+}
+
+impl SecurityContextStage {
+    pub(crate) fn new(profile: Option<SecurityProfile>) -> Self {
+        SecurityContextStage { profile }
+    }
+
+    fn restricted(&self) -> bool {
+        self.profile == Some(SecurityProfile::Restricted)
+    }
+
+    fn secure_pod<'a>(
+        &self,
+        pod_security_context: &mut Option<PodSecurityContext>,
+        security_context: &mut Option<SecurityContext>,
+        volume_mounts: &mut Option<Vec<VolumeMount>>,
+        containers: impl Iterator<Item = &'a mut Container>,
+    ) -> Result<()> {
+        if self.restricted() {
+            *pod_security_context = Some(with_defaults(
+                pod_security_context.take(),
+                restricted_pod_security_context(),
+            )?);
+            *security_context = Some(with_defaults(security_context.take(), restricted_security_context())?);
+            add_scratch_mount(volume_mounts);
+        }
+        for container in containers {
+            if let Some(defaults) = security_context.clone() {
+                container.security_context = Some(with_defaults(container.security_context.take(), defaults)?);
+            }
+            if self.restricted() {
+                add_scratch_mount(&mut container.volume_mounts);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RenderStage for SecurityContextStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let spec = &mut rendered.spec;
+        let driver = &mut spec.driver;
+        self.secure_pod(
+            &mut driver.pod_security_context,
+            &mut driver.security_context,
+            &mut driver.volume_mounts,
+            driver
+                .sidecars
+                .iter_mut()
+                .flatten()
+                .chain(driver.init_containers.iter_mut().flatten()),
+        )?;
+        if let Some(executor) = spec.executor.as_mut() {
+            self.secure_pod(
+                &mut executor.pod_security_context,
+                &mut executor.security_context,
+                &mut executor.volume_mounts,
+                executor
+                    .sidecars
+                    .iter_mut()
+                    .flatten()
+                    .chain(executor.init_containers.iter_mut().flatten()),
+            )?;
+        }
+
+        // the scratch volume keeps /tmp writable with a read-only root filesystem
+        if self.restricted() {
+            let volumes = spec.volumes.get_or_insert(vec![]);
+            if !volumes.iter().any(|v| v.name == crate::constants::SCRATCH_VOLUME_NAME) {
+                volumes.push(Volume {
+                    name: crate::constants::SCRATCH_VOLUME_NAME.to_string(),
+                    empty_dir: Some(EmptyDirVolumeSource::default()),
+                    ..Volume::default()
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn restricted_pod_security_context() -> PodSecurityContext {
+    PodSecurityContext {
+        run_as_non_root: Some(true),
+        run_as_user: Some(crate::constants::SPARK_UID),
+        run_as_group: Some(crate::constants::SPARK_UID),
+        fs_group: Some(crate::constants::SPARK_UID),
+        seccomp_profile: Some(runtime_default_seccomp_profile()),
+        ..PodSecurityContext::default()
+    }
+}
+
+fn restricted_security_context() -> SecurityContext {
+    SecurityContext {
+        allow_privilege_escalation: Some(false),
+        capabilities: Some(Capabilities {
+            add: None,
+            drop: Some(vec!["ALL".to_string()]),
+        }),
+        read_only_root_filesystem: Some(true),
+        run_as_non_root: Some(true),
+        run_as_user: Some(crate::constants::SPARK_UID),
+        seccomp_profile: Some(runtime_default_seccomp_profile()),
+        ..SecurityContext::default()
+    }
+}
+
+fn runtime_default_seccomp_profile() -> SeccompProfile {
+    SeccompProfile {
+        type_: crate::constants::SECCOMP_PROFILE_RUNTIME_DEFAULT.to_string(),
+        localhost_profile: None,
+    }
+}
+
+/// Mount the scratch volume, unless the scratch dir is mounted already
+fn add_scratch_mount(volume_mounts: &mut Option<Vec<VolumeMount>>) {
+    let volume_mounts = volume_mounts.get_or_insert(vec![]);
+    if !volume_mounts
+        .iter()
+        .any(|m| m.mount_path == crate::constants::SCRATCH_DIR_NAME)
+    {
+        volume_mounts.push(VolumeMount {
+            name: crate::constants::SCRATCH_VOLUME_NAME.to_string(),
+            mount_path: crate::constants::SCRATCH_DIR_NAME.to_string(),
+            ..VolumeMount::default()
+        });
+    }
+}
+
+/// Fill the unset fields of `value` from `defaults`
+fn with_defaults<T: Serialize + DeserializeOwned>(value: Option<T>, defaults: T) -> Result<T> {
+    let mut merged =
+        serde_json::to_value(defaults).map_err(|e| Error::FailedSerializeObjectToJson { internal: e })?;
+    if let Some(value) = value {
+        let value = serde_json::to_value(value).map_err(|e| Error::FailedSerializeObjectToJson { internal: e })?;
+        crate::merge::merge_spark_spec(&mut merged, &value);
+    }
+    serde_json::from_value(merged).map_err(|e| Error::FailedDeserializeObjectFromJson { internal: e })
+}
+
+/// Translates the GPU and extended resources into the spark resource scheduling configs,
+/// and warns about the amounts of the executors inconsistent with their cores.
+pub(crate) struct ResourceStage;
+
+impl RenderStage for ResourceStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered { spec, report, .. } = rendered;
+        let mut configs = HashMap::new();
+        let driver = &mut spec.driver;
+        for resource in requested_resources(driver.gpu.as_ref(), driver.extended_resources.take()) {
+            configs.extend(resource_configs("spark.driver", &resource)?);
+        }
+        if let Some(executor) = spec.executor.as_mut() {
+            let cores = executor.cores.unwrap_or(1).max(1);
+            for resource in requested_resources(executor.gpu.as_ref(), executor.extended_resources.take()) {
+                configs.extend(resource_configs("spark.executor", &resource)?);
+                configs.insert(
+                    format!("spark.task.resource.{}.amount", resource.name),
+                    task_amount(&resource, cores, report)?.to_string(),
+                );
+            }
+        }
+        report.extend_conf(
+            spec.spark_conf.get_or_insert(HashMap::new()),
+            PROVENANCE_SOURCE_RESOURCES,
+            configs,
+        );
+        Ok(())
+    }
+}
+
+/// The extended resources with the GPU, an explicit `gpu` resource wins over the GPU
+fn requested_resources(gpu: Option<&GPUSpec>, resources: Option<Vec<ExtendedResource>>) -> Vec<ExtendedResource> {
+    let mut resources = resources.unwrap_or_default();
+    if let Some(gpu) = gpu {
+        if !resources
+            .iter()
+            .any(|r| r.name == crate::constants::SPARK_GPU_RESOURCE_NAME)
+        {
+            resources.push(gpu.extended_resource());
+        }
+    }
+    resources
+}
+
+fn resource_configs(prefix: &str, resource: &ExtendedResource) -> Result<HashMap<String, String>> {
+    if resource.amount <= 0 {
+        return Err(invalid_resource_amount(resource, "the amount should be positive".to_string()));
+    }
+    let key = |field: &str| format!("{prefix}.resource.{}.{field}", resource.name);
+    let mut configs = HashMap::from([
+        (key("amount"), resource.amount.to_string()),
+        (key("vendor"), resource.vendor.clone()),
+    ]);
+    if let Some(script) = &resource.discovery_script {
+        configs.insert(key("discoveryScript"), script.clone());
+    }
+    Ok(configs)
+}
+
+/// The amount of the resource each task requires, which should run at least one task on an executor.
+/// The tasks it can run on an executor other than the cores waste either the resource or the cores, as spark does,
+/// which is warned about.
+fn task_amount(resource: &ExtendedResource, cores: i32, report: &mut ProvenanceReport) -> Result<f64> {
+    // by default, the resource is shared evenly by the tasks of the cores
+    let task_amount = resource.task_amount.unwrap_or_else(|| {
+        if resource.amount >= cores as i64 {
+            (resource.amount / cores as i64) as f64
+        } else {
+            1.0 / (cores as f64 / resource.amount as f64).ceil()
+        }
+    });
+    // the same as spark, a fraction <= 0.5 shares one resource by multiple tasks
+    let slots = if task_amount >= 1.0 && task_amount.fract() == 0.0 {
+        resource.amount / task_amount as i64
+    } else if task_amount > 0.0 && task_amount <= 0.5 {
+        resource.amount * (1.0 / task_amount + 1e-9).floor() as i64
+    } else {
+        return Err(invalid_resource_amount(
+            resource,
+            format!("the task amount {task_amount} should be either <= 0.5 or a whole number"),
+        ));
+    };
+    if slots == 0 {
+        return Err(invalid_resource_amount(
+            resource,
+            format!("the amount {} runs no task of amount {task_amount} per executor", resource.amount),
+        ));
+    }
+    if slots != cores as i64 {
+        let warning = format!(
+            "Resource [{}] of amount {} runs {slots} tasks of amount {task_amount} per executor, \
+             but the executor has {cores} cores",
+            resource.name, resource.amount
+        );
+        tracing::warn!("{}", warning);
+        report.warnings.push(warning);
+    }
+    Ok(task_amount)
+}
+
+fn invalid_resource_amount(resource: &ExtendedResource, reason: String) -> Error {
+    Error::InvalidResourceAmount {
+        name: resource.name.clone(),
+        reason,
+    }
+}
+
+/// Defaults the redaction of the spark configs in the UI and logs,
+/// and warns about the configs holding plaintext secrets.
+pub(crate) struct RedactionStage;
+
+impl RedactionStage {
+    /// the config looks like a secret by its name, and isn't a reference like `${env:SECRET}`
+    fn is_plaintext_secret(key: &str, value: &str) -> bool {
+        let key = key.to_lowercase();
+        let name = key.rsplit('.').next().unwrap_or(&key);
+        let secret_name = ["secret", "password", "token"].iter().any(|s| name.contains(s))
+            || key.ends_with("access.key")
+            || key.ends_with("secret.key");
+        secret_name && !value.is_empty() && !value.starts_with("${")
+    }
+}
+
+impl RenderStage for RedactionStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let Rendered {
+            spec,
+            report,
+            redacted_conf,
+            ..
+        } = rendered;
+        let conf = spec.spark_conf.get_or_insert(HashMap::new());
+        if !conf.contains_key(crate::constants::SPARK_REDACTION_REGEX) {
+            report.extend_conf(
+                conf,
+                PROVENANCE_SOURCE_REDACTION,
+                HashMap::from([(
+                    crate::constants::SPARK_REDACTION_REGEX.to_string(),
+                    crate::constants::SPARK_REDACTION_REGEX_DEFAULT.to_string(),
+                )]),
+            );
+        }
+        if !redacted_conf.is_empty() {
+            // the system properties from Secrets may not look like secrets by their name
+            let keys = redacted_conf
+                .iter()
+                .map(|key| key.replace('.', "[.]"))
+                .collect::<Vec<String>>();
+            let regex = format!("{}|^(?:{})$", conf[crate::constants::SPARK_REDACTION_REGEX], keys.join("|"));
+            report.amend_conf(conf, PROVENANCE_SOURCE_REDACTION, crate::constants::SPARK_REDACTION_REGEX, regex);
+        }
+
+        let mut plaintext = conf
+            .iter()
+            .filter(|(key, value)| Self::is_plaintext_secret(key, value))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<String>>();
+        plaintext.sort();
+        for key in plaintext {
+            let source = report
+                .spark_conf
+                .get(&key)
+                .map(|p| p.source.clone())
+                .unwrap_or(PROVENANCE_SOURCE_SPEC.to_string());
+            let warning = format!(
+                "Spark config [{key}] set by [{source}] holds a plaintext secret, reference it from a Secret instead"
+            );
+            tracing::warn!("{}", warning);
+            report.warnings.push(warning);
+        }
+        Ok(())
+    }
+}
+
+/// Fills the main application file, class and arguments by the job type.
+pub(crate) struct JobTypeStage {
+    app_name: String,
+    job: JobSpec,
+}
+
+impl JobTypeStage {
+    pub(crate) fn new(app_name: String, job: &JobSpec) -> Self {
+        JobTypeStage {
+            app_name,
+            job: job.clone(),
+        }
+    }
+}
+
+impl RenderStage for JobTypeStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        self.job.populate_sko_fields(&self.app_name, &mut rendered.spec)
+    }
+}
+
+/// Adds the configs, secrets, env-vars and volumes of an authentication or TLS of the hive server2 into the driver
+fn apply_hs2_security(spec: &mut SKOSparkApplicationSpec, report: &mut ProvenanceReport, security: HiveServer2Security) {
+    report.extend_conf(
+        spec.spark_conf.get_or_insert(HashMap::new()),
+        PROVENANCE_SOURCE_SESSION,
+        security.spark_configs,
+    );
+    let secrets = spec.driver.secrets.get_or_insert(vec![]);
+    for secret in security.secrets {
+        if !secrets.iter().any(|s| s.name == secret.name) {
+            secrets.push(secret);
+        }
+    }
+    for env_var in security.env {
+        push_env_var(&mut spec.driver.env, env_var, report, PROVENANCE_SOURCE_SESSION);
+    }
+    spec.volumes.get_or_insert(vec![]).extend(security.volumes);
+    spec.driver.volume_mounts.get_or_insert(vec![]).extend(security.volume_mounts);
+    spec.driver.init_containers.get_or_insert(vec![]).extend(security.init_containers);
+}
+
+/// Runs the driver as a hive server2, with its ports and configs.
+pub(crate) struct SessionStage {
+    session: SessionSpec,
+}
+
+impl SessionStage {
+    pub(crate) fn new(session: &SessionSpec) -> Self {
+        SessionStage {
+            session: session.clone(),
+        }
+    }
+
+    fn hs2_configs(&self, thrift_port: i32, ui_port: i32) -> HashMap<String, String> {
+        // TODO: more details
+        let mut configs = HashMap::from([
+            (
+                crate::constants::SPARK_HIVE_SERVER2_WEBUI_HOST.to_string(),
+                "0.0.0.0".to_string(),
+            ),
+            (
+                crate::constants::SPARK_HIVE_SERVER2_WEBUI_PORT.to_string(),
+                ui_port.to_string(),
+            ),
+            (
+                crate::constants::SPARK_HIVE_SERVER2_THRIFT_BIND_HOST.to_string(),
+                "0.0.0.0".to_string(),
+            ),
+            (
+                crate::constants::SPARK_HIVE_SERVER2_THRIFT_PORT.to_string(),
+                thrift_port.to_string(),
+            ),
+            (
+                crate::constants::SPARK_HIVE_SERVER2_ENABLE_DOAS.to_string(),
+                self.session.do_as.unwrap_or(false).to_string(),
+            ),
+        ]);
+        // otherwise the mode is set along with the other configs of the authentication
+        if self.session.authentication.is_none() {
+            configs.insert(
+                crate::hive_server2::SPARK_HIVE_SERVER2_AUTHENTICATION.to_string(),
+                "NONE".to_string(),
+            );
+        }
+        configs
+    }
+}
+
+impl RenderStage for SessionStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let thrift_conf = self.session.hive_server2_thrift_options.clone().unwrap_or_default();
+        let ui_conf = self.session.hive_server2_ui_options.clone().unwrap_or_default();
+        let thrift_port = thrift_conf.container_port_or(crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT);
+        let ui_port = ui_conf.container_port_or(crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT);
+        let hs2_thrift_port = Port {
+            name: thrift_conf
+                .service_port_name
+                .unwrap_or("hs2-thrift-port".to_string()),
+            protocol: "TCP".to_string(),
+            container_port: thrift_port,
+        };
+        let hs2_ui_port = Port {
+            name: ui_conf.service_port_name.unwrap_or("hs2-ui-port".to_string()),
+            protocol: "TCP".to_string(),
+            container_port: ui_port,
+        };
+
+        let Rendered { spec, report, .. } = rendered;
+        spec.main_application_file = Some(crate::constants::SPARK_MAIN_APPLICATION_FILE.to_owned());
+        spec.main_class = Some(crate::constants::SPARK_SESSION_MAIN_CLASS.to_owned());
+
+        // add ports
+        let driver_ports = spec.driver.ports.get_or_insert(vec![]);
+        driver_ports.extend(vec![hs2_thrift_port, hs2_ui_port]);
+
+        // add spark configs
+        let conf = spec.spark_conf.get_or_insert(HashMap::new());
+        report.extend_conf(conf, PROVENANCE_SOURCE_SESSION, self.hs2_configs(thrift_port, ui_port));
+
+        if let Some(authentication) = &self.session.authentication {
+            if matches!(authentication, HiveServer2Authentication::Kerberos(_))
+                && !conf.contains_key(crate::kerberos::SPARK_KERBEROS_KRB5_CONFIG_MAP)
+            {
+                return Err(Error::InvalidSessionAuthentication {
+                    reason: "the kerberos of the spark spec is required for the krb5.conf".to_string(),
+                });
+            }
+            apply_hs2_security(spec, report, authentication.security());
+        }
+        if let Some(tls) = &thrift_conf.tls {
+            let image = spec.image.as_deref().ok_or(Error::MissingObjectKey { key: "image" })?;
+            let security = tls.security(image);
+            apply_hs2_security(spec, report, security);
+        }
+
+        let conf = spec.spark_conf.get_or_insert(HashMap::new());
+        // renew the delegation tokens of the long-running session eagerly, unless configured
+        if conf.contains_key(crate::kerberos::SPARK_KERBEROS_PRINCIPAL) {
+            let renewal = [
+                (crate::kerberos::SPARK_CREDENTIALS_RENEWAL_RATIO, "0.5"),
+                (crate::kerberos::SPARK_CREDENTIALS_RETRY_WAIT, "10m"),
+            ]
+            .into_iter()
+            .filter(|(key, _)| !conf.contains_key(*key))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+            report.extend_conf(conf, PROVENANCE_SOURCE_SESSION, renewal);
+        }
+        Ok(())
+    }
+}
+
+/// Rejects the pod template files spark-submit can't read. SKO runs spark-submit in its own pod, which reads both
+/// template files, so they're only supported as the remote URIs of the `podTemplateFile` configs.
+pub(crate) struct PodTemplateStage;
+
+impl PodTemplateStage {
+    pub(crate) fn new() -> Self {
+        PodTemplateStage
+    }
+}
+
+/// whether spark-submit fetches the file of `uri` instead of reading it from its local filesystem
+fn is_remote_uri(uri: &str) -> bool {
+    uri.split_once("://")
+        .is_some_and(|(scheme, _)| !matches!(scheme, "file" | "local"))
+}
+
+impl RenderStage for PodTemplateStage {
+    fn apply(&self, rendered: &mut Rendered) -> Result<()> {
+        let spec = &rendered.spec;
+        for key in [
+            crate::constants::SPARK_DRIVER_POD_TEMPLATE_FILE,
+            crate::constants::SPARK_EXECUTOR_POD_TEMPLATE_FILE,
+        ] {
+            match spec.spark_conf.as_ref().and_then(|c| c.get(key)) {
+                Some(uri) if !is_remote_uri(uri) => {
+                    return Err(Error::UnsupportedPodTemplate {
+                        reason: format!(
+                            "[{key}] must be a URI spark-submit fetches, such as s3a:// or https://, \
+                             the local file [{uri}] isn't in the spark-on-k8s-operator pod"
+                        ),
+                    })
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The stages to render a SKO spec, built by [`RenderPipeline::resolve`].
+pub(crate) struct RenderPipeline {
+    stages: Vec<Box<dyn RenderStage>>,
+    /// the security context, resource and redaction stages, which run after all the other stages
+    /// to cover the containers and configs they add
+    final_stages: Vec<Box<dyn RenderStage>>,
+}
+
+impl RenderPipeline {
+    /// Resolve the template, S3 connection, catalogs, env sets and security context stages of `spark`.
+    pub(crate) async fn resolve<L: ResourceLookup>(
+        app_name: &str,
+        spark: &SparkSpec,
+        typ: &SparkJobType,
+        chain: &SparkTemplateChain,
+        lookup: &L,
+    ) -> Result<Self> {
+        let template = TemplateStage::new(spark, typ, chain)?;
+        let merged = template.merged().clone();
+        let mut stages: Vec<Box<dyn RenderStage>> =
+            vec![Box::new(template), Box::new(SchedulingStage::new(app_name))];
+
+        if let Some(s3_connection) = merged.s3_connection {
+            let (source, connection) = match s3_connection {
+                S3ConnectionDef::Inline(inline) => ("s3Connection/inline".to_string(), *inline),
+                S3ConnectionDef::Reference(name) => (
+                    format!("s3Connection/{name}"),
+                    lookup.s3_connection(&name).await?,
+                ),
+            };
+            stages.push(Box::new(S3Stage::new(app_name, source, connection)));
+        }
+
+        for bucket in merged.s3_buckets.unwrap_or_default() {
+            let (bucket_name, bucket) = match bucket {
+                S3BucketDef::Inline(inline) => (
+                    inline.bucket_name.clone().ok_or(Error::MissingObjectKey { key: "bucketName" })?,
+                    inline,
+                ),
+                S3BucketDef::Reference(name) => {
+                    let bucket = lookup.s3_bucket(&name).await?;
+                    (bucket.bucket_name.clone().unwrap_or(name), bucket)
+                }
+            };
+            // a bucket without its own connection goes through the default one
+            let connection = match bucket.connection {
+                Some(S3ConnectionDef::Inline(inline)) => *inline,
+                Some(S3ConnectionDef::Reference(name)) => lookup.s3_connection(&name).await?,
+                None => continue,
+            };
+            let source = format!("s3Bucket/{bucket_name}");
+            stages.push(Box::new(S3Stage::for_bucket(app_name, source, bucket_name, connection)));
+        }
+
+        for (i, bucket_connection) in merged.s3_connections.unwrap_or_default().into_iter().enumerate() {
+            let (source, connection) = match bucket_connection.connection {
+                S3ConnectionDef::Inline(inline) => (format!("s3Connection/inline-{i}"), *inline),
+                S3ConnectionDef::Reference(name) => (
+                    format!("s3Connection/{name}"),
+                    lookup.s3_connection(&name).await?,
+                ),
+            };
+            for bucket in bucket_connection.buckets {
+                stages.push(Box::new(S3Stage::for_bucket(
+                    app_name,
+                    source.clone(),
+                    bucket,
+                    connection.clone(),
+                )));
+            }
+        }
+
+        if let Some(kerberos) = merged.kerberos {
+            stages.push(Box::new(KerberosStage::new("kerberos".to_string(), kerberos)));
+        }
+
+        // make the key unique
+        for catalog in merged.catalogs.unwrap_or_default() {
+            let catalog = match catalog {
+                SparkCatalogDef::Inline(inline) => *inline,
+                SparkCatalogDef::Reference(name) => lookup.catalog(&name).await?,
+            };
+            let catalog = match catalog.hive_metastore.clone() {
+                Some(metastore) => catalog.with_metastore_uri(lookup.hive_metastore_uri(&metastore).await?)?,
+                None => catalog,
+            };
+            if let Some(kerberos) = catalog.kerberos.clone() {
+                stages.push(Box::new(KerberosStage::new(format!("catalog/{}", catalog.name), kerberos)));
+            }
+            stages.push(Box::new(CatalogStage::new(catalog)));
+        }
+
+        for jdbc_source in merged.jdbc_sources.unwrap_or_default() {
+            let jdbc_source = match jdbc_source {
+                SparkJdbcSourceDef::Inline(inline) => *inline,
+                SparkJdbcSourceDef::Reference(name) => lookup.jdbc_source(&name).await?,
+            };
+            stages.push(Box::new(JdbcSourceStage::new(jdbc_source)));
+        }
+
+        if let Some(event_log) = merged.event_log.filter(|e| e.enabled()) {
+            let (source, dir) = match (event_log.history_server, event_log.dir) {
+                (_, Some(dir)) => ("eventLog".to_string(), dir),
+                (Some(name), None) => {
+                    let history_server = lookup.history_server(&name).await?;
+                    let source = format!("sparkHistoryServer/{name}");
+                    // the logs are written with the connection the history server reads them with
+                    if let (Some(connection), Some(bucket)) =
+                        (history_server.s3_connection.clone(), history_server.log_bucket())
+                    {
+                        let connection = match connection {
+                            S3ConnectionDef::Inline(inline) => *inline,
+                            S3ConnectionDef::Reference(name) => lookup.s3_connection(&name).await?,
+                        };
+                        stages.push(Box::new(S3Stage::for_bucket(app_name, source.clone(), bucket, connection)));
+                    }
+                    (source, history_server.log_dir)
+                }
+                (None, None) => return Err(Error::MissingObjectKey { key: "eventLog.dir" }),
+            };
+            stages.push(Box::new(EventLogStage::new(source, dir)));
+        }
+
+        for (i, env_set) in merged.env_sets.unwrap_or_default().into_iter().enumerate() {
+            let (source, env_set) = match env_set {
+                SparkEnvSetDef::Inline(inline) => (format!("envSet/inline-{i}"), *inline),
+                SparkEnvSetDef::Reference(name) => {
+                    (format!("envSet/{name}"), lookup.env_set(&name).await?)
+                }
+            };
+            if let Some(kerberos) = env_set.kerberos.clone() {
+                stages.push(Box::new(KerberosStage::new(source.clone(), kerberos)));
+            }
+            stages.push(Box::new(EnvSetStage::new(source, env_set)));
+        }
+
+        let profile = match merged.security_profile {
+            Some(profile) => Some(profile),
+            None => match lookup.namespace_labels().await {
+                Ok(labels) => SecurityProfile::from_namespace_labels(&labels),
+                // the profile of the namespace is a default, not worth failing the render for
+                Err(error) => {
+                    tracing::warn!("Rendering without a security profile, the namespace can't be read: {error}");
+                    None
+                }
+            },
+        };
+        stages.push(Box::new(S3TlsStage));
+        let final_stages: Vec<Box<dyn RenderStage>> = vec![
+            Box::new(SecurityContextStage::new(profile)),
+            Box::new(ResourceStage),
+            Box::new(RedactionStage),
+        ];
+
+        Ok(RenderPipeline { stages, final_stages })
+    }
+
+    /// Adds the `stage` before the final security context, resource and redaction stages.
+    pub(crate) fn with_stage(mut self, stage: impl RenderStage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub(crate) fn render(&self) -> Result<Rendered> {
+        let mut rendered = Rendered::default();
+        for stage in self.stages.iter().chain(&self.final_stages) {
+            stage.apply(&mut rendered)?;
+        }
+        Ok(rendered)
+    }
+}
+
+fn sko_spec_driver(mut driver: DriverSpec) -> Result<DriverSpec> {
+    if driver.service_account.is_none() {
+        driver.service_account = Some(String::from(crate::constants::SKO_DEFAULT_SERVICE_ACCOUNT))
+    }
+    Ok(driver)
+}
+
+fn sko_application_type(typ: &SparkJobType) -> String {
+    match typ {
+        SparkJobType::SqlJob
+        | SparkJobType::SqlFileJob
+        | SparkJobType::JarJob
+        | SparkJobType::JavaJob => crate::constants::SKO_APPLICATION_TYPE_JAVA.to_owned(),
+        SparkJobType::ScalaJob => crate::constants::SKO_APPLICATION_TYPE_SCALA.to_owned(),
+        SparkJobType::PythonJob => crate::constants::SKO_APPLICATION_TYPE_PYTHON.to_owned(),
+        SparkJobType::RJob => crate::constants::SKO_APPLICATION_TYPE_R.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::ResourceExt;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::spark_application::{SparkJob, SparkScheduledJob, SparkSession};
+
+    /// serde_yaml doesn't deserialize the externally tagged enums from maps, go through json like the API server
+    fn from_yaml<T: serde::de::DeserializeOwned>(yaml: &str) -> T {
+        let value: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn spark_spec(yaml: &str) -> SparkSpec {
+        from_yaml(yaml)
+    }
+
+    #[test]
+    fn test_s3_stage_adds_secret_mount() {
+        let connection: S3ConnectionSpec = from_yaml("
+        host: minio
+        port: 9000
+        credentials:
+          secret: s3-connection
+        ");
+        let mut rendered = Rendered::default();
+        S3Stage::new("job", "s3Connection/inline".to_string(), connection)
+            .apply(&mut rendered)
+            .unwrap();
+
+        let secrets = rendered.spec.driver.secrets.unwrap();
+        assert_eq!(secrets[0].name, "s3-connection");
+        assert_eq!(rendered.spec.executor.unwrap().secrets.unwrap().len(), 1);
+        assert!(rendered.spec.spark_conf.unwrap().contains_key("spark.hadoop.fs.s3a.endpoint"));
+    }
+
+    #[test]
+    fn test_s3_stage_manages_inline_credentials() {
+        let connection: S3ConnectionSpec = from_yaml("
+        host: minio
+        credentials:
+          inline:
+            accessKey: access
+            secretKey: secret
+        ");
+        let mut rendered = Rendered::default();
+        S3Stage::new("job", "s3Connection/inline".to_string(), connection)
+            .apply(&mut rendered)
+            .unwrap();
+
+        assert_eq!(rendered.secrets["job-s3-credentials"]["secretKey"], "secret");
+        let env = rendered.spec.driver.env.unwrap();
+        assert!(env.iter().all(|e| e.value.is_none()));
+        let secret_ref = env[0].value_from.as_ref().unwrap().secret_key_ref.as_ref().unwrap();
+        assert_eq!(secret_ref.name, Some("job-s3-credentials".to_string()));
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert!(conf.values().all(|v| v != "access" && v != "secret"));
+    }
+
+    #[test]
+    fn test_s3_stage_web_identity() {
+        let connection: S3ConnectionSpec = from_yaml("
+        host: minio
+        credentials:
+          webIdentity:
+            roleArn: arn:aws:iam::000000000000:role/spark
+            stsRegion: eu-west-1
+        ");
+        let mut rendered = Rendered::default();
+        S3Stage::new("job", "s3Connection/inline".to_string(), connection)
+            .apply(&mut rendered)
+            .unwrap();
+
+        let spec = rendered.spec;
+        assert_eq!(
+            spec.spark_conf.unwrap()["spark.hadoop.fs.s3a.aws.credentials.provider"],
+            "com.amazonaws.auth.WebIdentityTokenCredentialsProvider"
+        );
+        let volumes = spec.volumes.unwrap();
+        let projection = &volumes[0].projected.as_ref().unwrap().sources.as_ref().unwrap()[0];
+        assert_eq!(
+            projection.service_account_token.as_ref().unwrap().audience,
+            Some("sts.amazonaws.com".to_string())
+        );
+        let executor = spec.executor.unwrap();
+        let env = executor.env.unwrap();
+        let value = |name: &str| env.iter().find(|e| e.name == name).and_then(|e| e.value.clone());
+        assert_eq!(
+            value("AWS_WEB_IDENTITY_TOKEN_FILE"),
+            Some("/var/run/secrets/eks.amazonaws.com/serviceaccount/token".to_string())
+        );
+        assert_eq!(value("AWS_ROLE_ARN"), Some("arn:aws:iam::000000000000:role/spark".to_string()));
+        assert_eq!(value("AWS_STS_REGIONAL_ENDPOINTS"), Some("regional".to_string()));
+        assert_eq!(
+            executor.volume_mounts.unwrap()[0].name,
+            crate::constants::AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME
+        );
+        assert!(spec.driver.secrets.is_none());
+    }
+
+    #[test]
+    fn test_s3_stage_instance_profile() {
+        let connection: S3ConnectionSpec = from_yaml("
+        host: minio
+        credentials:
+          instanceProfile:
+            metadataEndpoint: http://ec2-metadata-mock:1338
+        ");
+        let mut rendered = Rendered::default();
+        S3Stage::new("job", "s3Connection/inline".to_string(), connection)
+            .apply(&mut rendered)
+            .unwrap();
+
+        let spec = rendered.spec;
+        assert_eq!(
+            spec.spark_conf.unwrap()["spark.hadoop.fs.s3a.aws.credentials.provider"],
+            "org.apache.hadoop.fs.s3a.auth.IAMInstanceCredentialsProvider"
+        );
+        assert_eq!(
+            spec.driver.java_options,
+            Some("-Dcom.amazonaws.sdk.ec2MetadataServiceEndpointOverride=http://ec2-metadata-mock:1338".to_string())
+        );
+        assert!(spec.driver.env.is_none());
+        assert!(spec.volumes.is_none());
+    }
+
+    #[test]
+    fn test_redaction_stage() {
+        let mut rendered = Rendered::default();
+        rendered.report.extend_conf(
+            rendered.spec.spark_conf.get_or_insert(HashMap::new()),
+            "envSet/e",
+            HashMap::from([
+                ("spark.hadoop.fs.s3a.secret.key".to_string(), "secret".to_string()),
+                ("spark.hadoop.javax.jdo.option.ConnectionPassword".to_string(), "${env:DB_PASSWORD}".to_string()),
+                ("spark.kubernetes.kerberos.tokenSecret.name".to_string(), "tokens".to_string()),
+            ]),
+        );
+        RedactionStage.apply(&mut rendered).unwrap();
+
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(
+            conf[crate::constants::SPARK_REDACTION_REGEX],
+            crate::constants::SPARK_REDACTION_REGEX_DEFAULT
+        );
+        assert_eq!(rendered.report.warnings.len(), 1);
+        assert!(rendered.report.warnings[0].contains("[spark.hadoop.fs.s3a.secret.key] set by [envSet/e]"));
+    }
+
+    #[test]
+    fn test_s3_stage_tls_stores() {
+        let connection: S3ConnectionSpec = from_yaml("
+        host: minio
+        tls:
+          caCert:
+            configMap: minio-ca
+          clientCertSecret: minio-client
+        ");
+        let mut rendered = Rendered::default();
+        rendered.spec.image = Some("spark:3.4.1".to_string());
+        rendered.spec.driver.java_options = Some("-XX:+UseG1GC".to_string());
+        S3Stage::new("job", "s3Connection/inline".to_string(), connection)
+            .apply(&mut rendered)
+            .unwrap();
+        S3TlsStage.apply(&mut rendered).unwrap();
+
+        let spec = rendered.spec;
+        assert!(spec.spark_conf.unwrap()["spark.hadoop.fs.s3a.endpoint"].starts_with("https://"));
+        let java_options = spec.driver.java_options.unwrap();
+        assert!(java_options.starts_with("-XX:+UseG1GC -Djavax.net.ssl.trustStore="));
+        assert!(java_options.contains("-Djavax.net.ssl.keyStore="));
+        let volumes = spec.volumes.unwrap();
+        assert_eq!(volumes.len(), 3);
+        assert_eq!(volumes[1].config_map.as_ref().unwrap().name, Some("minio-ca".to_string()));
+        let executor = spec.executor.unwrap();
+        let init = &executor.init_containers.unwrap()[0];
+        assert_eq!(init.image, Some("spark:3.4.1".to_string()));
+        assert_eq!(init.volume_mounts.as_ref().unwrap().len(), 3);
+        assert_eq!(
+            executor.volume_mounts.unwrap()[0].name,
+            crate::constants::S3_TLS_STORES_VOLUME_NAME
+        );
+    }
+
+    #[test]
+    fn test_s3_stage_tls_without_verification() {
+        let connection: S3ConnectionSpec = from_yaml("
+        host: minio
+        tls:
+          verification: None
+        ");
+        let mut rendered = Rendered::default();
+        S3Stage::new("job", "s3Connection/inline".to_string(), connection)
+            .apply(&mut rendered)
+            .unwrap();
+        S3TlsStage.apply(&mut rendered).unwrap();
+
+        assert_eq!(
+            rendered.spec.executor.unwrap().java_options,
+            Some("-Dcom.amazonaws.sdk.disableCertChecking=true".to_string())
+        );
+        assert!(rendered.spec.driver.init_containers.is_none());
+        assert!(rendered.spec.volumes.is_none());
+    }
+
+    #[test]
+    fn test_s3_tls_stage_merges_connections() {
+        let cloud: S3ConnectionSpec = from_yaml("
+        host: s3.amazonaws.com
+        tls: {}
+        ");
+        let minio: S3ConnectionSpec = from_yaml("
+        host: minio
+        tls:
+          caCert:
+            secret: minio-ca
+          caCertKey: bundle.pem
+        ");
+        let internal: S3ConnectionSpec = from_yaml("
+        host: internal
+        tls:
+          caCert:
+            configMap: internal-ca
+        ");
+        let mut rendered = Rendered::default();
+        rendered.spec.image = Some("spark:3.4.1".to_string());
+        let connections = [
+            ("s3Connection/cloud", cloud),
+            ("s3Connection/minio", minio),
+            ("s3Connection/internal", internal),
+        ];
+        for (source, connection) in connections {
+            S3Stage::new("job", source.to_string(), connection).apply(&mut rendered).unwrap();
+        }
+        S3TlsStage.apply(&mut rendered).unwrap();
+
+        let spec = rendered.spec.clone();
+        assert!(spec.driver.java_options.unwrap().contains("-Djavax.net.ssl.trustStore="));
+        let volumes = spec.volumes.unwrap();
+        assert_eq!(
+            volumes.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(),
+            vec!["s3-tls-stores", "s3-tls-ca-0", "s3-tls-ca-1"]
+        );
+        let init_containers = spec.driver.init_containers.unwrap();
+        assert_eq!(init_containers.len(), 1);
+        let script = &init_containers[0].command.as_ref().unwrap()[2];
+        assert!(script.contains("/bytenative/tls/s3/ca-0/bundle.pem"));
+        assert!(script.contains("/bytenative/tls/s3/ca-1/ca.crt"));
+
+        // the certificate checking can't be skipped for some connections only
+        let unverified: S3ConnectionSpec = from_yaml("
+        host: minio
+        tls:
+          verification: None
+        ");
+        let error = S3Stage::new("job", "s3Connection/minio".to_string(), unverified)
+            .apply(&mut rendered)
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidS3Tls { .. }));
+    }
+
+    #[test]
+    fn test_catalog_and_env_set_stages() {
+        let catalog: SparkCatalogSpec = from_yaml("
+        name: cat
+        implClass: org.apache.iceberg.spark.SparkCatalog
+        jars:
+        - a.jar
+        catalogConfigs:
+          type: hadoop
+        ");
+        let env_set: SparkEnvSetSpec = from_yaml("
+        configs:
+          spark.a: a
+        deps:
+          jars:
+          - a.jar
+          - b.jar
+        ");
+        let mut rendered = Rendered::default();
+        CatalogStage::new(catalog).apply(&mut rendered).unwrap();
+        EnvSetStage::new("envSet/e".to_string(), env_set)
+            .apply(&mut rendered)
+            .unwrap();
+
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf.get("spark.sql.catalog.cat.type"), Some(&"hadoop".to_string()));
+        assert_eq!(conf.get("spark.a"), Some(&"a".to_string()));
+        assert_eq!(rendered.report.deps["a.jar"], vec!["catalog/cat", "envSet/e"]);
+        assert_eq!(rendered.report.spark_conf["spark.a"].source, "envSet/e");
+    }
+
+    #[test]
+    fn test_typed_catalog_stages() {
+        let mut rendered = Rendered::default();
+        rendered.spec.spark_version = "3.4.1".to_string();
+        for catalog in [
+            "
+            name: ib_rest_cat
+            kind:
+              iceberg:
+                backend: rest
+                uri: http://iceberg-rest:8181
+            catalogConfigs:
+              io-impl: org.apache.iceberg.aws.s3.S3FileIO
+            ",
+            "
+            name: spark_catalog
+            kind:
+              delta: {}
+            ",
+        ] {
+            CatalogStage::new(from_yaml(catalog)).apply(&mut rendered).unwrap();
+        }
+
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf["spark.sql.catalog.ib_rest_cat"], "org.apache.iceberg.spark.SparkCatalog");
+        assert_eq!(conf["spark.sql.catalog.ib_rest_cat.type"], "rest");
+        assert_eq!(conf["spark.sql.catalog.ib_rest_cat.io-impl"], "org.apache.iceberg.aws.s3.S3FileIO");
+        assert_eq!(conf["spark.sql.catalog.spark_catalog"], "org.apache.spark.sql.delta.catalog.DeltaCatalog");
+        assert_eq!(
+            conf[SPARK_SQL_EXTENSIONS],
+            "org.apache.iceberg.spark.extensions.IcebergSparkSessionExtensions,io.delta.sql.DeltaSparkSessionExtension"
+        );
+        assert_eq!(
+            rendered.spec.deps.unwrap().packages.unwrap(),
+            vec![
+                "org.apache.iceberg:iceberg-spark-runtime-3.4_2.12:1.4.3",
+                "io.delta:delta-core_2.12:2.4.0"
+            ]
+        );
+        assert!(rendered.report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_catalog_configs_from_secrets() {
+        let catalog: SparkCatalogSpec = from_yaml("
+        name: rest
+        kind:
+          iceberg:
+            backend: rest
+            uri: https://iceberg-rest:8181
+        catalogConfigs:
+          token: plaintext
+        catalogConfigsFrom:
+          token:
+            secretKeyRef:
+              name: rest-catalog
+              key: token
+        sparkConfigsFrom:
+          spark.hadoop.javax.jdo.option.ConnectionPassword:
+            configMapKeyRef:
+              name: metastore-db
+              key: password
+        ");
+        let mut rendered = Rendered::default();
+        rendered.spec.spark_version = "3.4.1".to_string();
+        rendered.spec.driver.env = Some(vec![EnvVar {
+            name: SPARK_SUBMIT_OPTS_ENV.to_string(),
+            value: Some("-Dlog4j.debug".to_string()),
+            ..EnvVar::default()
+        }]);
+        CatalogStage::new(catalog).apply(&mut rendered).unwrap();
+        RedactionStage.apply(&mut rendered).unwrap();
+
+        let spec = rendered.spec;
+        let conf = spec.spark_conf.unwrap();
+        assert!(!conf.contains_key("spark.sql.catalog.rest.token"));
+        assert_eq!(
+            conf["spark.hadoop.javax.jdo.option.ConnectionPassword"],
+            "${env.SPARK_CONF_SPARK_HADOOP_JAVAX_JDO_OPTION_CONNECTIONPASSWORD}"
+        );
+        let env = spec.driver.env.unwrap();
+        assert_eq!(
+            env.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+            vec![
+                "SPARK_CONF_SPARK_HADOOP_JAVAX_JDO_OPTION_CONNECTIONPASSWORD",
+                "SPARK_CONF_SPARK_SQL_CATALOG_REST_TOKEN",
+                SPARK_SUBMIT_OPTS_ENV,
+            ]
+        );
+        assert_eq!(
+            env[2].value.as_deref(),
+            Some("-Dlog4j.debug -Dspark.sql.catalog.rest.token='$(SPARK_CONF_SPARK_SQL_CATALOG_REST_TOKEN)'")
+        );
+        assert_eq!(
+            conf[crate::constants::SPARK_REDACTION_REGEX],
+            format!(
+                "{}|^(?:spark[.]sql[.]catalog[.]rest[.]token)$",
+                crate::constants::SPARK_REDACTION_REGEX_DEFAULT
+            )
+        );
+        // only the hadoop config is needed by the executors
+        assert_eq!(spec.executor.unwrap().env.unwrap().len(), 1);
+        assert!(rendered.report.warnings.is_empty());
+        assert_eq!(rendered.report.spark_conf["spark.sql.catalog.rest.token"].source, "catalog/rest");
+
+        // the keys of the system properties aren't quoted
+        let catalog: SparkCatalogSpec = from_yaml("
+        name: rest
+        kind:
+          iceberg:
+            backend: rest
+            uri: https://iceberg-rest:8181
+        catalogConfigsFrom:
+          header.X-Auth token:
+            secretKeyRef:
+              name: rest-catalog
+              key: token
+        ");
+        let mut rendered = Rendered::default();
+        rendered.spec.spark_version = "3.4.1".to_string();
+        let error = CatalogStage::new(catalog).apply(&mut rendered).unwrap_err();
+        assert!(matches!(error, Error::InvalidCatalog { .. }));
+    }
+
+    #[test]
+    fn test_jdbc_source_stage() {
+        let jdbc_source: SparkJdbcSourceSpec = from_yaml("
+        name: sales
+        url: jdbc:postgresql://postgres:5432/sales
+        driver: org.postgresql.Driver
+        jars: [s3a://spark-deps/public/jars/postgresql-42.6.0.jar]
+        credentials:
+          secret: sales-db
+        catalog: true
+        ");
+        let mut rendered = Rendered::default();
+        JdbcSourceStage::new(jdbc_source).apply(&mut rendered).unwrap();
+
+        let spec = rendered.spec;
+        assert_eq!(
+            rendered.report.deps["s3a://spark-deps/public/jars/postgresql-42.6.0.jar"],
+            vec!["jdbcSource/sales"]
+        );
+        assert_eq!(spec.spark_conf.unwrap()["spark.bytenative.jdbc.sales.url"], "jdbc:postgresql://postgres:5432/sales");
+        assert_eq!(spec.executor.unwrap().env.unwrap()[0].name, "JDBC_SALES_USER");
+        assert!(rendered.report.warnings.is_empty());
+
+        // the catalog authenticates by the credentials Secret too, through the system properties of the driver
+        let env = spec.driver.env.unwrap();
+        assert_eq!(
+            env.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+            vec!["JDBC_SALES_USER", "JDBC_SALES_PASSWORD", SPARK_SUBMIT_OPTS_ENV]
+        );
+        assert_eq!(
+            env[2].value.as_deref(),
+            Some(
+                "-Dspark.sql.catalog.sales.user='$(JDBC_SALES_USER)' \
+                 -Dspark.sql.catalog.sales.password='$(JDBC_SALES_PASSWORD)'"
+            )
+        );
+        assert_eq!(rendered.report.spark_conf["spark.sql.catalog.sales.password"].source, "jdbcSource/sales");
+        assert_eq!(
+            rendered.redacted_conf,
+            BTreeSet::from([
+                "spark.sql.catalog.sales.password".to_string(),
+                "spark.sql.catalog.sales.user".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_session_stage() {
+        let session: SessionSpec = from_yaml("
+        hiveServer2ThriftOptions:
+          servicePortName: thrift
+        ");
+        let mut rendered = Rendered::default();
+        SessionStage::new(&session).apply(&mut rendered).unwrap();
+
+        let ports = rendered.spec.driver.ports.unwrap();
+        assert_eq!(ports[0].name, "thrift");
+        assert_eq!(ports[1].name, "hs2-ui-port");
+        assert_eq!(
+            rendered.spec.main_class,
+            Some(crate::constants::SPARK_SESSION_MAIN_CLASS.to_string())
+        );
+        assert_eq!(
+            rendered.report.spark_conf[crate::constants::SPARK_HIVE_SERVER2_THRIFT_PORT].source,
+            PROVENANCE_SOURCE_SESSION
+        );
+    }
+
+    #[test]
+    fn test_session_ports() {
+        let session: SessionSpec = serde_json::from_value(serde_json::json!({
+            "hiveServer2ThriftOptions": { "containerPort": 10010, "servicePort": 10000, "nodePort": 31000 },
+        }))
+        .unwrap();
+        let mut rendered = Rendered::default();
+        SessionStage::new(&session).apply(&mut rendered).unwrap();
+        let ports = rendered.spec.driver.ports.unwrap();
+        assert_eq!((ports[0].container_port, ports[1].container_port), (10010, 8009));
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf[crate::constants::SPARK_HIVE_SERVER2_THRIFT_PORT], "10010");
+        assert_eq!(conf[crate::constants::SPARK_HIVE_SERVER2_WEBUI_PORT], "8009");
+
+        let thrift = session.hive_server2_thrift_options.unwrap();
+        assert_eq!(thrift.service_port_or(crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT), 10000);
+        // the NodePort of the default NodePort service, none for a ClusterIP service
+        assert_eq!(thrift.node_port(), Some(31000));
+        let ui = crate::spark_application::SparkHiveserver2ServiceConfiguration {
+            service_type: Some(crate::spark_application::ServiceType::ClusterIP),
+            node_port: Some(31001),
+            ..Default::default()
+        };
+        assert_eq!(ui.service_port_or(crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT), 8009);
+        assert_eq!(ui.node_port(), None);
+    }
+
+    #[test]
+    fn test_session_authentication() {
+        let session: SessionSpec = serde_json::from_value(serde_json::json!({
+            "authentication": {
+                "kerberos": { "principal": "hive/_HOST@EXAMPLE.COM", "keytabSecret": "hive-keytab" }
+            },
+            "doAs": true,
+        }))
+        .unwrap();
+        // the krb5.conf comes from the kerberos of the spark spec
+        let mut rendered = Rendered::default();
+        assert!(SessionStage::new(&session).apply(&mut rendered).is_err());
+
+        rendered.spec.spark_conf = Some(HashMap::from([(
+            crate::kerberos::SPARK_KERBEROS_KRB5_CONFIG_MAP.to_string(),
+            "krb5".to_string(),
+        )]));
+        SessionStage::new(&session).apply(&mut rendered).unwrap();
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf[crate::hive_server2::SPARK_HIVE_SERVER2_AUTHENTICATION], "KERBEROS");
+        assert_eq!(
+            conf[crate::hive_server2::SPARK_HIVE_SERVER2_KERBEROS_KEYTAB],
+            "/bytenative/hive-server2/hive-keytab/hive.keytab"
+        );
+        assert_eq!(conf[crate::constants::SPARK_HIVE_SERVER2_ENABLE_DOAS], "true");
+        assert_eq!(rendered.spec.driver.secrets.unwrap()[0].name, "hive-keytab");
+        assert!(rendered.report.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kerberos_of_catalog_for_session() {
+        let lookup = InMemoryLookup {
+            catalogs: BTreeMap::from([(
+                "hive".to_string(),
+                from_yaml("
+                name: hive
+                kerberos:
+                  principal: spark@EXAMPLE.COM
+                  keytabSecret: spark-keytab
+                  krb5ConfigMap: krb5
+                  hiveMetastorePrincipal: hive/_HOST@EXAMPLE.COM
+                  tokenRenewal:
+                    retryWait: 30m
+                "),
+            )]),
+            ..InMemoryLookup::default()
+        };
+        let spark = spark_spec("
+        driver: {}
+        catalogs:
+        - reference: hive
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JavaJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .with_stage(SessionStage::new(&SessionSpec::default()))
+            .render()
+            .unwrap();
+
+        assert_eq!(rendered.spec.driver.secrets.unwrap()[0].name, "spark-keytab");
+        assert!(rendered.spec.executor.unwrap().secrets.is_none());
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf["spark.hadoop.hive.metastore.kerberos.principal"], "hive/_HOST@EXAMPLE.COM");
+        // the session defaults don't overwrite the configured renewal
+        assert_eq!(conf[crate::kerberos::SPARK_CREDENTIALS_RETRY_WAIT], "30m");
+        assert_eq!(conf[crate::kerberos::SPARK_CREDENTIALS_RENEWAL_RATIO], "0.5");
+        assert_eq!(
+            rendered.report.spark_conf[crate::kerberos::SPARK_KERBEROS_PRINCIPAL].source,
+            "catalog/hive"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_resolves_template_references() {
+        let lookup = InMemoryLookup {
+            templates: BTreeMap::from([(
+                "t".to_string(),
+                spark_spec("
+                driver: {}
+                s3Connection:
+                  reference: minio
+                "),
+            )]),
+            s3_connections: BTreeMap::from([(
+                "minio".to_string(),
+                from_yaml("
+                host: minio
+                credentials:
+                  secret: s3-connection
+                "),
+            )]),
+            ..InMemoryLookup::default()
+        };
+        let chain = SparkTemplateChain::resolve(&lookup, Some(&"t".to_string()))
+            .await
+            .unwrap();
+        let rendered = RenderPipeline::resolve("job", &spark_spec("driver: {}"), &SparkJobType::JarJob, &chain, &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+
+        // the secret is mounted for the templated jobs as well
+        assert_eq!(rendered.spec.driver.secrets.unwrap()[0].name, "s3-connection");
+        assert_eq!(
+            rendered.spec.driver.service_account,
+            Some(crate::constants::SKO_DEFAULT_SERVICE_ACCOUNT.to_string())
+        );
+        assert_eq!(
+            rendered.report.spark_conf["spark.hadoop.fs.s3a.endpoint"].source,
+            "s3Connection/minio"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_renders_connections_of_buckets() {
+        let lookup = InMemoryLookup {
+            s3_connections: BTreeMap::from([(
+                "aws".to_string(),
+                from_yaml("
+                host: s3.eu-west-1.amazonaws.com
+                credentials:
+                  secret: aws-s3
+                "),
+            )]),
+            ..InMemoryLookup::default()
+        };
+        let spark = spark_spec("
+        driver: {}
+        s3Connections:
+        - buckets: [raw, archive]
+          connection:
+            reference: aws
+        - buckets: [dwh]
+          connection:
+            inline:
+              host: minio
+              port: 9000
+              accessStyle: Path
+              credentials:
+                secret: minio-s3
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert!(!conf.contains_key("spark.hadoop.fs.s3a.endpoint"));
+        assert_eq!(conf["spark.hadoop.fs.s3a.bucket.archive.endpoint"], "http://s3.eu-west-1.amazonaws.com");
+        assert_eq!(conf["spark.hadoop.fs.s3a.bucket.dwh.endpoint"], "http://minio:9000");
+        assert_eq!(conf["spark.hadoop.fs.s3a.bucket.dwh.path.style.access"], "true");
+        assert_eq!(conf["spark.hadoop.fs.s3a.bucket.dwh.secret.key"], "${env.AWS_SECRET_ACCESS_KEY_DWH}");
+        assert_eq!(rendered.report.spark_conf["spark.hadoop.fs.s3a.bucket.dwh.endpoint"].source, "s3Connection/inline-1");
+
+        // each secret once, at its own path
+        let secrets = rendered.spec.executor.unwrap().secrets.unwrap();
+        assert_eq!(
+            secrets.iter().map(|s| (s.name.as_str(), s.path.as_str())).collect::<Vec<_>>(),
+            vec![("aws-s3", "/bytenative/secrets/aws-s3"), ("minio-s3", "/bytenative/secrets/minio-s3")]
+        );
+        let env = rendered.spec.driver.env.unwrap();
+        let secret_of = |name: &str| {
+            env.iter()
+                .find(|e| e.name == name)
+                .and_then(|e| e.value_from.as_ref()?.secret_key_ref.as_ref()?.name.clone())
+        };
+        assert_eq!(secret_of("AWS_ACCESS_KEY_ID_ARCHIVE"), Some("aws-s3".to_string()));
+        assert_eq!(secret_of("AWS_ACCESS_KEY_ID_DWH"), Some("minio-s3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_renders_bucket_connections() {
+        let lookup = InMemoryLookup {
+            s3_connections: BTreeMap::from([(
+                "aws".to_string(),
+                from_yaml("
+                host: s3.eu-west-1.amazonaws.com
+                credentials:
+                  inline:
+                    accessKey: access
+                    secretKey: secret
+                "),
+            )]),
+            s3_buckets: BTreeMap::from([(
+                "raw".to_string(),
+                from_yaml("
+                bucketName: raw-data
+                connection:
+                  reference: aws
+                "),
+            )]),
+            ..InMemoryLookup::default()
+        };
+        let spark = spark_spec("
+        driver: {}
+        s3Connection:
+          inline:
+            host: minio
+            port: 9000
+            accessStyle: Path
+        s3Buckets:
+        - reference: raw
+        - inline:
+            bucketName: dwh
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf["spark.hadoop.fs.s3a.endpoint"], "http://minio:9000");
+        assert_eq!(conf["spark.hadoop.fs.s3a.bucket.raw-data.endpoint"], "http://s3.eu-west-1.amazonaws.com");
+        assert_eq!(conf["spark.hadoop.fs.s3a.bucket.raw-data.path.style.access"], "false");
+        assert_eq!(
+            conf["spark.hadoop.fs.s3a.bucket.raw-data.access.key"],
+            "${env.AWS_ACCESS_KEY_ID_RAW_DATA}"
+        );
+        // the bucket without a connection goes through the default one
+        assert!(!conf.keys().any(|k| k.starts_with("spark.hadoop.fs.s3a.bucket.dwh.")));
+        assert_eq!(
+            rendered.report.spark_conf["spark.hadoop.fs.s3a.bucket.raw-data.endpoint"].source,
+            "s3Bucket/raw-data"
+        );
+        assert_eq!(rendered.secrets["job-raw-data-s3-credentials"]["accessKey"], "access");
+        let env = rendered.spec.driver.env.unwrap();
+        assert_eq!(env[0].name, "AWS_ACCESS_KEY_ID_RAW_DATA");
+        assert!(rendered.report.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_renders_role_credentials() {
+        let render = |credentials: &str| {
+            let spark = spark_spec(&format!("
+            driver: {{}}
+            executor: {{}}
+            s3Connection:
+              inline:
+                host: s3.eu-west-1.amazonaws.com
+                credentials:
+                  {credentials}
+            "));
+            async move {
+                let lookup = InMemoryLookup::default();
+                RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+                    .await
+                    .unwrap()
+                    .render()
+                    .unwrap()
+            }
+        };
+        let provider = "spark.hadoop.fs.s3a.aws.credentials.provider";
+
+        let rendered = render("webIdentity: {roleArn: 'arn:aws:iam::000000000000:role/spark'}").await;
+        let spec = rendered.spec;
+        assert_eq!(spec.spark_conf.unwrap()[provider], "com.amazonaws.auth.WebIdentityTokenCredentialsProvider");
+        let volumes = spec.volumes.unwrap();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].name, crate::constants::AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME);
+        assert!(volumes[0].projected.is_some());
+        for (env, mounts) in [
+            (spec.driver.env, spec.driver.volume_mounts),
+            (spec.executor.as_ref().unwrap().env.clone(), spec.executor.as_ref().unwrap().volume_mounts.clone()),
+        ] {
+            let env = env.unwrap();
+            let value = |name: &str| env.iter().find(|e| e.name == name).and_then(|e| e.value.clone());
+            assert_eq!(value("AWS_ROLE_ARN"), Some("arn:aws:iam::000000000000:role/spark".to_string()));
+            assert_eq!(
+                value("AWS_WEB_IDENTITY_TOKEN_FILE"),
+                Some("/var/run/secrets/eks.amazonaws.com/serviceaccount/token".to_string())
+            );
+            let mounts = mounts.unwrap();
+            assert_eq!(mounts.len(), 1);
+            assert_eq!(mounts[0].name, crate::constants::AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME);
+            assert_eq!(mounts[0].mount_path, "/var/run/secrets/eks.amazonaws.com/serviceaccount");
+        }
+        assert!(rendered.secrets.is_empty());
+
+        let rendered = render("instanceProfile: {metadataEndpoint: 'http://ec2-metadata-mock:1338'}").await;
+        let spec = rendered.spec;
+        assert_eq!(
+            spec.spark_conf.unwrap()[provider],
+            "org.apache.hadoop.fs.s3a.auth.IAMInstanceCredentialsProvider"
+        );
+        assert!(spec.volumes.is_none());
+        let java_options = "-Dcom.amazonaws.sdk.ec2MetadataServiceEndpointOverride=http://ec2-metadata-mock:1338";
+        assert_eq!(spec.driver.java_options.as_deref(), Some(java_options));
+        let executor = spec.executor.unwrap();
+        assert_eq!(executor.java_options.as_deref(), Some(java_options));
+        assert!(spec.driver.env.is_none() && executor.env.is_none());
+        assert!(spec.driver.volume_mounts.is_none() && executor.volume_mounts.is_none());
+        assert!(rendered.secrets.is_empty());
+    }
+
+    #[test]
+    fn test_scheduling_stage() {
+        let mut rendered = Rendered::default();
+        rendered.spec.driver = from_yaml("
+        priorityClassName: spark
+        ");
+        rendered.spec.executor = Some(from_yaml("
+        instances: 2
+        priorityClassName: spark
+        topologySpreadConstraints:
+        - maxSkew: 1
+          topologyKey: topology.kubernetes.io/zone
+          whenUnsatisfiable: ScheduleAnyway
+        - maxSkew: 1
+          topologyKey: kubernetes.io/hostname
+          whenUnsatisfiable: DoNotSchedule
+          labelSelector:
+            matchLabels:
+              team: data
+        "));
+        SchedulingStage::new("job").apply(&mut rendered).unwrap();
+
+        let spec = &rendered.spec;
+        assert_eq!(
+            spec.batch_scheduler_options.as_ref().unwrap().priority_class_name,
+            Some("spark".to_string())
+        );
+        let executor = spec.executor.as_ref().unwrap();
+        assert!(executor.priority_class_name.is_none());
+        assert!(executor.topology_spread_constraints.is_none());
+        let terms = executor
+            .affinity
+            .clone()
+            .unwrap()
+            .pod_anti_affinity
+            .unwrap()
+            .preferred_during_scheduling_ignored_during_execution
+            .unwrap();
+        assert_eq!(terms.len(), 2);
+        assert_eq!(terms[0].pod_affinity_term.topology_key, "topology.kubernetes.io/zone");
+        assert_eq!(
+            terms[0].pod_affinity_term.label_selector.clone().unwrap().match_labels.unwrap(),
+            BTreeMap::from([
+                ("spark-role".to_string(), "executor".to_string()),
+                ("sparkoperator.k8s.io/app-name".to_string(), "job".to_string()),
+            ])
+        );
+        assert_eq!(
+            terms[1].pod_affinity_term.label_selector.clone().unwrap().match_labels.unwrap()["team"],
+            "data"
+        );
+        assert!(spec.driver.affinity.is_none());
+        assert_eq!(
+            rendered.report.warnings,
+            vec!["Topology spread of the executor over [kubernetes.io/hostname] is only preferred, \
+                  SKO can't enforce DoNotSchedule"]
+        );
+
+        // SKO applies a single priority class to all the pods
+        let mut rendered = Rendered::default();
+        rendered.spec.driver = from_yaml("
+        priorityClassName: spark-driver
+        ");
+        rendered.spec.executor = Some(from_yaml("
+        priorityClassName: spark-executor
+        "));
+        assert!(matches!(
+            SchedulingStage::new("job").apply(&mut rendered),
+            Err(Error::InvalidPriorityClass { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pod_template_stage() {
+        // the template files are resolved by spark-submit in the spark-on-k8s-operator pod
+        let mut rendered = Rendered::default();
+        for (uri, resolvable) in [
+            ("s3a://spark/templates/executor.yaml", true),
+            ("https://templates.example.com/executor.yaml", true),
+            ("/bytenative/pod-templates/executor.yaml", false),
+            ("local:///opt/spark/executor.yaml", false),
+        ] {
+            rendered.spec.spark_conf = Some(HashMap::from([(
+                crate::constants::SPARK_EXECUTOR_POD_TEMPLATE_FILE.to_string(),
+                uri.to_string(),
+            )]));
+            assert_eq!(PodTemplateStage::new().apply(&mut rendered).is_ok(), resolvable, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restricted_profile_from_namespace() {
+        let lookup = InMemoryLookup {
+            namespace_labels: BTreeMap::from([(
+                crate::constants::POD_SECURITY_ENFORCE_LABEL.to_string(),
+                "restricted".to_string(),
+            )]),
+            ..InMemoryLookup::default()
+        };
+        let spark = spark_spec("
+        driver:
+          securityContext:
+            readOnlyRootFilesystem: false
+          sidecars:
+          - name: fluent-bit
+            image: fluent-bit
+          initContainers:
+          - name: init
+            image: busybox
+            securityContext:
+              runAsUser: 2000
+        executor:
+          instances: 1
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+
+        let driver = rendered.spec.driver;
+        let pod_security_context = driver.pod_security_context.unwrap();
+        assert_eq!(pod_security_context.run_as_user, Some(crate::constants::SPARK_UID));
+        assert_eq!(pod_security_context.seccomp_profile.unwrap().type_, "RuntimeDefault");
+        // the explicit fields win over the profile
+        let security_context = driver.security_context.unwrap();
+        assert_eq!(security_context.read_only_root_filesystem, Some(false));
+        assert_eq!(security_context.capabilities.unwrap().drop, Some(vec!["ALL".to_string()]));
+
+        let sidecar = driver.sidecars.unwrap()[0].clone();
+        assert_eq!(sidecar.security_context.unwrap().read_only_root_filesystem, Some(false));
+        assert_eq!(sidecar.volume_mounts.unwrap()[0].mount_path, crate::constants::SCRATCH_DIR_NAME);
+        let init = driver.init_containers.unwrap()[0].clone().security_context.unwrap();
+        assert_eq!(init.run_as_user, Some(2000));
+        assert_eq!(init.allow_privilege_escalation, Some(false));
+
+        let executor = rendered.spec.executor.unwrap();
+        assert_eq!(executor.security_context.unwrap().read_only_root_filesystem, Some(true));
+        assert_eq!(executor.volume_mounts.unwrap()[0].name, crate::constants::SCRATCH_VOLUME_NAME);
+        assert_eq!(rendered.spec.volumes.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_namespace_renders_without_profile() {
+        let lookup = InMemoryLookup {
+            namespace_unreadable: true,
+            ..InMemoryLookup::default()
+        };
+        let spark = spark_spec("
+        driver: {}
+        executor:
+          instances: 1
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+
+        assert!(rendered.spec.driver.pod_security_context.is_none());
+        assert!(rendered.spec.driver.security_context.is_none());
+        assert!(rendered.spec.executor.unwrap().security_context.is_none());
+
+        // an explicit profile doesn't need the namespace
+        let spark = spark_spec("
+        driver: {}
+        securityProfile: restricted
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+        assert!(rendered.spec.driver.pod_security_context.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_privileged_profile_keeps_security_contexts() {
+        let lookup = InMemoryLookup {
+            namespace_labels: BTreeMap::from([(
+                crate::constants::POD_SECURITY_ENFORCE_LABEL.to_string(),
+                "restricted".to_string(),
+            )]),
+            ..InMemoryLookup::default()
+        };
+        // the spec profile overrides the namespace one
+        let spark = spark_spec("
+        securityProfile: privileged
+        driver:
+          securityContext:
+            runAsUser: 0
+          sidecars:
+          - name: fluent-bit
+            image: fluent-bit
+        ");
+        let rendered = RenderPipeline::resolve("job", &spark, &SparkJobType::JarJob, &SparkTemplateChain::default(), &lookup)
+            .await
+            .unwrap()
+            .render()
+            .unwrap();
+
+        let driver = rendered.spec.driver;
+        assert!(driver.pod_security_context.is_none());
+        assert!(driver.volume_mounts.is_none());
+        let sidecar = driver.sidecars.unwrap()[0].clone();
+        assert_eq!(sidecar.security_context.unwrap().run_as_user, Some(0));
+        assert!(rendered.spec.volumes.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restricted_profile_covers_session() {
+        let spark = spark_spec("
+        image: spark:3.4.1
+        securityProfile: restricted
+        driver: {}
+        ");
+        let session: SessionSpec = from_yaml("
+        hiveServer2ThriftOptions:
+          tls: {}
+        ");
+        let rendered = RenderPipeline::resolve(
+            "session",
+            &spark,
+            &SparkJobType::JavaJob,
+            &SparkTemplateChain::default(),
+            &InMemoryLookup::default(),
+        )
+        .await
+        .unwrap()
+        .with_stage(SessionStage::new(&session))
+        .render()
+        .unwrap();
+
+        // the keystore init-container of the session gets the restricted security context too
+        let driver = rendered.spec.driver;
+        let init = driver
+            .init_containers
+            .unwrap()
+            .into_iter()
+            .find(|c| c.name == crate::hive_server2::TLS_SELF_SIGNED_INIT_CONTAINER_NAME)
+            .unwrap();
+        let security_context = init.security_context.unwrap();
+        assert_eq!(security_context.allow_privilege_escalation, Some(false));
+        assert_eq!(security_context.run_as_non_root, Some(true));
+        assert!(init
+            .volume_mounts
+            .unwrap()
+            .iter()
+            .any(|m| m.name == crate::constants::SCRATCH_VOLUME_NAME));
+    }
+
+    #[test]
+    fn test_resource_stage() {
+        let mut rendered = Rendered::default();
+        rendered.spec.driver = from_yaml("
+        gpu:
+          name: nvidia.com/gpu
+          quantity: 1
+        ");
+        rendered.spec.executor = Some(from_yaml("
+        cores: 4
+        gpu:
+          name: nvidia.com/gpu
+          quantity: 1
+        extendedResources:
+        - name: fpga
+          vendor: xilinx.com
+          amount: 2
+          taskAmount: 0.5
+          discoveryScript: /opt/fpga.sh
+        "));
+        ResourceStage.apply(&mut rendered).unwrap();
+
+        let conf = rendered.spec.spark_conf.unwrap();
+        assert_eq!(conf["spark.driver.resource.gpu.amount"], "1");
+        assert_eq!(conf["spark.executor.resource.gpu.vendor"], "nvidia.com");
+        assert_eq!(
+            conf["spark.executor.resource.gpu.discoveryScript"],
+            crate::constants::SPARK_GPU_DISCOVERY_SCRIPT
+        );
+        assert_eq!(conf["spark.task.resource.gpu.amount"], "0.25");
+        assert_eq!(conf["spark.executor.resource.fpga.discoveryScript"], "/opt/fpga.sh");
+        assert_eq!(conf["spark.task.resource.fpga.amount"], "0.5");
+        // the gpu is still requested by SKO, the extended resources are requested by spark
+        let executor = rendered.spec.executor.unwrap();
+        assert_eq!(executor.gpu.unwrap().quantity, 1);
+        assert!(executor.extended_resources.is_none());
+        assert_eq!(rendered.report.spark_conf["spark.task.resource.gpu.amount"].source, "resources");
+    }
+
+    #[test]
+    fn test_resource_stage_inconsistent_with_cores() {
+        let executor = |gpus: &str, cores: &str, task_amount: &str| {
+            let mut rendered = Rendered::default();
+            rendered.spec.executor = Some(from_yaml(&format!("
+            cores: {cores}
+            extendedResources:
+            - name: gpu
+              vendor: nvidia.com
+              amount: {gpus}
+              {task_amount}
+            ")));
+            ResourceStage.apply(&mut rendered).map(|_| rendered)
+        };
+        // one GPU per task on a multi-core executor wastes the cores, which is warned about
+        let rendered = executor("1", "8", "taskAmount: 1").unwrap();
+        assert_eq!(rendered.spec.spark_conf.unwrap()["spark.task.resource.gpu.amount"], "1");
+        assert_eq!(
+            rendered.report.warnings,
+            vec!["Resource [gpu] of amount 1 runs 1 tasks of amount 1 per executor, but the executor has 8 cores"]
+        );
+        let rendered = executor("2", "3", "").unwrap();
+        assert_eq!(rendered.spec.spark_conf.unwrap()["spark.task.resource.gpu.amount"], "0.5");
+        assert_eq!(rendered.report.warnings.len(), 1);
+
+        for (gpus, cores, task_amount) in [("1", "2", "taskAmount: 0.7"), ("1", "2", "taskAmount: 2")] {
+            let err = executor(gpus, cores, task_amount).unwrap_err();
+            assert!(matches!(err, Error::InvalidResourceAmount { .. }), "{err}");
+        }
+    }
+
+    // ---------------------------------------------------------------------
+    // golden files of the examples, re-generate them by `UPDATE_GOLDEN=1 cargo test -p crd golden`
+
+    fn examples_dir() -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../examples")
+    }
+
+    fn golden_dir() -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/golden")
+    }
+
+    fn example_files() -> Vec<std::path::PathBuf> {
+        let mut files = std::fs::read_dir(examples_dir())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "yaml"))
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    fn example_documents(file: &std::path::Path) -> Vec<serde_json::Value> {
+        let content = std::fs::read_to_string(file).unwrap();
+        serde_yaml::Deserializer::from_str(&content)
+            .map(|d| serde_json::Value::deserialize(d).unwrap())
+            .filter(|v| !v.is_null())
+            .collect()
+    }
+
+    fn document_kind(doc: &serde_json::Value) -> &str {
+        doc.get("kind").and_then(|k| k.as_str()).unwrap_or_default()
+    }
+
+    /// all the referable resources from the examples
+    fn examples_lookup() -> InMemoryLookup {
+        let mut lookup = InMemoryLookup::default();
+        for doc in example_files().iter().flat_map(|f| example_documents(f)) {
+            let name = doc["metadata"]["name"].as_str().unwrap_or_default().to_string();
+            let spec = doc.get("spec").cloned().unwrap_or_default();
+            match document_kind(&doc) {
+                "SparkTemplate" => {
+                    lookup.templates.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "S3Connection" => {
+                    lookup.s3_connections.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "S3Bucket" => {
+                    lookup.s3_buckets.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "SparkCatalog" => {
+                    lookup.catalogs.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "SparkEnvSet" => {
+                    lookup.env_sets.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "SparkJdbcSource" => {
+                    lookup.jdbc_sources.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "SparkHistoryServer" => {
+                    lookup.history_servers.insert(name, serde_json::from_value(spec).unwrap());
+                }
+                "HiveMetastore" => {
+                    let metastore: HiveMetastore = serde_json::from_value(doc.clone()).unwrap();
+                    lookup.hive_metastore_uris.insert(name, metastore.uri().unwrap());
+                }
+                _ => {}
+            }
+        }
+        lookup
+    }
+
+    /// sort the keys of the maps, to make the output deterministic
+    fn sorted(value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => {
+                let sorted = map
+                    .into_iter()
+                    .map(|(k, v)| (k, sorted(v)))
+                    .collect::<BTreeMap<String, serde_json::Value>>();
+                serde_json::Value::Object(sorted.into_iter().collect())
+            }
+            serde_json::Value::Array(values) => {
+                serde_json::Value::Array(values.into_iter().map(sorted).collect())
+            }
+            value => value,
+        }
+    }
+
+    async fn render_document(doc: serde_json::Value, lookup: &InMemoryLookup) -> Option<serde_json::Value> {
+        let (kind, name, rendered) = match document_kind(&doc) {
+            "SparkJob" => {
+                let job: SparkJob = serde_json::from_value(doc).unwrap();
+                let chain = SparkTemplateChain::resolve(lookup, job.spec.spark_template.as_ref()).await.unwrap();
+                let rendered = job.sko_spec(lookup, &chain).await.unwrap();
+                ("SparkJob", job.name_any(), rendered)
+            }
+            "SparkScheduledJob" => {
+                let job: SparkScheduledJob = serde_json::from_value(doc).unwrap();
+                let chain = SparkTemplateChain::resolve(lookup, job.spec.spark_template.as_ref()).await.unwrap();
+                let rendered = job.sko_spec(lookup, &chain).await.unwrap();
+                ("SparkScheduledJob", job.name_any(), rendered)
+            }
+            "SparkSession" => {
+                let session: SparkSession = serde_json::from_value(doc).unwrap();
+                let chain = SparkTemplateChain::resolve(lookup, session.spec.spark_template.as_ref()).await.unwrap();
+                let rendered = session.sko_spec(lookup, &chain).await.unwrap();
+                ("SparkSession", session.name_any(), rendered)
+            }
+            _ => return None,
+        };
+        Some(sorted(serde_json::json!({ "kind": kind, "name": name, "spec": rendered.spec })))
+    }
+
+    #[tokio::test]
+    async fn test_golden_examples() {
+        let lookup = examples_lookup();
+        let update = std::env::var("UPDATE_GOLDEN").is_ok();
+        let mut rendered_files = 0;
+        for file in example_files() {
+            let mut output = String::new();
+            for doc in example_documents(&file) {
+                if let Some(rendered) = render_document(doc, &lookup).await {
+                    output.push_str("---\n");
+                    output.push_str(&serde_yaml::to_string(&rendered).unwrap());
+                }
+            }
+            if output.is_empty() {
+                continue;
+            }
+            rendered_files += 1;
+            let golden = golden_dir().join(file.file_name().unwrap());
+            if update {
+                std::fs::create_dir_all(golden_dir()).unwrap();
+                std::fs::write(&golden, &output).unwrap();
+            }
+            let expected = std::fs::read_to_string(&golden)
+                .unwrap_or_else(|_| panic!("missing golden file {golden:?}"));
+            assert_eq!(output, expected, "rendered {file:?} differs from {golden:?}");
+        }
+        assert!(rendered_files > 0);
+    }
+}
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use k8s_openapi::api::core::v1::{
-    KeyToPath, SecretVolumeSource, Volume, EnvVar, EnvVarSource, SecretKeySelector,
+    ConfigMapVolumeSource, Container, EmptyDirVolumeSource, SecretVolumeSource, Volume, VolumeMount,
+    EnvVar, EnvVarSource, ProjectedVolumeSource, SecretKeySelector, ServiceAccountTokenProjection, VolumeProjection,
 };
 use kube::{Api, Client, CustomResource};
 use schemars::JsonSchema;
@@ -77,6 +78,7 @@ impl InlinedS3BucketSpec {
 }
 
 /// Operators are expected to define fields for this type in order to work with S3 buckets.
+/// The name of a referenced bucket defaults to the name of the resource.
 #[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum S3BucketDef {
@@ -103,7 +105,7 @@ impl S3BucketDef {
 #[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum S3ConnectionDef {
-    Inline(S3ConnectionSpec),
+    Inline(Box<S3ConnectionSpec>),
     Reference(String),
 }
 
@@ -111,7 +113,7 @@ impl S3ConnectionDef {
     /// Returns an [S3ConnectionSpec].
     pub async fn resolve(&self, client: &Client, namespace: &str) -> Result<S3ConnectionSpec> {
         match self {
-            S3ConnectionDef::Inline(s3_connection_spec) => Ok(s3_connection_spec.clone()),
+            S3ConnectionDef::Inline(s3_connection_spec) => Ok(*s3_connection_spec.clone()),
             S3ConnectionDef::Reference(s3_conn_reference) => {
                 S3ConnectionSpec::get(s3_conn_reference, client, namespace).await
             }
@@ -125,6 +127,16 @@ impl S3ConnectionDef {
 pub struct InlineS3Credentials {
     pub access_key: String,
     pub secret_key: String,
+    /// Turn the keys into a Secret owned by the application, which the pods reference instead of
+    /// the literal values, defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_secret: Option<bool>,
+}
+
+impl InlineS3Credentials {
+    pub fn managed(&self) -> bool {
+        self.managed_secret.unwrap_or(true)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
@@ -133,9 +145,75 @@ pub enum S3Credentials {
     Secret(String),
     Inline(InlineS3Credentials),
     Anonymous(String),
+    /// Assume a role with the projected service account token, like the IAM roles for service accounts
+    WebIdentity(WebIdentityCredentials),
+    /// The credentials of the node, from the instance metadata service or the default provider chain
+    InstanceProfile(InstanceProfileCredentials),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebIdentityCredentials {
+    /// the ARN of the role to assume, for example: `arn:aws:iam::123456789012:role/spark`
+    pub role_arn: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_name: Option<String>,
+    /// the audience of the projected token, defaults to `sts.amazonaws.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    /// the requested lifetime of the projected token, defaults to 86400
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration_seconds: Option<i64>,
+    /// the region of the STS endpoint. The endpoint itself can't be overridden for the web identity by the
+    /// AWS SDK v1 of hadoop-aws, so unlike the instance profile it can't be pointed to a local STS stand-in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sts_region: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceProfileCredentials {
+    /// Go through the whole default provider chain (env-vars, system properties, profile, web identity,
+    /// instance metadata) rather than the instance metadata only, defaults to false.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_chain: Option<bool>,
+    /// overrides the instance metadata endpoint, for example a local stand-in: `http://ec2-metadata-mock:1338`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_endpoint: Option<String>,
+}
+
+impl WebIdentityCredentials {
+    fn token_file(&self) -> String {
+        format!("{AWS_WEB_IDENTITY_TOKEN_DIR_NAME}/{AWS_WEB_IDENTITY_TOKEN_FILE_NAME}")
+    }
+
+    fn env_vars(&self) -> Vec<EnvVar> {
+        let env_var = |name: &str, value: &str| EnvVar {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+            value_from: None,
+        };
+        let mut env_vars = vec![
+            env_var("AWS_ROLE_ARN", &self.role_arn),
+            env_var("AWS_WEB_IDENTITY_TOKEN_FILE", &self.token_file()),
+        ];
+        if let Some(session_name) = &self.session_name {
+            env_vars.push(env_var("AWS_ROLE_SESSION_NAME", session_name));
+        }
+        if let Some(region) = &self.sts_region {
+            env_vars.push(env_var("AWS_REGION", region));
+            env_vars.push(env_var("AWS_STS_REGIONAL_ENDPOINTS", "regional"));
+        }
+        env_vars
+    }
 }
 
-use crate::constants::{S3_ACCESS_KEY_ID, S3_SECRET_ACCESS_KEY, S3_SECRET_DIR_NAME};
+use crate::constants::{
+    AWS_WEB_IDENTITY_DEFAULT_AUDIENCE, AWS_WEB_IDENTITY_DEFAULT_EXPIRATION_SECONDS, AWS_WEB_IDENTITY_TOKEN_DIR_NAME,
+    AWS_WEB_IDENTITY_TOKEN_FILE_NAME, AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME, S3_ACCESS_KEY_ID, S3_SECRET_ACCESS_KEY, S3_SECRET_DIR_NAME, S3_TLS_CA_VOLUME_NAME, S3_TLS_CLIENT_VOLUME_NAME,
+    S3_TLS_DEFAULT_CA_CERT_KEY, S3_TLS_DIR_NAME, S3_TLS_INIT_CONTAINER_NAME, S3_TLS_KEYSTORE_FILE_NAME,
+    S3_TLS_STORES_VOLUME_NAME, S3_TLS_STORE_PASSWORD, S3_TLS_TRUSTSTORE_FILE_NAME,
+};
 impl S3Credentials {
     pub fn spark_configs(&self) -> HashMap<String, String> {
         match self {
@@ -156,6 +234,13 @@ impl S3Credentials {
                     ),
                 ])
             }
+            // the keys are read from the env-vars referencing the managed secret
+            S3Credentials::Inline(inline) if inline.managed() => {
+                HashMap::from([(
+                    "spark.hadoop.fs.s3a.aws.credentials.provider".to_string(),
+                    "com.amazonaws.auth.EnvironmentVariableCredentialsProvider".to_string(),
+                )])
+            }
             S3Credentials::Inline(inline) => {
                 HashMap::from([
                     (
@@ -173,39 +258,89 @@ impl S3Credentials {
                 ])
             }
             S3Credentials::Anonymous(_) => Self::spark_configs_anonymous(),
+            S3Credentials::WebIdentity(_) | S3Credentials::InstanceProfile(_) => {
+                self.provider_configs().unwrap_or_default()
+            }
+        }
+    }
+
+    /// The credentials provider of the variants needing neither keys nor a secret
+    pub fn provider_configs(&self) -> Option<HashMap<String, String>> {
+        let provider = match self {
+            S3Credentials::WebIdentity(_) => "com.amazonaws.auth.WebIdentityTokenCredentialsProvider",
+            S3Credentials::InstanceProfile(profile) if profile.default_chain.unwrap_or(false) => {
+                "com.amazonaws.auth.DefaultAWSCredentialsProviderChain"
+            }
+            S3Credentials::InstanceProfile(_) => "org.apache.hadoop.fs.s3a.auth.IAMInstanceCredentialsProvider",
+            _ => return None,
+        };
+        Some(HashMap::from([(
+            "spark.hadoop.fs.s3a.aws.credentials.provider".to_string(),
+            provider.to_string(),
+        )]))
+    }
+
+    /// The java options pointing the SDK of the JVMs to the instance metadata stand-in
+    pub fn java_options(&self) -> Option<String> {
+        match self {
+            S3Credentials::InstanceProfile(InstanceProfileCredentials {
+                metadata_endpoint: Some(endpoint),
+                ..
+            }) => Some(format!("-Dcom.amazonaws.sdk.ec2MetadataServiceEndpointOverride={endpoint}")),
+            _ => None,
+        }
+    }
+
+    /// The projected service account token volume of the web identity
+    pub fn token_volume(&self) -> Option<Volume> {
+        let S3Credentials::WebIdentity(web_identity) = self else {
+            return None;
+        };
+        Some(Volume {
+            name: AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME.to_string(),
+            projected: Some(ProjectedVolumeSource {
+                default_mode: Some(0o420),
+                sources: Some(vec![VolumeProjection {
+                    service_account_token: Some(ServiceAccountTokenProjection {
+                        audience: Some(
+                            web_identity
+                                .audience
+                                .clone()
+                                .unwrap_or_else(|| AWS_WEB_IDENTITY_DEFAULT_AUDIENCE.to_string()),
+                        ),
+                        expiration_seconds: Some(
+                            web_identity
+                                .expiration_seconds
+                                .unwrap_or(AWS_WEB_IDENTITY_DEFAULT_EXPIRATION_SECONDS),
+                        ),
+                        path: AWS_WEB_IDENTITY_TOKEN_FILE_NAME.to_string(),
+                    }),
+                    ..VolumeProjection::default()
+                }]),
+            }),
+            ..Volume::default()
+        })
+    }
+
+    pub fn token_volume_mount(&self) -> Option<VolumeMount> {
+        match self {
+            S3Credentials::WebIdentity(_) => Some(VolumeMount {
+                name: AWS_WEB_IDENTITY_TOKEN_VOLUME_NAME.to_string(),
+                mount_path: AWS_WEB_IDENTITY_TOKEN_DIR_NAME.to_string(),
+                read_only: Some(true),
+                ..VolumeMount::default()
+            }),
+            _ => None,
         }
     }
     
     pub fn env_vars(&self) -> Option<Vec<EnvVar>> {
         match self {
-            S3Credentials::Secret(secret) => {
-                Some(vec![
-                    EnvVar {
-                        name: "AWS_ACCESS_KEY_ID".to_string(),
-                        value_from: Some(EnvVarSource { 
-                            secret_key_ref: Some(SecretKeySelector {
-                                key: S3_ACCESS_KEY_ID.to_string(),
-                                name: Some(secret.clone()), 
-                                optional: Some(true) }),
-                            ..Default::default()
-                        }),
-                        value: None
-                    },
-                    EnvVar {
-                        name: "AWS_SECRET_ACCESS_KEY".to_string(),
-                        value_from: Some(EnvVarSource { 
-                            secret_key_ref: Some(SecretKeySelector {
-                                key: S3_SECRET_ACCESS_KEY.to_string(),
-                                name: Some(secret.clone()), 
-                                optional: Some(true) }),
-                            ..Default::default()
-                        }),
-                        value: None
-                    },
-                ])
-            }
+            S3Credentials::Secret(secret) => Some(Self::secret_env_vars(secret)),
+            // referencing the managed secret, see `managed_secret_data`
+            S3Credentials::Inline(inline) if inline.managed() => None,
             S3Credentials::Inline(inline) => {
-                let InlineS3Credentials { access_key, secret_key } = inline.clone();
+                let InlineS3Credentials { access_key, secret_key, .. } = inline.clone();
                 Some(vec![
                     EnvVar {
                         name: "AWS_ACCESS_KEY_ID".to_string(),
@@ -219,7 +354,47 @@ impl S3Credentials {
                     },
                 ])
             }
-            S3Credentials::Anonymous(_) => None
+            S3Credentials::WebIdentity(web_identity) => Some(web_identity.env_vars()),
+            S3Credentials::Anonymous(_) | S3Credentials::InstanceProfile(_) => None
+        }
+    }
+
+    /// The credentials env-vars referencing the keys of the `secret`
+    pub fn secret_env_vars(secret: &str) -> Vec<EnvVar> {
+        vec![
+            EnvVar {
+                name: "AWS_ACCESS_KEY_ID".to_string(),
+                value_from: Some(EnvVarSource { 
+                    secret_key_ref: Some(SecretKeySelector {
+                        key: S3_ACCESS_KEY_ID.to_string(),
+                        name: Some(secret.to_string()), 
+                        optional: Some(true) }),
+                    ..Default::default()
+                }),
+                value: None
+            },
+            EnvVar {
+                name: "AWS_SECRET_ACCESS_KEY".to_string(),
+                value_from: Some(EnvVarSource { 
+                    secret_key_ref: Some(SecretKeySelector {
+                        key: S3_SECRET_ACCESS_KEY.to_string(),
+                        name: Some(secret.to_string()), 
+                        optional: Some(true) }),
+                    ..Default::default()
+                }),
+                value: None
+            },
+        ]
+    }
+
+    /// The data of the Secret managed for the inline credentials
+    pub fn managed_secret_data(&self) -> Option<BTreeMap<String, String>> {
+        match self {
+            S3Credentials::Inline(inline) if inline.managed() => Some(BTreeMap::from([
+                (S3_ACCESS_KEY_ID.to_string(), inline.access_key.clone()),
+                (S3_SECRET_ACCESS_KEY.to_string(), inline.secret_key.clone()),
+            ])),
+            _ => None,
         }
     }
 
@@ -230,37 +405,13 @@ impl S3Credentials {
         )])
     }
 
-    pub fn secret_volume(&self, volume_name: &str) -> Option<Volume> {
-        Some(k8s_openapi::api::core::v1::Volume {
-            name: volume_name.to_string(),
-            // TODO: check make a SecretOperatorVolumeSource
-            secret: Some(SecretVolumeSource {
-                secret_name: Some(volume_name.to_string()),
-                default_mode: Some(0o420),
-                items: Some(vec![
-                    KeyToPath {
-                        key: S3_ACCESS_KEY_ID.to_string(),
-                        path: format!("{S3_SECRET_DIR_NAME}/{S3_ACCESS_KEY_ID}"),
-                        mode: Some(0o420),
-                    },
-                    KeyToPath {
-                        key: S3_SECRET_ACCESS_KEY.to_string(),
-                        path: format!("{S3_SECRET_DIR_NAME}/{S3_SECRET_ACCESS_KEY}"),
-                        mode: Some(0o420),
-                    },
-                ]),
-                optional: Some(true),
-            }),
-            ..Volume::default()
-        })
-    }
-
     pub fn secret_mount(&self) -> Option<crate::spark_application::SecretInfo> {
         match self {
+            // mounted per secret, as the connections of the buckets could have different ones
             S3Credentials::Secret(name) => {
                 Some(crate::spark_application::SecretInfo {
                     name: name.clone(),
-                    path: S3_SECRET_DIR_NAME.to_string(),
+                    path: format!("{S3_SECRET_DIR_NAME}/{name}"),
                     typ: "Generic".to_string(),
                 })
             }
@@ -268,22 +419,67 @@ impl S3Credentials {
         }
     }
 
-    pub fn volume_mounts(&self) -> Option<k8s_openapi::api::core::v1::VolumeMount> {
-        match self {
-            S3Credentials::Secret(secret) => {
-                let secret_dir = format!("{S3_SECRET_DIR_NAME}/{secret}");
-                Some(k8s_openapi::api::core::v1::VolumeMount {
-                    name: secret.to_string(),
-                    mount_path: secret_dir,
-                    read_only: Some(true),
-                    ..k8s_openapi::api::core::v1::VolumeMount::default()
-                })
+    /// The credentials are keys, read from the env-vars by the S3A
+    pub fn has_keys(&self) -> bool {
+        matches!(self, S3Credentials::Secret(_) | S3Credentials::Inline(_))
+    }
+
+    /// The credentials settings of the `bucket` only, the keys are read from the env-vars named by
+    /// [bucket_env_var_name], as the AWS SDK env-vars are shared by all the buckets.
+    pub fn bucket_spark_configs(&self, bucket: &str) -> HashMap<String, String> {
+        let configs = match self {
+            S3Credentials::Secret(_) | S3Credentials::Inline(_) => HashMap::from([
+                (
+                    "spark.hadoop.fs.s3a.aws.credentials.provider".to_string(),
+                    "org.apache.hadoop.fs.s3a.SimpleAWSCredentialsProvider".to_string(),
+                ),
+                (
+                    "spark.hadoop.fs.s3a.access.key".to_string(),
+                    format!("${{env.{}}}", bucket_env_var_name("AWS_ACCESS_KEY_ID", bucket)),
+                ),
+                (
+                    "spark.hadoop.fs.s3a.secret.key".to_string(),
+                    format!("${{env.{}}}", bucket_env_var_name("AWS_SECRET_ACCESS_KEY", bucket)),
+                ),
+            ]),
+            S3Credentials::Anonymous(_) => Self::spark_configs_anonymous(),
+            S3Credentials::WebIdentity(_) | S3Credentials::InstanceProfile(_) => {
+                self.provider_configs().unwrap_or_default()
             }
-            _ => None,
-        }
+        };
+        configs
+            .into_iter()
+            .map(|(k, v)| (bucket_conf_key(&k, bucket), v))
+            .collect()
     }
 }
 
+/// The per-bucket S3A key of the global `key`, for example: `spark.hadoop.fs.s3a.bucket.<bucket>.endpoint`
+pub fn bucket_conf_key(key: &str, bucket: &str) -> String {
+    key.replacen(
+        "spark.hadoop.fs.s3a.",
+        &format!("spark.hadoop.fs.s3a.bucket.{bucket}."),
+        1,
+    )
+}
+
+/// The env-var `name` of the keys of `bucket`, for example: `AWS_ACCESS_KEY_ID_SPARK_DWH`
+pub fn bucket_env_var_name(name: &str, bucket: &str) -> String {
+    let suffix = bucket
+        .to_uppercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    format!("{name}_{suffix}")
+}
+
+/// A S3 connection used by the listed buckets only, rendered into their per-bucket S3A settings.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3BucketConnection {
+    /// the names of the buckets accessed through the connection
+    pub buckets: Vec<String>,
+    pub connection: S3ConnectionDef,
+}
+
 /// S3 connection definition as CRD.
 #[derive(
     CustomResource, Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize,
@@ -310,16 +506,12 @@ pub struct S3ConnectionSpec {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub access_style: Option<S3AccessStyle>,
     /// If the S3 uses authentication you have to specify you S3 credentials.
-    /// In the most cases a SecretClass providing `accessKey` and `secretKey` is sufficient.
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub credentials_inline: Option<InlineS3Credentials>,
+    /// In the most cases a Secret providing `accessKey` and `secretKey` is sufficient.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub credentials: Option<S3Credentials>,
     /// If you want to use TLS when talking to S3 you can enable TLS encrypted communication with this setting.
-    /// the String value is the name of secret or `None` that means no TLS validation
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    // pub tls: Option<Tls>,
-    pub tls: Option<String>,
+    pub tls: Option<S3Tls>,
 }
 
 impl S3ConnectionSpec {
@@ -349,6 +541,41 @@ impl S3ConnectionSpec {
         })
     }
 
+    /// The endpoint and access style of the `bucket` only, the file system implementation stays global
+    pub fn bucket_spark_configs(&self, bucket: &str) -> HashMap<String, String> {
+        self.spark_configs()
+            .into_iter()
+            .filter(|(k, _)| k != "spark.hadoop.fs.s3a.impl")
+            .map(|(k, v)| (bucket_conf_key(&k, bucket), v))
+            .collect()
+    }
+
+    /// The configs of the long-running servers, such as the metastore and history server, whose keys
+    /// are read from the env-vars rather than the secret mounts of the spark pods
+    pub fn server_spark_configs(&self) -> HashMap<String, String> {
+        let mut configs = self.spark_configs();
+        configs.extend(match &self.credentials {
+            Some(credentials) if credentials.has_keys() => HashMap::from([(
+                "spark.hadoop.fs.s3a.aws.credentials.provider".to_string(),
+                "com.amazonaws.auth.EnvironmentVariableCredentialsProvider".to_string(),
+            )]),
+            Some(credentials) => credentials.spark_configs(),
+            None => S3Credentials::spark_configs_anonymous(),
+        });
+        configs
+    }
+
+    /// The credentials env-vars of the servers, the inline keys are referenced from the `managed_secret`
+    pub fn server_env_vars(&self, managed_secret: &str) -> Vec<EnvVar> {
+        match &self.credentials {
+            Some(credentials) if credentials.managed_secret_data().is_some() => {
+                S3Credentials::secret_env_vars(managed_secret)
+            }
+            Some(credentials) => credentials.env_vars().unwrap_or_default(),
+            None => vec![],
+        }
+    }
+
     pub fn spark_configs(&self) -> HashMap<String, String> {
         let ret = HashMap::from([
             (
@@ -375,6 +602,271 @@ impl S3ConnectionSpec {
     }
 }
 
+/// TLS settings of a S3 connection, the CA and client certificates are turned into the
+/// truststore and keystore of the JVMs by an init-container of the driver and executors.
+/// The CA certificates of all the connections of an application are merged into the truststore,
+/// while their verification and client certificate must be the same.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Tls {
+    #[serde(default)]
+    pub verification: TlsVerification,
+    /// The CA certificates to verify the server with, in PEM format.
+    /// Only the JVM default CAs are trusted if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<CaCertSource>,
+    /// The key of the CA certificates in the Secret or ConfigMap, defaults to `ca.crt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert_key: Option<String>,
+    /// The `kubernetes.io/tls` Secret holding the client certificate, for the mutual TLS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert_secret: Option<String>,
+}
+
+#[derive(
+    Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString,
+)]
+pub enum TlsVerification {
+    /// Trust any server certificate, don't use it out of the test environments
+    None,
+    /// Verify the server certificate with the CA certificates
+    #[default]
+    Server,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CaCertSource {
+    Secret(String),
+    ConfigMap(String),
+}
+
+impl S3Tls {
+    fn ca_cert_key(&self) -> &str {
+        self.ca_cert_key.as_deref().unwrap_or(S3_TLS_DEFAULT_CA_CERT_KEY)
+    }
+}
+
+/// The TLS settings of all the S3 connections of an application. The truststore, keystore and the certificate
+/// checking are global to the JVMs, so the CA certificates of every connection are merged into a single truststore.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct S3TlsStores {
+    /// the TLS settings by the source of their connection
+    connections: Vec<(String, S3Tls)>,
+}
+
+impl S3TlsStores {
+    /// Adds the TLS of the connection of `source`. The verification can't be skipped for some connections only,
+    /// and the JVMs present a single client certificate.
+    pub fn push(&mut self, source: &str, tls: &S3Tls) -> Result<()> {
+        if let Some((other, other_tls)) = self.connections.iter().find(|(_, t)| t.verification != tls.verification) {
+            return Err(Error::InvalidS3Tls {
+                reason: format!(
+                    "the verification {} of [{source}] conflicts with the verification {} of [{other}]",
+                    tls.verification, other_tls.verification
+                ),
+            });
+        }
+        if let (Some(secret), Some((other, other_secret))) = (&tls.client_cert_secret, self.client_cert_secret()) {
+            if secret != other_secret {
+                return Err(Error::InvalidS3Tls {
+                    reason: format!(
+                        "the client certificate [{secret}] of [{source}] conflicts with [{other_secret}] of [{other}]"
+                    ),
+                });
+            }
+        }
+        if !self.connections.iter().any(|(_, t)| t == tls) {
+            self.connections.push((source.to_string(), tls.clone()));
+        }
+        Ok(())
+    }
+
+    fn stores_dir() -> String {
+        format!("{S3_TLS_DIR_NAME}/stores")
+    }
+
+    fn verification(&self) -> Option<&TlsVerification> {
+        self.connections.first().map(|(_, t)| &t.verification)
+    }
+
+    /// the distinct CA certificates of the connections, with their keys
+    fn ca_certs(&self) -> Vec<(&CaCertSource, &str)> {
+        let mut ca_certs = vec![];
+        for (_, tls) in &self.connections {
+            if let Some(ca_cert) = &tls.ca_cert {
+                if !ca_certs.contains(&(ca_cert, tls.ca_cert_key())) {
+                    ca_certs.push((ca_cert, tls.ca_cert_key()));
+                }
+            }
+        }
+        ca_certs
+    }
+
+    /// the client certificate Secret, with the source of its connection
+    fn client_cert_secret(&self) -> Option<(&String, &String)> {
+        self.connections
+            .iter()
+            .find_map(|(source, t)| t.client_cert_secret.as_ref().map(|secret| (source, secret)))
+    }
+
+    fn has_stores(&self) -> bool {
+        self.verification() == Some(&TlsVerification::Server)
+            && (!self.ca_certs().is_empty() || self.client_cert_secret().is_some())
+    }
+
+    /// the volume and the mount path of the `i`th CA certificates
+    fn ca_volume_name(i: usize) -> String {
+        format!("{S3_TLS_CA_VOLUME_NAME}-{i}")
+    }
+
+    fn ca_dir(i: usize) -> String {
+        format!("{S3_TLS_DIR_NAME}/ca-{i}")
+    }
+
+    /// The JVM options to use the generated stores, or to skip the verification
+    pub fn java_options(&self) -> Option<String> {
+        if self.verification() == Some(&TlsVerification::None) {
+            // the AWS SDK of S3A skips the certificate checking
+            return Some("-Dcom.amazonaws.sdk.disableCertChecking=true".to_string());
+        }
+        let stores_dir = Self::stores_dir();
+        let mut options = vec![];
+        if !self.ca_certs().is_empty() {
+            options.extend([
+                format!("-Djavax.net.ssl.trustStore={stores_dir}/{S3_TLS_TRUSTSTORE_FILE_NAME}"),
+                format!("-Djavax.net.ssl.trustStorePassword={S3_TLS_STORE_PASSWORD}"),
+                "-Djavax.net.ssl.trustStoreType=PKCS12".to_string(),
+            ]);
+        }
+        if self.client_cert_secret().is_some() {
+            options.extend([
+                format!("-Djavax.net.ssl.keyStore={stores_dir}/{S3_TLS_KEYSTORE_FILE_NAME}"),
+                format!("-Djavax.net.ssl.keyStorePassword={S3_TLS_STORE_PASSWORD}"),
+                "-Djavax.net.ssl.keyStoreType=PKCS12".to_string(),
+            ]);
+        }
+        (!options.is_empty()).then(|| options.join(" "))
+    }
+
+    /// The volumes of the certificates and the generated stores
+    pub fn volumes(&self) -> Vec<Volume> {
+        if !self.has_stores() {
+            return vec![];
+        }
+        let mut volumes = vec![Volume {
+            name: S3_TLS_STORES_VOLUME_NAME.to_string(),
+            empty_dir: Some(EmptyDirVolumeSource::default()),
+            ..Volume::default()
+        }];
+        for (i, (ca_cert, _)) in self.ca_certs().into_iter().enumerate() {
+            volumes.push(match ca_cert {
+                CaCertSource::Secret(name) => Volume {
+                    name: Self::ca_volume_name(i),
+                    secret: Some(SecretVolumeSource {
+                        secret_name: Some(name.clone()),
+                        ..SecretVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                },
+                CaCertSource::ConfigMap(name) => Volume {
+                    name: Self::ca_volume_name(i),
+                    config_map: Some(ConfigMapVolumeSource {
+                        name: Some(name.clone()),
+                        ..ConfigMapVolumeSource::default()
+                    }),
+                    ..Volume::default()
+                },
+            });
+        }
+        if let Some((_, secret)) = self.client_cert_secret() {
+            volumes.push(Volume {
+                name: S3_TLS_CLIENT_VOLUME_NAME.to_string(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(secret.clone()),
+                    ..SecretVolumeSource::default()
+                }),
+                ..Volume::default()
+            });
+        }
+        volumes
+    }
+
+    /// The init-container generating the stores by the `image`, which needs the `keytool`, and the
+    /// `openssl` for the client certificate, with the mount of the stores into the spark containers.
+    /// The `image` is only required when there are stores to generate.
+    pub fn stores(&self, image: Option<&str>) -> Result<Option<(Container, VolumeMount)>> {
+        if !self.has_stores() {
+            return Ok(None);
+        }
+        let image = image.ok_or(Error::MissingObjectKey { key: "image" })?;
+        let volume_mount = VolumeMount {
+            name: S3_TLS_STORES_VOLUME_NAME.to_string(),
+            mount_path: Self::stores_dir(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        };
+        Ok(Some((self.init_container(image), volume_mount)))
+    }
+
+    fn init_container(&self, image: &str) -> Container {
+        let stores_dir = Self::stores_dir();
+        let mut script = vec!["set -e".to_string(), format!("cd {stores_dir}")];
+        let mut volume_mounts = vec![VolumeMount {
+            name: S3_TLS_STORES_VOLUME_NAME.to_string(),
+            mount_path: stores_dir,
+            ..VolumeMount::default()
+        }];
+        let ca_certs = self.ca_certs();
+        if !ca_certs.is_empty() {
+            // trust the JVM default CAs as well
+            script.push(format!(
+                "keytool -importkeystore -noprompt -srckeystore \"$JAVA_HOME/lib/security/cacerts\" \
+                -srcstorepass changeit -destkeystore {S3_TLS_TRUSTSTORE_FILE_NAME} -deststoretype PKCS12 \
+                -deststorepass {S3_TLS_STORE_PASSWORD}"
+            ));
+        }
+        for (i, (_, ca_cert_key)) in ca_certs.iter().enumerate() {
+            // split every bundle into its certificates, to import them all
+            script.push(format!(
+                "awk '/BEGIN CERTIFICATE/{{n++}} {{print > (\"ca-{i}-\" n \".crt\")}}' {}/{ca_cert_key}",
+                Self::ca_dir(i)
+            ));
+            volume_mounts.push(VolumeMount {
+                name: Self::ca_volume_name(i),
+                mount_path: Self::ca_dir(i),
+                read_only: Some(true),
+                ..VolumeMount::default()
+            });
+        }
+        if !ca_certs.is_empty() {
+            script.push(format!(
+                "for cert in ca-*.crt; do keytool -importcert -noprompt -alias \"s3-$cert\" -file \"$cert\" \
+                -keystore {S3_TLS_TRUSTSTORE_FILE_NAME} -storetype PKCS12 -storepass {S3_TLS_STORE_PASSWORD}; done"
+            ));
+        }
+        if self.client_cert_secret().is_some() {
+            script.push(format!(
+                "openssl pkcs12 -export -in {S3_TLS_DIR_NAME}/client/tls.crt -inkey {S3_TLS_DIR_NAME}/client/tls.key \
+                -out {S3_TLS_KEYSTORE_FILE_NAME} -passout pass:{S3_TLS_STORE_PASSWORD}"
+            ));
+            volume_mounts.push(VolumeMount {
+                name: S3_TLS_CLIENT_VOLUME_NAME.to_string(),
+                mount_path: format!("{S3_TLS_DIR_NAME}/client"),
+                read_only: Some(true),
+                ..VolumeMount::default()
+            });
+        }
+        Container {
+            name: S3_TLS_INIT_CONTAINER_NAME.to_string(),
+            image: Some(image.to_string()),
+            command: Some(vec!["/bin/sh".to_string(), "-c".to_string(), script.join("\n")]),
+            volume_mounts: Some(volume_mounts),
+            ..Container::default()
+        }
+    }
+}
+
 #[derive(
     Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString,
 )]
@@ -398,14 +890,13 @@ mod test {
     fn test_ser_inline() {
         let bucket = S3BucketSpec {
             bucket_name: Some("test-bucket-name".to_owned()),
-            connection: Some(S3ConnectionDef::Inline(S3ConnectionSpec {
+            connection: Some(S3ConnectionDef::Inline(Box::new(S3ConnectionSpec {
                 host: Some("host".to_owned()),
                 port: Some(8080),
                 credentials: None,
                 access_style: Some(S3AccessStyle::VirtualHosted),
                 tls: None,
-                ..Default::default()
-            })),
+            }))),
         };
 
         let actual_yaml = serde_yaml::to_string(&bucket).expect("Yaml serialization Bucket");
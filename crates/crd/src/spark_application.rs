@@ -3,10 +3,10 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::ops::Deref;
 
-use k8s_openapi::api::core::v1::{ConfigMap, Service, EnvVar, EnvFromSource};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret, Service, EnvVar, EnvFromSource};
 use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
 use kube::core::ObjectMeta;
-use kube::{Client, CustomResource, ResourceExt, Api};
+use kube::{Client, CustomResource, ResourceExt};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_valid::Validate;
@@ -14,16 +14,18 @@ use crate::metadata::ObjectMetaBuilder;
 use crate::metadata::ObjectLabels;
 use strum::{Display, EnumString};
 
-use crate::s3::{S3ConnectionDef, S3ConnectionSpec};
+use crate::provenance::{self, ProvenanceReport, PROVENANCE_SOURCE_SPEC};
+use crate::render::{
+    JobTypeStage, KubeLookup, PodTemplateStage, RenderPipeline, Rendered, ResourceLookup,
+    SessionStage,
+};
 use crate::sko_spark_application::{
     SKOScheduledSparkApplicationSpec, SKOSparkApplicationSpec,
     ScheduledSparkApplication as SKOScheduledSparkApplication,
     SparkApplication as SKOSparkApplication,
 };
 use crate::{Error, Result};
-use crate::{
-    SparkApplicationStatus, SparkCatalogDef, SparkCatalogSpec, SparkEnvSetDef, SparkEnvSetSpec,
-};
+use crate::SparkApplicationStatus;
 
 #[derive(Clone, CustomResource, Default, Debug, Deserialize, JsonSchema, Serialize)]
 #[kube(
@@ -36,7 +38,8 @@ use crate::{
 )]
 #[serde(rename_all = "camelCase")]
 pub struct SparkSpec {
-    #[serde(default)]
+    // skipped when empty, so that it is inherited from the templates
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub spark_version: String,
 
     // Mode is the deployment mode of the Spark application.
@@ -70,7 +73,8 @@ pub struct SparkSpec {
     // Volumes
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub volumes: Option<Vec<k8s_openapi::api::core::v1::Volume>>,
-    // Driver DriverSpec
+    // Driver DriverSpec, defaulted so that it can be inherited from the templates
+    #[serde(default)]
     pub driver: DriverSpec,
     // Executor ExecutorSpec
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -111,36 +115,191 @@ pub struct SparkSpec {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub s3_connection: Option<crate::s3::S3ConnectionDef>,
 
+    /// The buckets accessed through another connection than `s3Connection`,
+    /// rendered into the per-bucket S3A settings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_buckets: Option<Vec<crate::s3::S3BucketDef>>,
+
+    /// The connections mapped to the buckets, for a job accessing several S3 endpoints,
+    /// for example copying data between a cloud bucket and an on-prem MinIO.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_connections: Option<Vec<crate::s3::S3BucketConnection>>,
+
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub catalogs: Option<Vec<crate::SparkCatalogDef>>,
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub env_sets: Option<Vec<crate::SparkEnvSetDef>>,
+
+    /// The JDBC sources used by the application, referenced like the catalogs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jdbc_sources: Option<Vec<crate::jdbc::SparkJdbcSourceDef>>,
+
+    /// The event logs of the application, written into the directory of a `SparkHistoryServer`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_log: Option<crate::history_server::EventLogSpec>,
+
+    /// Kerberos authentication to the secured HDFS and Hive metastore.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kerberos: Option<crate::kerberos::KerberosSpec>,
+
+    /// ParentTemplate is the name of the `SparkTemplate` this template inherits from,
+    /// only used when the spec belongs to a `SparkTemplate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_template: Option<String>,
+
+    /// Patch directives applied when merging this spec onto its template, keyed by the field path.
+    /// for example: `volumes: replace` or `driver.sidecars[istio-proxy]: delete`
+    #[serde(default, rename = "$patch", skip_serializing_if = "Option::is_none")]
+    pub patch: Option<BTreeMap<String, crate::merge::PatchDirective>>,
+
+    /// ProvenanceReport enables a ConfigMap `<name>-provenance` next to the application,
+    /// recording which source set each effective spark config and dependency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance_report: Option<bool>,
+
+    /// SecurityProfile fills the security contexts of the driver, executors and all their containers,
+    /// defaults to the profile labeled on the namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_profile: Option<SecurityProfile>,
 }
 
 impl SparkSpec {
+    /// merge the spec onto the full chain of the template and its parent templates
     pub async fn merge_template_opt(&self, client: &Client, namespace: &str, template: Option<&String>) -> Result<Self> {
-        if let Some(template) = template {
-            let st_api = Api::<SparkTemplate>::namespaced(client.clone(), namespace);
-            let st = st_api.get(template)
-                .await.map_err(|_| {
-                    Error::MissingSparkTemplate { name: template.to_string() }
-                })?;
-            let mut st_value = serde_json::to_value::<SparkSpec>(st.spec)
+        SparkTemplateChain::resolve(&KubeLookup::new(client, namespace), template)
+            .await?
+            .merge(self)
+    }
+}
+
+/// The resolved inheritance chain of a `SparkTemplate`,
+/// ordered from the template referenced by the application up to its root ancestor.
+#[derive(Clone, Debug, Default)]
+pub struct SparkTemplateChain {
+    templates: Vec<(String, SparkSpec)>,
+}
+
+impl SparkTemplateChain {
+    /// Resolve the chain by following `parentTemplate` from `template`
+    pub(crate) async fn resolve<L: ResourceLookup>(lookup: &L, template: Option<&String>) -> Result<Self> {
+        let mut chain = SparkTemplateChain::default();
+        let mut next = template.cloned();
+        while let Some(name) = next {
+            let spec = lookup.spark_template(&name).await?;
+            next = spec.parent_template.clone();
+            chain.push(name, spec)?;
+        }
+        Ok(chain)
+    }
+
+    /// Append the parent of the last template,
+    /// fails if the template is already in the chain or the chain grows too deep.
+    pub fn push(&mut self, name: String, spec: SparkSpec) -> Result<()> {
+        if self.templates.iter().any(|(n, _)| n == &name) {
+            let mut names = self.names();
+            names.push(name);
+            return Err(Error::SparkTemplateCycle { chain: names.join(" -> ") });
+        }
+        if self.templates.len() >= crate::constants::SPARK_TEMPLATE_MAX_DEPTH {
+            return Err(Error::SparkTemplateChainTooDeep {
+                name: self.names().first().cloned().unwrap_or(name),
+                max_depth: crate::constants::SPARK_TEMPLATE_MAX_DEPTH,
+            });
+        }
+        self.templates.push((name, spec));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.templates.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    /// Merge `spec` onto the chain strategically, starting from the root ancestor, and `spec` wins at last
+    pub fn merge(&self, spec: &SparkSpec) -> Result<SparkSpec> {
+        if self.is_empty() {
+            return Ok(spec.clone());
+        }
+        let mut merged = serde_json::Value::Object(serde_json::Map::new());
+        for template in self.templates.iter().map(|(_, t)| t).rev().chain([spec]) {
+            let value = serde_json::to_value::<&SparkSpec>(template)
                 .map_err(|e| {
                     Error::FailedSerializeObjectToJson { internal: e }
                 })?;
-            let self_value = serde_json::to_value::<SparkSpec>(self.clone())
-            .map_err(|e| {
-                Error::FailedDeserializeObjectFromJson { internal: e }
-            })?;
-            json_patch::merge(&mut st_value, &self_value);
-            Ok(serde_json::from_value::<SparkSpec>(st_value)
+            crate::merge::merge_spark_spec(&mut merged, &value);
+        }
+        let mut merged = serde_json::from_value::<SparkSpec>(merged)
             .map_err(|e| {
                 Error::FailedMergeObjects { internal: e }
-            })?)
-        } else {
-            Ok(self.clone())
+            })?;
+        // the chain is fully resolved at this point
+        merged.parent_template = None;
+        Ok(merged)
+    }
+
+    /// Record the sources of the spark configs and deps in `merged`,
+    /// which is merged from `spec` onto this chain.
+    /// The configs are recorded from the root ancestor to the spec, so the values overridden by a child template
+    /// or the spec are recorded as overwritten, with a warning if they're different.
+    pub fn record_provenance(
+        &self,
+        spec: &SparkSpec,
+        merged: &SparkSpec,
+        report: &mut ProvenanceReport,
+    ) {
+        let layers = [(PROVENANCE_SOURCE_SPEC.to_string(), spec)]
+            .into_iter()
+            .chain(self.templates.iter().map(|(n, t)| (provenance::template_source(n), t)))
+            .collect::<Vec<(String, &SparkSpec)>>();
+
+        // sorted to make the report deterministic
+        let conf = merged
+            .spark_conf
+            .iter()
+            .flatten()
+            .collect::<BTreeMap<&String, &String>>();
+        for (source, layer) in layers.iter().rev() {
+            let layer_conf = layer
+                .spark_conf
+                .iter()
+                .flatten()
+                .collect::<BTreeMap<&String, &String>>();
+            // the keys deleted by a later layer aren't effective
+            for (key, value) in layer_conf.into_iter().filter(|(k, _)| conf.contains_key(k)) {
+                report.record_conf(key, value, source);
+            }
+        }
+        for (key, value) in conf {
+            if report.spark_conf.get(key.as_str()).map(|c| &c.value) != Some(value) {
+                report.record_conf(key, value, PROVENANCE_SOURCE_SPEC);
+            }
+        }
+
+        if let Some(deps) = merged.deps.as_ref() {
+            for dep in deps.iter() {
+                let sources = layers
+                    .iter()
+                    .filter(|(_, l)| l.deps.as_ref().is_some_and(|d| d.iter().any(|d| d == dep)))
+                    .map(|(s, _)| s.as_str())
+                    .collect::<Vec<&str>>();
+                for source in sources {
+                    report.record_dep(dep, source);
+                }
+            }
+        }
+    }
+
+    /// record the chain in the annotations of the generated resource
+    pub fn annotate(&self, metadata: &mut ObjectMeta) {
+        if !self.is_empty() {
+            metadata.annotations.get_or_insert_with(BTreeMap::new).insert(
+                crate::constants::SPARK_TEMPLATE_CHAIN_ANNOTATION.to_string(),
+                self.names().join(","),
+            );
         }
     }
 }
@@ -210,8 +369,8 @@ pub struct DriverSpec {
     pub memory_overhead: Option<String>,
     /// GPU specifies GPU requirement for the pod.
     /// +optional
-    // #[serde(default, rename = "gpu", skip_serializing_if = "Option::is_none")]
-    // GPU *GPUSpec `json:"gpu,omitempty"`
+    #[serde(default, rename = "gpu", skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<GPUSpec>,
     /// Image is the container image to use. Overrides Spec.Image if set.
     /// +optional
     // #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -219,15 +378,15 @@ pub struct DriverSpec {
     /// ConfigMaps carries information of other ConfigMaps to add to the pod.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    config_maps: Option<Vec<NamePath>>,
+    pub(crate) config_maps: Option<Vec<NamePath>>,
     /// Secrets carries information of secrets to add to the pod.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    secrets: Option<Vec<SecretInfo>>,
+    pub(crate) secrets: Option<Vec<SecretInfo>>,
     /// Env carries the environment variables to add to the pod.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    env: Option<Vec<EnvVar>>,
+    pub(crate) env: Option<Vec<EnvVar>>,
 
     /// EnvVars carries the environment variables to add to the pod.
     /// Deprecated. Consider using `env` instead.
@@ -260,24 +419,25 @@ pub struct DriverSpec {
     pub volume_mounts: Option<Vec<k8s_openapi::api::core::v1::VolumeMount>>,
     /// Affinity specifies the affinity/anti-affinity settings for the pod.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // Affinity *apiv1.Affinity `json:"affinity,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<k8s_openapi::api::core::v1::Affinity>,
     /// Tolerations specifies the tolerations listed in ".spec.tolerations" to be applied to the pod.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // Tolerations []apiv1.Toleration `json:"tolerations,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerations: Option<Vec<k8s_openapi::api::core::v1::Toleration>>,
     /// PodSecurityContext specifies the PodSecurityContext to apply.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // PodSecurityContext *apiv1.PodSecurityContext `json:"podSecurityContext,omitempty"`
-    /// SecurityContext specifies the container's SecurityContext to apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_security_context: Option<k8s_openapi::api::core::v1::PodSecurityContext>,
+    /// SecurityContext specifies the container's SecurityContext to apply,
+    /// it's also the default of the sidecars and init-containers.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // SecurityContext *apiv1.SecurityContext `json:"securityContext,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_context: Option<k8s_openapi::api::core::v1::SecurityContext>,
     /// SchedulerName specifies the scheduler that will be used for scheduling
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // SchedulerName *string `json:"schedulerName,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduler_name: Option<String>,
     /// Sidecars is a list of sidecar containers that run along side the main Spark container.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -301,8 +461,8 @@ pub struct DriverSpec {
     // DNSConfig *apiv1.PodDNSConfig `json:"dnsConfig,omitempty"`
     /// Termination grace period seconds for the pod
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // TerminationGracePeriodSeconds *int64 `json:"terminationGracePeriodSeconds,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination_grace_period_seconds: Option<i64>,
     /// HostAliases settings for the pod, following the Kubernetes specifications.
     /// +optional
     // #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -311,6 +471,27 @@ pub struct DriverSpec {
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub share_process_namespace: Option<bool>,
+
+    // NOT in SparkPodSpec of SKO, rendered into the fields it has
+
+    /// TopologySpreadConstraints describes how the pods ought to spread across topology domains, such as zones.
+    /// SKO doesn't pass them to the pods, so each one is rendered into a preferred pod anti-affinity on its
+    /// topology key, which selects the pods of the same role of the application by default. The maxSkew
+    /// isn't enforced, and `DoNotSchedule` is only preferred.
+    /// +optional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topology_spread_constraints: Option<Vec<k8s_openapi::api::core::v1::TopologySpreadConstraint>>,
+    /// PriorityClassName is the name of the PriorityClass of the pod, rendered into
+    /// `batchSchedulerOptions.priorityClassName` which the SKO webhook applies to both the driver and executors,
+    /// so the ones of the driver and executor can't differ.
+    /// +optional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority_class_name: Option<String>,
+    /// ExtendedResources are the custom resources requested by the pod, scheduled by spark with
+    /// the `spark.{driver,executor}.resource.*` configs.
+    /// +optional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extended_resources: Option<Vec<ExtendedResource>>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
@@ -368,8 +549,8 @@ pub struct ExecutorSpec {
     pub memory_overhead: Option<String>,
     /// GPU specifies GPU requirement for the pod.
     /// +optional
-    // #[serde(default, rename = "gpu", skip_serializing_if = "Option::is_none")]
-    // GPU *GPUSpec `json:"gpu,omitempty"`
+    #[serde(default, rename = "gpu", skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<GPUSpec>,
     /// Image is the container image to use. Overrides Spec.Image if set.
     /// +optional
     // #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -377,15 +558,15 @@ pub struct ExecutorSpec {
     /// ConfigMaps carries information of other ConfigMaps to add to the pod.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    config_maps: Option<Vec<NamePath>>,
+    pub(crate) config_maps: Option<Vec<NamePath>>,
     /// Secrets carries information of secrets to add to the pod.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    secrets: Option<Vec<SecretInfo>>,
+    pub(crate) secrets: Option<Vec<SecretInfo>>,
     /// Env carries the environment variables to add to the pod.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    env: Option<Vec<EnvVar>>,
+    pub(crate) env: Option<Vec<EnvVar>>,
     /// EnvVars carries the environment variables to add to the pod.
     /// Deprecated. Consider using `env` instead.
     /// +optional
@@ -416,24 +597,25 @@ pub struct ExecutorSpec {
     pub volume_mounts: Option<Vec<k8s_openapi::api::core::v1::VolumeMount>>,
     /// Affinity specifies the affinity/anti-affinity settings for the pod.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // Affinity *apiv1.Affinity `json:"affinity,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<k8s_openapi::api::core::v1::Affinity>,
     /// Tolerations specifies the tolerations listed in ".spec.tolerations" to be applied to the pod.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // Tolerations []apiv1.Toleration `json:"tolerations,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerations: Option<Vec<k8s_openapi::api::core::v1::Toleration>>,
     /// PodSecurityContext specifies the PodSecurityContext to apply.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // PodSecurityContext *apiv1.PodSecurityContext `json:"podSecurityContext,omitempty"`
-    /// SecurityContext specifies the container's SecurityContext to apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_security_context: Option<k8s_openapi::api::core::v1::PodSecurityContext>,
+    /// SecurityContext specifies the container's SecurityContext to apply,
+    /// it's also the default of the sidecars and init-containers.
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // SecurityContext *apiv1.SecurityContext `json:"securityContext,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_context: Option<k8s_openapi::api::core::v1::SecurityContext>,
     /// SchedulerName specifies the scheduler that will be used for scheduling
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // SchedulerName *string `json:"schedulerName,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduler_name: Option<String>,
     /// Sidecars is a list of sidecar containers that run along side the main Spark container.
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -457,8 +639,8 @@ pub struct ExecutorSpec {
     // DNSConfig *apiv1.PodDNSConfig `json:"dnsConfig,omitempty"`
     /// Termination grace period seconds for the pod
     /// +optional
-    // #[serde(default, skip_serializing_if = "Option::is_none")]
-    // TerminationGracePeriodSeconds *int64 `json:"terminationGracePeriodSeconds,omitempty"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination_grace_period_seconds: Option<i64>,
     /// HostAliases settings for the pod, following the Kubernetes specifications.
     /// +optional
     // #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -467,6 +649,27 @@ pub struct ExecutorSpec {
     /// +optional
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub share_process_namespace: Option<bool>,
+
+    // NOT in SparkPodSpec of SKO, rendered into the fields it has
+
+    /// TopologySpreadConstraints describes how the pods ought to spread across topology domains, such as zones.
+    /// SKO doesn't pass them to the pods, so each one is rendered into a preferred pod anti-affinity on its
+    /// topology key, which selects the pods of the same role of the application by default. The maxSkew
+    /// isn't enforced, and `DoNotSchedule` is only preferred.
+    /// +optional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topology_spread_constraints: Option<Vec<k8s_openapi::api::core::v1::TopologySpreadConstraint>>,
+    /// PriorityClassName is the name of the PriorityClass of the pod, rendered into
+    /// `batchSchedulerOptions.priorityClassName` which the SKO webhook applies to both the driver and executors,
+    /// so the ones of the driver and executor can't differ.
+    /// +optional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority_class_name: Option<String>,
+    /// ExtendedResources are the custom resources requested by the pod, scheduled by spark with
+    /// the `spark.{driver,executor}.resource.*` configs.
+    /// +optional
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extended_resources: Option<Vec<ExtendedResource>>,
 }
 
 // SecretInfo captures information of a secret.
@@ -489,17 +692,64 @@ pub struct SecretInfo {
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NamePath {
-    name: String,
-    path: String,
+    pub name: String,
+    pub path: String,
+}
+
+// GPUSpec defines GPU resource requirements.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GPUSpec {
+    /// Name is GPU resource name, such as: nvidia.com/gpu or amd.com/gpu
+    pub name: String,
+    /// Quantity is the number of GPUs to request for driver or executor.
+    pub quantity: i64,
+}
+
+impl GPUSpec {
+    /// The GPU as an extended resource named `gpu` of the vendor domain
+    pub fn extended_resource(&self) -> ExtendedResource {
+        let vendor = self
+            .name
+            .split_once('/')
+            .map(|(vendor, _)| vendor)
+            .unwrap_or(&self.name);
+        ExtendedResource {
+            name: crate::constants::SPARK_GPU_RESOURCE_NAME.to_string(),
+            vendor: vendor.to_string(),
+            amount: self.quantity,
+            task_amount: None,
+            discovery_script: Some(crate::constants::SPARK_GPU_DISCOVERY_SCRIPT.to_string()),
+        }
+    }
+}
+
+// ExtendedResource is a custom resource of the pod, requested as `<vendor>/<name>` from K8S.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedResource {
+    /// Name is the spark resource name, such as: gpu or fpga
+    pub name: String,
+    /// Vendor is the domain of the K8S resource, such as: nvidia.com
+    pub vendor: String,
+    /// Amount is the number of the resource to request for driver or each executor.
+    pub amount: i64,
+    /// TaskAmount is the amount of the resource each task requires, only for executors,
+    /// defaults to the amount shared evenly by the tasks of the executor cores, must run at least one task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_amount: Option<f64>,
+    /// DiscoveryScript finds the addresses of the resource in the container.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery_script: Option<String>,
 }
 
 // Port represents the port definition in the pods objects.
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Port {
-    name: String,
-    protocol: String,
-    container_port: i32,
+    pub(crate) name: String,
+    pub(crate) protocol: String,
+    pub(crate) container_port: i32,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
@@ -597,6 +847,21 @@ impl Dependencies {
     pub fn extend_jars(&mut self, jars: Vec<String>) {
         self.jars.get_or_insert(Vec::new()).extend(jars);
     }
+
+    /// iterate all the jars, files, packages ...
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        [
+            &self.jars,
+            &self.files,
+            &self.py_files,
+            &self.packages,
+            &self.exclude_packages,
+            &self.repositories,
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
+    }
 }
 
 /// MonitoringSpec defines the monitoring specification.
@@ -708,10 +973,17 @@ pub struct SparkUIConfiguration {
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SparkHiveserver2ServiceConfiguration {
+    /// The port the hive server2 listens on in the driver, defaults to 10001 for the thrift and 8009 for the UI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_port: Option<i32>,
     // ServicePort allows configuring the port at service level that might be different from the targetPort.
-    // TargetPort should be the same as the one defined in spark.hiveserver2.port
+    // Defaults to the container port.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub service_port: Option<i32>,
+    /// The NodePort of a NodePort or LoadBalancer service, allocated by the API server if not set.
+    /// A NodePort already allocated to another Service is reported in the status, and retried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_port: Option<i32>,
     // ServicePortName allows configuring the name of the service port.
     // This may be useful for sidecar proxies like Envoy injected by Istio which require specific ports names to treat traffic as proper HTTP.
     // Defaults to driver-thrift-port.
@@ -729,6 +1001,35 @@ pub struct SparkHiveserver2ServiceConfiguration {
     // TlsHosts is useful If we need to declare SSL certificates to the ingress object
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ingress_tls: Option<k8s_openapi::api::networking::v1::IngressTLS>,
+    /// The TLS of the thrift endpoint, ignored by the UI service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<crate::hive_server2::HiveServer2Tls>,
+    /// The host of the Ingress of the UI, which is created if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress_host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress_class_name: Option<String>,
+    /// The Gateway API HTTPRoute of the UI, which is created if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_route: Option<crate::ui_exposure::HttpRouteOptions>,
+}
+
+impl SparkHiveserver2ServiceConfiguration {
+    pub fn container_port_or(&self, default: i32) -> i32 {
+        self.container_port.unwrap_or(default)
+    }
+
+    pub fn service_port_or(&self, default: i32) -> i32 {
+        self.service_port.unwrap_or(self.container_port_or(default))
+    }
+
+    /// The NodePort of the service, which only NodePort and LoadBalancer services have
+    pub fn node_port(&self) -> Option<i32> {
+        match self.service_type.clone().unwrap_or_default() {
+            ServiceType::NodePort | ServiceType::LoadBalancer => self.node_port,
+            _ => None,
+        }
+    }
 }
 
 // DynamicAllocation contains configuration options for dynamic allocation.
@@ -824,7 +1125,7 @@ impl JobSpec {
         app_name: &String,
         sko: &mut SKOSparkApplicationSpec,
     ) -> Result<()> {
-        // assumed the sko.typ is assigned in sko_spec_default
+        // assumed the sko.typ is assigned in the TemplateStage
         match self.typ {
             SparkJobType::JarJob | SparkJobType::JavaJob | SparkJobType::ScalaJob => {
                 let jar = self.jar.clone().ok_or(Error::MissingJobField {
@@ -980,263 +1281,103 @@ pub struct SessionSpec {
         skip_serializing_if = "Option::is_none"
     )]
     pub hive_server2_ui_options: Option<SparkHiveserver2ServiceConfiguration>,
+    /// The Ingress and HTTPRoute of the Spark UI of the driver. The Service of the Spark UI is created by the
+    /// spark-on-k8s-operator, so only the ingress and route fields are used.
+    #[serde(
+        default,
+        rename = "sparkUIOptions",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub spark_ui_options: Option<SparkHiveserver2ServiceConfiguration>,
+    /// Write the connection endpoints of the status into the ConfigMap `<name>-connection` as well,
+    /// for the client pods to mount, defaults to false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_config_map: Option<bool>,
+    /// How the clients of the hive server2 are authenticated, none if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authentication: Option<crate::hive_server2::HiveServer2Authentication>,
+    /// Run the queries as the authenticated user rather than the service account of the session, defaults to false.
+    /// The service account should be allowed to impersonate the users by the hadoop proxy users.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub do_as: Option<bool>,
+    /// Suspend the session once idle for the duration, for example: `30m`. The application is deleted
+    /// while the services and spec are kept. It is only resumed by the `spark.bytenative.com/resume` annotation,
+    /// the connections to a suspended session are refused rather than resuming it. A session with an open
+    /// connection isn't idle, even when it runs no jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<String>,
+    /// How the inactivity is probed, defaults to the Spark UI of the driver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_probe: Option<crate::idle::IdleProbe>,
 }
 
-async fn sko_spec_default_from_template(
-    spark: &SparkSpec,
-    typ: &SparkJobType,
-    client: &Client,
-    namespace: &str,
-    template: Option<&String>
-) -> Result<SKOSparkApplicationSpec> {
-    let merged = spark.merge_template_opt(client, namespace, template).await?;
-    let SparkSpec {
-        spark_version,
-        mode,
-        proxy_user,
-        image,
-        image_pull_policy,
-        image_pull_secrets,
-        spark_conf,
-        hadoop_conf,
-        spark_config_map,
-        hadoop_config_map,
-        volumes,
-        driver,
-        executor,
-        deps,
-        restart_policy,
-        node_selector,
-        failure_retries,
-        retry_interval,
-        memory_overhead_factor,
-        monitoring,
-        batch_scheduler,
-        batch_scheduler_options,
-        time_to_live_seconds,
-        spark_uioptions,
-        dynamic_allocation,
-        s3_connection,
-        catalogs,
-        env_sets,
-    } = merged;
-    
-    // driver and executor
-    let mut driver = sko_spec_driver(driver)?;
-    let mut executor = executor.unwrap_or_default();
-    // intial configs with ...
-    let mut conf = spark_conf.unwrap_or_default();
-    // initial deps
-    let mut deps = deps.unwrap_or_default();
-    // s3 conf
-    if let Some(s3_connection) = s3_connection {
-        let spec = match s3_connection {
-            S3ConnectionDef::Inline(inline) => inline,
-            S3ConnectionDef::Reference(resource_name) => {
-                S3ConnectionSpec::get(resource_name.as_str(), client, namespace).await?
-            }
-        };
-        conf.extend(spec.spark_configs());
-
-        if let Some(credentials) = spec.credentials {
-            // add credentials env-var for driver and executor
-            if let Some(env_vars) = credentials.env_vars() {
-                driver.env.get_or_insert(vec![]).extend(env_vars.clone());
-                executor.env.get_or_insert(vec![]).extend(env_vars);
-            }
-            // add credentials volume and volume mount fro driver and executor
-            // if let Some(secret) = credentials.secret_mount() {
-            //     driver.secrets.get_or_insert(vec![]).push(secret.clone());
-            //     executor.secrets.get_or_insert(vec![]).push(secret);
-            // }       
-        }
-    }
-    // catalog conf
-    // make the key unique
-    if let Some(catalogs) = catalogs {
-        for catalog in catalogs {
-            let spec = match catalog {
-                SparkCatalogDef::Inline(inline) => inline,
-                SparkCatalogDef::Reference(resource_name) => {
-                    SparkCatalogSpec::get(resource_name.as_str(), client, namespace).await?
-                }
-            };
-            conf.extend(spec.spark_configs());
-            spec.jars.map(|r| deps.extend_jars(r));
-        }
-    };
-
-    // envset conf
-    if let Some(env_sets) = env_sets {
-        for env_set in env_sets {
-            let spec = match env_set {
-                SparkEnvSetDef::Inline(inline) => inline,
-                SparkEnvSetDef::Reference(resource_name) => {
-                    SparkEnvSetSpec::get(resource_name.as_str(), client, namespace).await?
-                }
-            };
-            conf.extend(spec.configs.unwrap_or_default());
-            deps.extend(spec.deps.unwrap_or_default());
-        }
+impl SessionSpec {
+    pub fn idle_timeout(&self) -> Result<Option<std::time::Duration>> {
+        self.idle_timeout.as_deref().map(crate::idle::parse_duration).transpose()
     }
-
-    Ok(SKOSparkApplicationSpec {
-        typ: sko_application_type(typ),
-        spark_version: spark_version,
-        mode: mode,
-        proxy_user: proxy_user,
-        image: image,
-        image_pull_policy: image_pull_policy,
-        image_pull_secrets: image_pull_secrets,
-        main_application_file: None,
-        main_class: None,
-        python_verison: None,
-        arguments: None,
-
-        spark_conf: Some(conf),
-        hadoop_conf: hadoop_conf,
-        spark_config_map: spark_config_map,
-        hadoop_config_map: hadoop_config_map,
-        volumes: volumes,
-        driver: driver,
-        executor: Some(executor),
-        deps: Some(deps),
-        restart_policy: restart_policy,
-        node_selector: node_selector,
-        failure_retries: failure_retries,
-        retry_interval: retry_interval,
-        memory_overhead_factor: memory_overhead_factor,
-        monitoring: monitoring,
-        batch_scheduler: batch_scheduler,
-        batch_scheduler_options: batch_scheduler_options,
-        time_to_live_seconds: time_to_live_seconds,
-        spark_uioptions: spark_uioptions,
-        dynamic_allocation: dynamic_allocation,
-    })
 }
 
-// should ONLY ref to self.spec.spark
-async fn sko_spec_default(
-    spark: &SparkSpec,
-    typ: &SparkJobType,
-    client: &Client,
-    namespace: &str,
-) -> Result<SKOSparkApplicationSpec> {
-    // driver and executor
-    let mut driver = sko_spec_driver(spark.driver.clone())?;
-    let mut executor = spark.executor.clone().unwrap_or_default();
-    // intial configs with ...
-    let mut conf = spark.spark_conf.clone().unwrap_or_default();
-    // initial deps
-    let mut deps = spark.deps.clone().unwrap_or_default();
-    // s3 conf
-    if let Some(s3_connection) = &spark.s3_connection {
-        let spec = match s3_connection {
-            S3ConnectionDef::Inline(inline) => inline.clone(),
-            S3ConnectionDef::Reference(resource_name) => {
-                S3ConnectionSpec::get(resource_name.as_str(), client, namespace).await?
-            }
-        };
-        // add credentials env-var for driver and executor
-        if let Some(credentials) = &spec.credentials {
-            if let Some(env_vars) = credentials.env_vars() {
-                driver.env.get_or_insert(vec![]).extend(env_vars.clone());
-                executor.env.get_or_insert(vec![]).extend(env_vars);
-            }    
-        }
-        
-        conf.extend(spec.spark_configs());
-
-        // add credentials volume and volume mount fro driver and executor
-        if let Some(credentials) = &spec.credentials {
-            if let Some(secret) = credentials.secret_mount() {
-                driver.secrets.get_or_insert(vec![]).push(secret.clone());
-                executor.secrets.get_or_insert(vec![]).push(secret);
-            }                
-        }
-    }
-    // catalog conf
-    // make the key unique
-    if let Some(catalogs) = &spark.catalogs {
-        for catalog in catalogs {
-            let spec = match catalog {
-                SparkCatalogDef::Inline(inline) => inline.clone(),
-                SparkCatalogDef::Reference(resource_name) => {
-                    SparkCatalogSpec::get(resource_name.as_str(), client, namespace).await?
-                }
-            };
-            conf.extend(spec.spark_configs());
-            spec.jars.as_ref().map(|r| deps.extend_jars(r.clone()));
-        }
-    };
-
-    // envset conf
-    if let Some(env_sets) = &spark.env_sets {
-        for env_set in env_sets {
-            let spec = match env_set {
-                SparkEnvSetDef::Inline(inline) => inline.clone(),
-                SparkEnvSetDef::Reference(resource_name) => {
-                    SparkEnvSetSpec::get(resource_name.as_str(), client, namespace).await?
-                }
-            };
-            conf.extend(spec.configs.clone().unwrap_or_default());
-            deps.extend(spec.deps.clone().unwrap_or_default());
-        }
-    }
+/// The SKO application to deploy, with the ConfigMaps and Secrets it depends on.
+pub struct SKOResources<T> {
+    pub application: T,
+    /// created before the application
+    pub config_maps: Vec<ConfigMap>,
+    /// created before the application, such as the inline S3 credentials
+    pub secrets: Vec<Secret>,
+    pub report: ProvenanceReport,
+}
 
-    Ok(SKOSparkApplicationSpec {
-        typ: sko_application_type(typ),
-        spark_version: spark.spark_version.clone(),
-        mode: spark.mode.clone(),
-        proxy_user: spark.proxy_user.clone(),
-        image: spark.image.clone(),
-        image_pull_policy: spark.image_pull_policy.clone(),
-        image_pull_secrets: spark.image_pull_secrets.clone(),
-        main_application_file: None,
-        main_class: None,
-        python_verison: None,
-        arguments: None,
-
-        spark_conf: Some(conf),
-        hadoop_conf: spark.hadoop_conf.clone(),
-        spark_config_map: spark.spark_config_map.clone(),
-        hadoop_config_map: spark.hadoop_config_map.clone(),
-        volumes: spark.volumes.clone(),
-        driver: driver,
-        executor: Some(executor),
-        deps: Some(deps),
-        restart_policy: spark.restart_policy.clone(),
-        node_selector: spark.node_selector.clone(),
-        failure_retries: spark.failure_retries.clone(),
-        retry_interval: spark.retry_interval.clone(),
-        memory_overhead_factor: spark.memory_overhead_factor.clone(),
-        monitoring: spark.monitoring.clone(),
-        batch_scheduler: spark.batch_scheduler.clone(),
-        batch_scheduler_options: spark.batch_scheduler_options.clone(),
-        time_to_live_seconds: spark.time_to_live_seconds.clone(),
-        spark_uioptions: spark.spark_uioptions.clone(),
-        dynamic_allocation: spark.dynamic_allocation.clone(),
-    })
+/// The Secrets managed for the application, if any.
+fn sko_secrets(rendered: &Rendered, meta: impl Fn(String) -> Result<ObjectMeta>) -> Result<Vec<Secret>> {
+    rendered
+        .secrets
+        .iter()
+        .map(|(name, data)| {
+            Ok(Secret {
+                metadata: meta(name.clone())?,
+                string_data: Some(data.clone()),
+                type_: Some("Opaque".to_string()),
+                ..Secret::default()
+            })
+        })
+        .collect()
 }
 
-fn sko_spec_driver(mut driver: DriverSpec) -> Result<DriverSpec> {
-    if driver.service_account.is_none() {
-        driver.service_account = Some(String::from(crate::constants::SKO_DEFAULT_SERVICE_ACCOUNT))
+/// record the warnings of the rendering in the annotations of the generated resource
+fn annotate_warnings(metadata: &mut ObjectMeta, report: &ProvenanceReport) -> Result<()> {
+    if !report.warnings.is_empty() {
+        let warnings = serde_json::to_string(&report.warnings)
+            .map_err(|e| Error::FailedSerializeObjectToJson { internal: e })?;
+        metadata.annotations.get_or_insert_with(BTreeMap::new).insert(
+            crate::constants::SPARK_WARNINGS_ANNOTATION.to_string(),
+            warnings,
+        );
     }
-    Ok(driver)
+    Ok(())
 }
 
-fn sko_application_type(typ: &SparkJobType) -> String {
-    match typ {
-        SparkJobType::SqlJob
-        | SparkJobType::SqlFileJob
-        | SparkJobType::JarJob
-        | SparkJobType::JavaJob => crate::constants::SKO_APPLICATION_TYPE_JAVA.to_owned(),
-        SparkJobType::ScalaJob => crate::constants::SKO_APPLICATION_TYPE_SCALA.to_owned(),
-        SparkJobType::PythonJob => crate::constants::SKO_APPLICATION_TYPE_PYTHON.to_owned(),
-        SparkJobType::RJob => crate::constants::SKO_APPLICATION_TYPE_R.to_owned(),
+/// The ConfigMap of the provenance report, if any.
+fn sko_config_maps(
+    spark: &SparkSpec,
+    app_name: &str,
+    rendered: &Rendered,
+    meta: impl Fn(String) -> Result<ObjectMeta>,
+) -> Result<Vec<ConfigMap>> {
+    let mut config_maps = vec![];
+    if spark.provenance_report.unwrap_or(false) {
+        config_maps.push(ConfigMap {
+            metadata: meta(provenance::config_map_name(app_name))?,
+            binary_data: None,
+            data: Some(BTreeMap::from([(
+                crate::constants::PROVENANCE_REPORT_FILE_NAME.to_string(),
+                rendered.report.to_yaml()?,
+            )])),
+            immutable: None,
+        });
     }
+    Ok(config_maps)
 }
+
 trait Sparkable {
     fn spark(&self) -> &SparkSpec;
 }
@@ -1295,19 +1436,16 @@ impl SparkJob {
         Ok(self.sko_meta_named(name)?)
     }
 
-    async fn sko_spec(&self, client: &Client, namespace: &str) -> Result<SKOSparkApplicationSpec> {
-        // let mut sko = sko_spec_default(self.spark(), &self.spec.job.typ, client, namespace).await?;
-        let mut sko = sko_spec_default_from_template(
-            self.spark(), 
-            &self.spec.job.typ, 
-            client, 
-            namespace, 
-            self.spec.spark_template.as_ref()
-        ).await?;
-
-        let name = self.name_any();
-        self.spec.job.populate_sko_fields(&name, &mut sko)?;
-        Ok(sko)
+    pub(crate) async fn sko_spec<L: ResourceLookup>(
+        &self,
+        lookup: &L,
+        chain: &SparkTemplateChain,
+    ) -> Result<Rendered> {
+        RenderPipeline::resolve(&self.name_any(), self.spark(), &self.spec.job.typ, chain, lookup)
+            .await?
+            .with_stage(JobTypeStage::new(self.name_any(), &self.spec.job))
+            .with_stage(PodTemplateStage::new())
+            .render()
     }
 
     pub async fn sko_application(
@@ -1315,12 +1453,34 @@ impl SparkJob {
         client: &Client,
         namespace: &str,
     ) -> Result<SKOSparkApplication> {
+        Ok(self.sko_resources(client, namespace).await?.application)
+    }
+
+    pub async fn sko_resources(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<SKOResources<SKOSparkApplication>> {
+        let lookup = KubeLookup::new(client, namespace);
+        let chain = SparkTemplateChain::resolve(&lookup, self.spec.spark_template.as_ref()).await?;
+        let mut metadata = self.sko_meta()?;
+        chain.annotate(&mut metadata);
+        let rendered = self.sko_spec(&lookup, &chain).await?;
+        let config_maps =
+            sko_config_maps(self.spark(), &self.name_any(), &rendered, |n| self.sko_meta_named(n))?;
+        let secrets = sko_secrets(&rendered, |n| self.sko_meta_named(n))?;
+        annotate_warnings(&mut metadata, &rendered.report)?;
         let appl = SKOSparkApplication {
-            metadata: self.sko_meta()?,
-            spec: self.sko_spec(client, namespace).await?,
+            metadata,
+            spec: rendered.spec,
             status: Option::None,
         };
-        Ok(appl)
+        Ok(SKOResources {
+            application: appl,
+            config_maps,
+            secrets,
+            report: rendered.report,
+        })
     }
 
     pub fn sql_config_map(&self) -> Result<Option<ConfigMap>> {
@@ -1394,31 +1554,16 @@ impl SparkScheduledJob {
         Ok(self.sko_meta_named(name)?)
     }
 
-    async fn sko_spec(
+    pub(crate) async fn sko_spec<L: ResourceLookup>(
         &self,
-        client: &Client,
-        namespace: &str,
-    ) -> Result<SKOScheduledSparkApplicationSpec> {
-        // let mut templete =
-        //     sko_spec_default(self.spark(), &SparkJobType::JavaJob, client, namespace).await?;
-        let mut templete = sko_spec_default_from_template(
-            self.spark(), 
-            &self.spec.job.typ, 
-            client, 
-            namespace, 
-            self.spec.spark_template.as_ref()
-        ).await?;
-
-        let name = self.name_any();
-        self.spec.job.populate_sko_fields(&name, &mut templete)?;
-        Ok(SKOScheduledSparkApplicationSpec {
-            schedule: self.spec.schedule.schedule.clone(),
-            template: templete,
-            suspend: self.spec.schedule.suspend,
-            concurrency_policy: self.spec.schedule.concurrency_policy.clone(),
-            successful_run_history_limit: self.spec.schedule.successful_run_history_limit,
-            failed_run_history_limit: self.spec.schedule.failed_run_history_limit,
-        })
+        lookup: &L,
+        chain: &SparkTemplateChain,
+    ) -> Result<Rendered> {
+        RenderPipeline::resolve(&self.name_any(), self.spark(), &self.spec.job.typ, chain, lookup)
+            .await?
+            .with_stage(JobTypeStage::new(self.name_any(), &self.spec.job))
+            .with_stage(PodTemplateStage::new())
+            .render()
     }
 
     pub async fn sko_application(
@@ -1426,12 +1571,41 @@ impl SparkScheduledJob {
         client: &Client,
         namespace: &str,
     ) -> Result<SKOScheduledSparkApplication> {
+        Ok(self.sko_resources(client, namespace).await?.application)
+    }
+
+    pub async fn sko_resources(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<SKOResources<SKOScheduledSparkApplication>> {
+        let lookup = KubeLookup::new(client, namespace);
+        let chain = SparkTemplateChain::resolve(&lookup, self.spec.spark_template.as_ref()).await?;
+        let mut metadata = self.sko_meta()?;
+        chain.annotate(&mut metadata);
+        let rendered = self.sko_spec(&lookup, &chain).await?;
+        let config_maps =
+            sko_config_maps(self.spark(), &self.name_any(), &rendered, |n| self.sko_meta_named(n))?;
+        let secrets = sko_secrets(&rendered, |n| self.sko_meta_named(n))?;
+        annotate_warnings(&mut metadata, &rendered.report)?;
         let appl = SKOScheduledSparkApplication {
-            metadata: self.sko_meta()?,
-            spec: self.sko_spec(client, namespace).await?,
+            metadata,
+            spec: SKOScheduledSparkApplicationSpec {
+                schedule: self.spec.schedule.schedule.clone(),
+                template: rendered.spec,
+                suspend: self.spec.schedule.suspend,
+                concurrency_policy: self.spec.schedule.concurrency_policy.clone(),
+                successful_run_history_limit: self.spec.schedule.successful_run_history_limit,
+                failed_run_history_limit: self.spec.schedule.failed_run_history_limit,
+            },
             status: Option::None,
         };
-        Ok(appl)
+        Ok(SKOResources {
+            application: appl,
+            config_maps,
+            secrets,
+            report: rendered.report,
+        })
     }
 
     pub fn sql_config_map(&self) -> Result<Option<ConfigMap>> {
@@ -1511,60 +1685,16 @@ impl SparkSession {
         Ok(self.sko_meta_named(new_name)?)
     }
 
-    async fn sko_spec(&self, client: &Client, namespace: &str) -> Result<SKOSparkApplicationSpec> {
-        let main_application_file = Some(crate::constants::SPARK_MAIN_APPLICATION_FILE.to_owned());
-        let main_class = Some(crate::constants::SPARK_SESSION_MAIN_CLASS.to_owned());
-
-        let thrift_conf = self
-            .spec
-            .session
-            .hive_server2_thrift_options
-            .clone()
-            .unwrap_or_default();
-        let ui_conf = self
-            .spec
-            .session
-            .hive_server2_ui_options
-            .clone()
-            .unwrap_or_default();
-        let hs2_thrift_port = Port {
-            name: thrift_conf
-                .service_port_name
-                .clone()
-                .unwrap_or("hs2-thrift-port".to_string()),
-            protocol: "TCP".to_string(),
-            container_port: crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT,
-        };
-        let hs2_ui_port = Port {
-            name: ui_conf
-                .service_port_name
-                .clone()
-                .unwrap_or("hs2-ui-port".to_string()),
-            protocol: "TCP".to_string(),
-            container_port: crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT,
-        };
-
-        // let mut sko = sko_spec_default(self.spark(), &SparkJobType::JavaJob, client, namespace).await?;
-        let mut sko = sko_spec_default_from_template(
-            self.spark(), 
-            &SparkJobType::JavaJob, 
-            client, 
-            namespace, 
-            self.spec.spark_template.as_ref()
-        ).await?;
-
-        sko.main_application_file = main_application_file;
-        sko.main_class = main_class;
-
-        // add ports
-        let driver_ports = sko.driver.ports.get_or_insert(vec![]);
-        driver_ports.extend(vec![hs2_thrift_port, hs2_ui_port]);
-
-        // add spark configs
-        let spark_conf = sko.spark_conf.get_or_insert(HashMap::new());
-        spark_conf.extend(self.spark_hs2_configs()?);
-
-        Ok(sko)
+    pub(crate) async fn sko_spec<L: ResourceLookup>(
+        &self,
+        lookup: &L,
+        chain: &SparkTemplateChain,
+    ) -> Result<Rendered> {
+        RenderPipeline::resolve(&self.name_any(), self.spark(), &SparkJobType::JavaJob, chain, lookup)
+            .await?
+            .with_stage(SessionStage::new(&self.spec.session))
+            .with_stage(PodTemplateStage::new())
+            .render()
     }
 
     pub async fn sko_application(
@@ -1572,39 +1702,135 @@ impl SparkSession {
         client: &Client,
         namespace: &str,
     ) -> Result<SKOSparkApplication> {
+        Ok(self.sko_resources(client, namespace).await?.application)
+    }
+
+    pub async fn sko_resources(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<SKOResources<SKOSparkApplication>> {
         // TODO: validate session ...
+        let lookup = KubeLookup::new(client, namespace);
+        let chain = SparkTemplateChain::resolve(&lookup, self.spec.spark_template.as_ref()).await?;
+        let mut metadata = self.sko_meta()?;
+        chain.annotate(&mut metadata);
+        let rendered = self.sko_spec(&lookup, &chain).await?;
+        let config_maps =
+            sko_config_maps(self.spark(), &self.name_any(), &rendered, |n| self.sko_meta_named(n))?;
+        let secrets = sko_secrets(&rendered, |n| self.sko_meta_named(n))?;
+        annotate_warnings(&mut metadata, &rendered.report)?;
         let appl = SKOSparkApplication {
-            metadata: self.sko_meta()?,
-            spec: self.sko_spec(client, namespace).await?,
+            metadata,
+            spec: rendered.spec,
             status: Option::None,
         };
-        Ok(appl)
+        Ok(SKOResources {
+            application: appl,
+            config_maps,
+            secrets,
+            report: rendered.report,
+        })
+    }
+
+    /// The exposures of the hive server2 UI and of the Spark UI of the driver
+    pub fn ui_exposures(&self) -> Result<[crate::ui_exposure::UiExposure; 2]> {
+        let name = self.name_any();
+        let session = &self.spec.session;
+        let hs2_ui_options = session.hive_server2_ui_options.clone().unwrap_or_default();
+        let hs2_ui_service = self.hive_server2_ui_service()?;
+        let hs2_ui = crate::ui_exposure::ui_exposure(
+            self.sko_meta_named(format!("{name}{}", crate::ui_exposure::HS2_UI_EXPOSURE_SUFFIX))?,
+            &hs2_ui_options,
+            &hs2_ui_service.name_any(),
+            hs2_ui_options.service_port_or(crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT),
+        );
+        // the UI service of the driver created by the spark-on-k8s-operator
+        let spark_ui_port = self
+            .spec
+            .spark
+            .spark_uioptions
+            .as_ref()
+            .map_or(crate::idle::SPARK_UI_PORT, |o| o.service_port);
+        let spark_ui = crate::ui_exposure::ui_exposure(
+            self.sko_meta_named(format!("{name}{}", crate::ui_exposure::SPARK_UI_EXPOSURE_SUFFIX))?,
+            &session.spark_ui_options.clone().unwrap_or_default(),
+            &format!("{name}{}", crate::idle::SPARK_UI_SERVICE_SUFFIX),
+            spark_ui_port,
+        );
+        Ok([hs2_ui, spark_ui])
+    }
+
+    /// The connection endpoints of the session, from its created thrift and UI services if any.
+    /// The Ingress or HTTPRoute URLs of the UIs win over the services, and the NodePorts are reached
+    /// at `node_address`.
+    pub fn endpoints(
+        &self,
+        thrift_service: Option<&Service>,
+        ui_service: Option<&Service>,
+        node_address: Option<&str>,
+        ui_url: Option<String>,
+        spark_ui_url: Option<String>,
+    ) -> Result<crate::endpoints::SessionEndpoints> {
+        let namespace = self.namespace().ok_or(Error::MissingObjectKey { key: "namespace" })?;
+        let session = &self.spec.session;
+        let tls = session.hive_server2_thrift_options.as_ref().and_then(|o| o.tls.as_ref());
+        let jdbc_url = |host: &str, port: i32| {
+            crate::hive_server2::jdbc_url(host, port, session.authentication.as_ref(), tls)
+        };
+        // the in-cluster address of a service, as it's built if it isn't created yet
+        let cluster_address = |service: &Service| {
+            let host = format!("{}.{namespace}.svc", service.name_any());
+            let port = service.spec.as_ref().and_then(|s| s.ports.as_ref()?.first().map(|p| p.port));
+            (host, port.unwrap_or_default())
+        };
+
+        let built_thrift_service = self.hive_server2_thrift_service()?;
+        let (host, port) = cluster_address(thrift_service.unwrap_or(&built_thrift_service));
+        let cluster_jdbc_url = jdbc_url(&host, port);
+        let external_jdbc_url = thrift_service
+            .and_then(|s| crate::endpoints::external_address(s, node_address))
+            .map(|(host, port)| jdbc_url(&host, port));
+        let beeline = format!(
+            "beeline -u '{}'",
+            external_jdbc_url.as_ref().unwrap_or(&cluster_jdbc_url)
+        );
+
+        let built_ui_service = self.hive_server2_ui_service()?;
+        let ui_service_url = ui_service
+            .and_then(|s| crate::endpoints::external_address(s, node_address))
+            .unwrap_or_else(|| cluster_address(ui_service.unwrap_or(&built_ui_service)));
+        let spark_ui_service_url = format!(
+            "http://{}{}.{namespace}.svc:{}/",
+            self.name_any(),
+            crate::idle::SPARK_UI_SERVICE_SUFFIX,
+            self.spec
+                .spark
+                .spark_uioptions
+                .as_ref()
+                .map_or(crate::idle::SPARK_UI_PORT, |o| o.service_port)
+        );
+        Ok(crate::endpoints::SessionEndpoints {
+            jdbc_url: cluster_jdbc_url,
+            external_jdbc_url,
+            beeline,
+            ui_url: ui_url.or_else(|| Some(format!("http://{}:{}/", ui_service_url.0, ui_service_url.1))),
+            spark_ui_url: spark_ui_url.or(Some(spark_ui_service_url)),
+            node_port_conflicts: None,
+        })
     }
 
-    fn spark_hs2_configs(&self) -> Result<HashMap<String, String>> {
-        // TODO: more details
-        Ok(HashMap::from([
-            (
-                crate::constants::SPARK_HIVE_SERVER2_WEBUI_HOST.to_string(),
-                "0.0.0.0".to_string(),
-            ),
-            (
-                crate::constants::SPARK_HIVE_SERVER2_WEBUI_PORT.to_string(),
-                crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT.to_string(),
-            ),
-            (
-                crate::constants::SPARK_HIVE_SERVER2_THRIFT_BIND_HOST.to_string(),
-                "0.0.0.0".to_string(),
-            ),
-            (
-                crate::constants::SPARK_HIVE_SERVER2_THRIFT_PORT.to_string(),
-                crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT.to_string(),
-            ),
-            (
-                crate::constants::SPARK_HIVE_SERVER2_ENABLE_DOAS.to_string(),
-                "false".to_string(),
-            ),
-        ]))
+    /// The ConfigMap of the connection `endpoints`, for the client pods to mount
+    pub fn connection_config_map(&self, endpoints: &crate::endpoints::SessionEndpoints) -> Result<ConfigMap> {
+        Ok(ConfigMap {
+            metadata: self.sko_meta_named(format!(
+                "{}{}",
+                self.name_any(),
+                crate::endpoints::CONNECTION_CONFIG_MAP_SUFFIX
+            ))?,
+            data: Some(endpoints.config_map_data()),
+            ..ConfigMap::default()
+        })
     }
 
     pub fn hive_server2_thrift_service(&self) -> Result<Service> {
@@ -1635,18 +1861,11 @@ impl SparkSession {
                         .clone()
                         .or(Some("hs2-thrift-port".to_string())),
                     app_protocol: None,
-                    node_port: match servicec_config.service_type {
-                        Some(ServiceType::NodePort) => servicec_config.service_port.or(Some(
-                            crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_NODE_PORT,
-                        )),
-                        _ => None,
-                    },
-                    port: servicec_config
-                        .service_port
-                        .unwrap_or(crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_NODE_PORT),
+                    node_port: servicec_config.node_port(),
+                    port: servicec_config.service_port_or(crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT),
                     protocol: Some("TCP".to_string()),
                     target_port: Some(IntOrString::Int(
-                        crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT,
+                        servicec_config.container_port_or(crate::constants::HIVE_SERVER2_THRIFT_DEFAULT_PORT),
                     )),
                 }]),
                 // TODO: selector
@@ -1688,18 +1907,11 @@ impl SparkSession {
                         .clone()
                         .or(Some("hs2-ui-port".to_string())),
                     app_protocol: None,
-                    node_port: match servicec_config.service_type {
-                        Some(ServiceType::NodePort) => servicec_config
-                            .service_port
-                            .or(Some(crate::constants::HIVE_SERVER2_UI_DEFAULT_NODE_PORT)),
-                        _ => None,
-                    },
-                    port: servicec_config
-                        .service_port
-                        .unwrap_or(crate::constants::HIVE_SERVER2_UI_DEFAULT_NODE_PORT),
+                    node_port: servicec_config.node_port(),
+                    port: servicec_config.service_port_or(crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT),
                     protocol: Some("TCP".to_string()),
                     target_port: Some(IntOrString::Int(
-                        crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT,
+                        servicec_config.container_port_or(crate::constants::HIVE_SERVER2_UI_DEFAULT_PORT),
                     )),
                 }]),
                 selector: Some(<BTreeMap<String, String>>::from([
@@ -1713,6 +1925,32 @@ impl SparkSession {
     }
 }
 
+/// The Pod Security Standards profile the spark pods are rendered for.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SecurityProfile {
+    /// keep the security contexts as they are
+    Privileged,
+    /// keep the security contexts as they are, the baseline profile doesn't require any
+    Baseline,
+    /// run as non-root `SPARK_UID` without any capabilities, with the RuntimeDefault seccomp profile
+    /// and a read-only root filesystem
+    Restricted,
+}
+
+impl SecurityProfile {
+    /// The profile labeled on the namespace, by the operator label or the enforced Pod Security label
+    pub fn from_namespace_labels(labels: &BTreeMap<String, String>) -> Option<SecurityProfile> {
+        [
+            crate::constants::SECURITY_PROFILE_LABEL,
+            crate::constants::POD_SECURITY_ENFORCE_LABEL,
+        ]
+        .iter()
+        .find_map(|label| labels.get(*label).and_then(|v| v.parse().ok()))
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize, Display, EnumString)]
 pub enum ImagePullPolicy {
     Always,
@@ -1797,6 +2035,104 @@ mod tests {
         )
     }
 
+    fn template_spec(yaml: &str) -> SparkSpec {
+        serde_yaml::from_str::<SparkSpec>(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_template_chain_merge() {
+        let mut chain = SparkTemplateChain::default();
+        chain.push("child".to_string(), template_spec("
+        parentTemplate: root
+        image: child-image
+        driver:
+          memory: 1g
+        sparkConf:
+          spark.child: child
+        ")).unwrap();
+        chain.push("root".to_string(), template_spec("
+        sparkVersion: 3.4.1
+        image: root-image
+        driver:
+          cores: 1
+          memory: 512m
+        sparkConf:
+          spark.root: root
+          spark.child: root
+        ")).unwrap();
+        let job = template_spec("
+        driver:
+          cores: 2
+        ");
+
+        let merged = chain.merge(&job).unwrap();
+        assert_eq!(chain.names(), vec!["child", "root"]);
+        assert_eq!(merged.spark_version, "3.4.1");
+        assert_eq!(merged.image, Some("child-image".to_string()));
+        assert_eq!(merged.driver.cores, Some(2));
+        assert_eq!(merged.driver.memory, Some("1g".to_string()));
+        assert_eq!(merged.parent_template, None);
+        let conf = merged.spark_conf.unwrap();
+        assert_eq!(conf.get("spark.root"), Some(&"root".to_string()));
+        assert_eq!(conf.get("spark.child"), Some(&"child".to_string()));
+    }
+
+    #[test]
+    fn test_template_chain_provenance() {
+        let mut chain = SparkTemplateChain::default();
+        chain.push("root".to_string(), template_spec("
+        driver: {}
+        sparkConf:
+          spark.root: root
+          spark.job: root
+        deps:
+          jars:
+          - root.jar
+        ")).unwrap();
+        let job = template_spec("
+        driver: {}
+        sparkConf:
+          spark.job: job
+        deps:
+          jars:
+          - root.jar
+          - job.jar
+        ");
+
+        let merged = chain.merge(&job).unwrap();
+        let mut report = ProvenanceReport::default();
+        chain.record_provenance(&job, &merged, &mut report);
+        assert_eq!(report.spark_conf["spark.root"].source, "template/root");
+        assert_eq!(report.spark_conf["spark.job"].source, "spec");
+        assert_eq!(report.spark_conf["spark.job"].overwritten[0].source, "template/root");
+        assert_eq!(
+            report.warnings,
+            vec!["Spark config [spark.job] set by [template/root] is overwritten by [spec] with a different value"]
+        );
+        assert_eq!(report.deps["root.jar"], vec!["spec", "template/root"]);
+        assert_eq!(report.deps["job.jar"], vec!["spec"]);
+    }
+
+    #[test]
+    fn test_template_chain_cycle_and_depth() {
+        let mut chain = SparkTemplateChain::default();
+        chain.push("a".to_string(), SparkSpec::default()).unwrap();
+        chain.push("b".to_string(), SparkSpec::default()).unwrap();
+        assert!(matches!(
+            chain.push("a".to_string(), SparkSpec::default()),
+            Err(Error::SparkTemplateCycle { chain }) if chain == "a -> b -> a"
+        ));
+
+        let mut chain = SparkTemplateChain::default();
+        for i in 0..crate::constants::SPARK_TEMPLATE_MAX_DEPTH {
+            chain.push(format!("t{i}"), SparkSpec::default()).unwrap();
+        }
+        assert!(matches!(
+            chain.push("too-deep".to_string(), SparkSpec::default()),
+            Err(Error::SparkTemplateChainTooDeep { .. })
+        ));
+    }
+
     #[test]
     fn test_sko_migrated_sample() {
         let f = std::fs::OpenOptions::new()
@@ -0,0 +1,163 @@
+//! Exposure of the UIs of the sessions, the hive server2 UI and the Spark UI of the driver,
+//! by Ingresses and Gateway API HTTPRoutes owned by the session.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule, IngressServiceBackend, IngressSpec,
+    ServiceBackendPort,
+};
+use kube::core::ObjectMeta;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::gateway_api::{
+    HTTPBackendRef, HTTPPathMatch, HTTPRoute, HTTPRouteMatch, HTTPRouteRule, HTTPRouteSpec, ParentReference,
+};
+use crate::spark_application::SparkHiveserver2ServiceConfiguration;
+
+pub const HS2_UI_EXPOSURE_SUFFIX: &str = "-hs2-ui";
+pub const SPARK_UI_EXPOSURE_SUFFIX: &str = "-spark-ui";
+
+/// The HTTPRoute attached to a Gateway, requires the Gateway API CRDs
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRouteOptions {
+    /// the name of the Gateway
+    pub gateway: String,
+    /// the namespace of the Gateway, defaults to the namespace of the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway_namespace: Option<String>,
+    /// the listener of the Gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section_name: Option<String>,
+    /// for example: `spark-ui.example.com`, any host of the listener if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostnames: Option<Vec<String>>,
+}
+
+/// The Ingress and HTTPRoute of a UI, with the URL to reach it by
+#[derive(Clone, Debug, Default)]
+pub struct UiExposure {
+    pub ingress: Option<Ingress>,
+    pub http_route: Option<HTTPRoute>,
+    pub url: Option<String>,
+}
+
+/// The exposure of the `service` at `port` by the ingress and route fields of `options`,
+/// the objects are named and owned by `metadata`
+pub fn ui_exposure(
+    metadata: ObjectMeta,
+    options: &SparkHiveserver2ServiceConfiguration,
+    service: &str,
+    port: i32,
+) -> UiExposure {
+    let ingress = options.ingress_host.as_ref().map(|host| {
+        let tls = options.ingress_tls.clone().map(|mut tls| {
+            tls.hosts.get_or_insert_with(|| vec![host.clone()]);
+            vec![tls]
+        });
+        Ingress {
+            metadata: ObjectMeta {
+                annotations: options
+                    .ingress_annotations
+                    .clone()
+                    .map(|annotations| annotations.into_iter().collect::<BTreeMap<_, _>>()),
+                ..metadata.clone()
+            },
+            spec: Some(IngressSpec {
+                ingress_class_name: options.ingress_class_name.clone(),
+                rules: Some(vec![IngressRule {
+                    host: Some(host.clone()),
+                    http: Some(HTTPIngressRuleValue {
+                        paths: vec![HTTPIngressPath {
+                            path: Some("/".to_string()),
+                            path_type: "Prefix".to_string(),
+                            backend: IngressBackend {
+                                service: Some(IngressServiceBackend {
+                                    name: service.to_string(),
+                                    port: Some(ServiceBackendPort {
+                                        name: None,
+                                        number: Some(port),
+                                    }),
+                                }),
+                                resource: None,
+                            },
+                        }],
+                    }),
+                }]),
+                tls,
+                ..IngressSpec::default()
+            }),
+            ..Ingress::default()
+        }
+    });
+    let http_route = options.http_route.as_ref().map(|route| HTTPRoute {
+        metadata: metadata.clone(),
+        spec: HTTPRouteSpec {
+            parent_refs: vec![ParentReference {
+                name: route.gateway.clone(),
+                namespace: route.gateway_namespace.clone(),
+                section_name: route.section_name.clone(),
+            }],
+            hostnames: route.hostnames.clone(),
+            rules: vec![HTTPRouteRule {
+                matches: vec![HTTPRouteMatch {
+                    path: HTTPPathMatch {
+                        typ: "PathPrefix".to_string(),
+                        value: "/".to_string(),
+                    },
+                }],
+                backend_refs: vec![HTTPBackendRef {
+                    name: service.to_string(),
+                    port,
+                }],
+            }],
+        },
+    });
+
+    // the scheme of a route depends on the listener of the Gateway, which isn't known here
+    let ingress_url = options.ingress_host.as_ref().map(|host| {
+        let scheme = if options.ingress_tls.is_some() { "https" } else { "http" };
+        format!("{scheme}://{host}/")
+    });
+    let route_url = options
+        .http_route
+        .as_ref()
+        .and_then(|route| route.hostnames.as_ref()?.first().map(|host| format!("http://{host}/")));
+    UiExposure {
+        ingress,
+        http_route,
+        url: ingress_url.or(route_url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ui_exposure() {
+        let options: SparkHiveserver2ServiceConfiguration = serde_json::from_value(serde_json::json!({
+            "ingressHost": "hs2-ui.example.com",
+            "ingressClassName": "nginx",
+            "ingressTls": { "secretName": "hs2-ui-tls" },
+            "httpRoute": { "gateway": "public", "hostnames": ["hs2.example.com"] },
+        }))
+        .unwrap();
+        let metadata = ObjectMeta {
+            name: Some("session-hs2-ui".to_string()),
+            ..ObjectMeta::default()
+        };
+        let exposure = ui_exposure(metadata, &options, "session-hs2-ui-svc", 8009);
+
+        let ingress = exposure.ingress.unwrap().spec.unwrap();
+        assert_eq!(ingress.tls.unwrap()[0].hosts, Some(vec!["hs2-ui.example.com".to_string()]));
+        let backend = ingress.rules.unwrap()[0].http.clone().unwrap().paths[0].backend.clone();
+        assert_eq!(backend.service.unwrap().port.unwrap().number, Some(8009));
+        let route = exposure.http_route.unwrap();
+        assert_eq!(route.spec.parent_refs[0].name, "public");
+        assert_eq!(route.spec.rules[0].backend_refs[0].name, "session-hs2-ui-svc");
+        assert_eq!(exposure.url.as_deref(), Some("https://hs2-ui.example.com/"));
+    }
+}
@@ -6,6 +6,113 @@ use serde::de::DeserializeOwned;
 
 use crate::error::{ Result, Error };
 
+/// Creates the ConfigMaps the SKO application depends on, such as the pod templates
+/// and the provenance report.
+pub(crate) async fn deploy_config_maps(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    config_maps: &[k8s_openapi::api::core::v1::ConfigMap],
+) -> Result<()> {
+    let cm_api: Api<k8s_openapi::api::core::v1::ConfigMap> = Api::namespaced(client, namespace);
+    for cm in config_maps {
+        cm_api
+            .create(&kube::api::PostParams::default(), cm)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to create config map: {:?}", e);
+                Error::FailedDeployConfigMap { name: name.to_string() }
+            })?;
+    }
+    Ok(())
+}
+
+/// Creates the Secrets managed for the SKO application, such as the inline S3 credentials.
+pub(crate) async fn deploy_secrets(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    secrets: &[k8s_openapi::api::core::v1::Secret],
+) -> Result<()> {
+    let secret_api: Api<k8s_openapi::api::core::v1::Secret> = Api::namespaced(client, namespace);
+    for secret in secrets {
+        secret_api
+            .create(&kube::api::PostParams::default(), secret)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to create secret: {:?}", e);
+                Error::FailedDeploySecret { name: name.to_string() }
+            })?;
+    }
+    Ok(())
+}
+
+/// Applies the `resource` owned by the operator with the server-side apply, which creates or updates it.
+pub(crate) async fn apply_resource<K>(client: Client, namespace: &str, resource: &K) -> Result<K>
+where
+    K: Resource<Scope = k8s_openapi::NamespaceResourceScope>,
+    K: Clone + DeserializeOwned + serde::Serialize + std::fmt::Debug,
+    <K as kube::Resource>::DynamicType: Default,
+{
+    let name = resource.meta().name.clone().ok_or(Error::ResourceNameNotExists)?;
+    let api: Api<K> = Api::namespaced(client, namespace);
+    api.patch(
+        &name,
+        &PatchParams::apply(crd::constants::OPERATOR_NAME).force(),
+        &kube::api::Patch::Apply(resource),
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to apply resource: {:?}", e);
+        Error::FailedApplyResource { name: name.clone() }
+    })
+}
+
+/// Deletes the resource `name` owned by `owner_uid`, if any, which is left alone if it isn't owned.
+pub(crate) async fn delete_owned_resource<K>(client: Client, namespace: &str, name: &str, owner_uid: &str) -> Result<()>
+where
+    K: Resource<Scope = k8s_openapi::NamespaceResourceScope>,
+    K: Clone + DeserializeOwned + std::fmt::Debug,
+    <K as kube::Resource>::DynamicType: Default,
+{
+    let api: Api<K> = Api::namespaced(client, namespace);
+    let resource = api
+        .get_opt(name)
+        .await
+        .map_err(|_| Error::FailedDeleteResource { name: name.to_string() })?;
+    let owned = resource.is_some_and(|resource| {
+        resource
+            .meta()
+            .owner_references
+            .iter()
+            .flatten()
+            .any(|r| r.uid == owner_uid)
+    });
+    if owned {
+        api.delete(name, &kube::api::DeleteParams::default()).await.map_err(|e| {
+            tracing::error!("Failed to delete resource: {:?}", e);
+            Error::FailedDeleteResource { name: name.to_string() }
+        })?;
+    }
+    Ok(())
+}
+
+/// Exposes the template chain and the rendering warnings annotated on the SKO resource in `data["status"]`
+pub(crate) fn annotated_status(annotations: &std::collections::BTreeMap<String, String>, data: &mut serde_json::Value) {
+    let template_chain = annotations
+        .get(crd::constants::SPARK_TEMPLATE_CHAIN_ANNOTATION)
+        .map(|chain| chain.split(',').collect::<Vec<&str>>());
+    if let Some(template_chain) = template_chain {
+        data["status"]["templateChain"] = serde_json::json!(template_chain);
+    }
+    let warnings = annotations
+        .get(crd::constants::SPARK_WARNINGS_ANNOTATION)
+        .and_then(|warnings| serde_json::from_str::<Vec<String>>(warnings).ok());
+    if let Some(warnings) = warnings {
+        data["status"]["warnings"] = serde_json::json!(warnings);
+    }
+}
+
 /// Action to be taken upon an specific resource during reconciliation
 pub(crate) enum SparkApplicationAction {
     /// Create the subresources, this includes spawning `n` pods with service
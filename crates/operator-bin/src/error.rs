@@ -31,18 +31,39 @@ pub enum Error {
     #[error("Failed to delete a SKO Resource [{name}]")]
     FailedDeleteSKOResource { name: String },
     
+    #[error("Failed to probe the activity of a session from [{path}]")]
+    FailedProbeSession { path: String },
+
     #[error("Failed to create Service [{name}]")]
     FailedCreateService { name: String },
         
     #[error("Failed to create ConfigMap [{name}]")]
     FailedDeployConfigMap { name: String },
 
+    #[error("Failed to create Secret [{name}]")]
+    FailedDeploySecret { name: String },
+
     #[error("Failed to resolve HS2 UI Service for Spark Session [{name}]")]
     FailedResolveHS2UIService { name: String },
     
     #[error("Failed to resolve HS2 Thrift Service for Spark Session [{name}]")]
     FailedResolveHS2ThriftService { name: String },
     
+    #[error("Failed to build the resources of Hive Metastore [{name}]: {source}")]
+    FailedBuildHiveMetastore { name: String, source: crd::Error },
+
+    #[error("Failed to build the resources of Spark History Server [{name}]: {source}")]
+    FailedBuildHistoryServer { name: String, source: crd::Error },
+
+    #[error("Failed to delete resource [{name}]")]
+    FailedDeleteResource { name: String },
+
+    #[error("Failed to apply resource [{name}]")]
+    FailedApplyResource { name: String },
+
+    #[error("Failed to create Job [{name}]")]
+    FailedCreateJob { name: String },
+
     #[error("Error from Crd: [{source}]")]
     CrdError { source: crd::Error },
 }
@@ -0,0 +1,78 @@
+use k8s_openapi::api::networking::v1::Ingress;
+use kube::api::{Patch, PatchParams};
+use kube::{client::Client, runtime::controller::Action, Api};
+use kube::ResourceExt;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use tokio::time::Duration;
+
+use crate::controller::{apply_resource, delete_owned_resource, ContextData};
+use crd::history_server::{SparkHistoryServer, SparkHistoryServerPhase};
+
+/// Deploys the history server, the subresources are owned by it and garbage collected along with it.
+pub async fn reconcile(resource: Arc<SparkHistoryServer>, context: Arc<ContextData>) -> Result<Action> {
+    let client: Client = context.client.clone();
+    let name = resource.name_any();
+    let namespace: String = match resource.namespace() {
+        None => {
+            return Err(Error::ResourceNamespaceNotExists { name });
+        }
+        Some(namespace) => namespace,
+    };
+
+    let resources = resource
+        .history_server_resources(&client, &namespace)
+        .await
+        .map_err(|e| Error::FailedBuildHistoryServer {
+            name: name.to_string(),
+            source: e,
+        })?;
+    apply_resource(client.clone(), &namespace, &resources.config_map).await?;
+    for secret in &resources.secrets {
+        apply_resource(client.clone(), &namespace, secret).await?;
+    }
+    let deployment = apply_resource(client.clone(), &namespace, &resources.deployment).await?;
+    apply_resource(client.clone(), &namespace, &resources.service).await?;
+    match &resources.ingress {
+        Some(ingress) => {
+            apply_resource(client.clone(), &namespace, ingress).await?;
+        }
+        // the ingress is removed from the spec
+        None => {
+            let uid = resource.uid().unwrap_or_default();
+            delete_owned_resource::<Ingress>(client.clone(), &namespace, &name, &uid).await?
+        }
+    }
+
+    let ready = deployment
+        .status
+        .and_then(|s| s.ready_replicas)
+        .unwrap_or_default();
+    let phase = if ready > 0 {
+        SparkHistoryServerPhase::Running
+    } else {
+        SparkHistoryServerPhase::Starting
+    };
+    let api: Api<SparkHistoryServer> = Api::namespaced(client, &namespace);
+    let status = json!({
+        "status": {
+            "phase": phase.to_string(),
+            "url": resource.url(),
+        }
+    });
+    api.patch_status(&name, &PatchParams::default(), &Patch::Merge(&status))
+        .await
+        .map_err(|_| Error::FailedPatchResource {
+            name: name.to_string(),
+        })?;
+
+    // the changes of the owned Deployment trigger the reconciliation as well
+    Ok(Action::requeue(Duration::from_secs(300)))
+}
+
+pub fn on_error(resource: Arc<SparkHistoryServer>, error: &Error, _context: Arc<ContextData>) -> Action {
+    tracing::error!("Reconciliation error:\n{:?}.\n{:?}", error, resource.name_any());
+    Action::requeue(Duration::from_secs(5))
+}
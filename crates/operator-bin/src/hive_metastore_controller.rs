@@ -0,0 +1,117 @@
+use k8s_openapi::api::batch::v1::Job;
+use kube::api::{Patch, PatchParams, PostParams};
+use kube::{client::Client, runtime::controller::Action, Api};
+use kube::ResourceExt;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use tokio::time::Duration;
+
+use crate::controller::{apply_resource, ContextData};
+use crd::hive_metastore::{HiveMetastore, HiveMetastorePhase};
+
+/// Deploys the metastore, the schema Job is run first and the Deployment and Service are applied
+/// once it succeeds. The subresources are owned by the metastore, so they are garbage collected
+/// along with it and no finalizer is needed.
+pub async fn reconcile(resource: Arc<HiveMetastore>, context: Arc<ContextData>) -> Result<Action> {
+    let client: Client = context.client.clone();
+    let name = resource.name_any();
+    let namespace: String = match resource.namespace() {
+        None => {
+            return Err(Error::ResourceNamespaceNotExists { name });
+        }
+        Some(namespace) => namespace,
+    };
+
+    let resources = resource
+        .metastore_resources(&client, &namespace)
+        .await
+        .map_err(|e| Error::FailedBuildHiveMetastore {
+            name: name.to_string(),
+            source: e,
+        })?;
+    apply_resource(client.clone(), &namespace, &resources.config_map).await?;
+    for secret in &resources.secrets {
+        apply_resource(client.clone(), &namespace, secret).await?;
+    }
+
+    let phase = match run_schema_job(client.clone(), &namespace, &resources.schema_job).await? {
+        Some(true) => {
+            let deployment = apply_resource(client.clone(), &namespace, &resources.deployment).await?;
+            apply_resource(client.clone(), &namespace, &resources.service).await?;
+            let ready = deployment
+                .status
+                .and_then(|s| s.ready_replicas)
+                .unwrap_or_default();
+            if ready > 0 {
+                HiveMetastorePhase::Running
+            } else {
+                HiveMetastorePhase::Starting
+            }
+        }
+        Some(false) => HiveMetastorePhase::Failed,
+        None => HiveMetastorePhase::InitializingSchema,
+    };
+
+    let api: Api<HiveMetastore> = Api::namespaced(client, &namespace);
+    let status = json!({
+        "status": {
+            "phase": phase.to_string(),
+            "uri": resource.uri(),
+        }
+    });
+    api.patch_status(&name, &PatchParams::default(), &Patch::Merge(&status))
+        .await
+        .map_err(|_| Error::FailedPatchResource {
+            name: name.to_string(),
+        })?;
+
+    // the changes of the owned Job and Deployment trigger the reconciliation as well
+    match phase {
+        HiveMetastorePhase::Running | HiveMetastorePhase::Failed => Ok(Action::requeue(Duration::from_secs(300))),
+        _ => Ok(Action::requeue(Duration::from_secs(10))),
+    }
+}
+
+pub fn on_error(resource: Arc<HiveMetastore>, error: &Error, _context: Arc<ContextData>) -> Action {
+    tracing::error!("Reconciliation error:\n{:?}.\n{:?}", error, resource.name_any());
+    Action::requeue(Duration::from_secs(5))
+}
+
+/// Creates the schema Job unless it exists, and returns whether it has succeeded or failed,
+/// none while it is running.
+async fn run_schema_job(client: Client, namespace: &str, job: &Job) -> Result<Option<bool>> {
+    let job_api: Api<Job> = Api::namespaced(client, namespace);
+    let name = job.name_any();
+    let existing = job_api
+        .get_opt(&name)
+        .await
+        .map_err(|_| Error::FailedCreateJob { name: name.clone() })?;
+    let job = match existing {
+        Some(job) => job,
+        None => job_api
+            .create(&PostParams::default(), job)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to create schema job: {:?}", e);
+                Error::FailedCreateJob { name: name.clone() }
+            })?,
+    };
+
+    let condition = |typ: &str| {
+        job.status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .is_some_and(|conditions| {
+                conditions.iter().any(|c| c.type_ == typ && c.status == "True")
+            })
+    };
+    if condition("Complete") {
+        Ok(Some(true))
+    } else if condition("Failed") {
+        Ok(Some(false))
+    } else {
+        Ok(None)
+    }
+}
@@ -1,5 +1,7 @@
 mod controller;
 mod error;
+mod hive_metastore_controller;
+mod history_server_controller;
 mod sko_application_controller;
 mod sko_schd_application_controller;
 mod spark_job_controller;
@@ -66,6 +68,9 @@ pub struct SkoParams {
     /// Provides the path to a job or session yaml
     #[arg(long, short = 'f')]
     pub file: String,
+    /// Prints the provenance report of the spark configs and deps as well
+    #[arg(long)]
+    pub provenance: bool,
 }
 
 #[derive(clap::Parser, Debug, PartialEq, Eq)]
@@ -114,7 +119,7 @@ async fn main() -> anyhow::Result<()> {
             let kube_client = kube::client::Client::try_default()
                 .await
                 .expect("Failed to create kube client");
-            crd::print_sko_object_from(sko.typ, sko.file, &kube_client).await?;
+            crd::print_sko_object_from(sko.typ, sko.file, sko.provenance, &kube_client).await?;
             return Ok(());
         }
 
@@ -160,6 +165,10 @@ async fn main() -> anyhow::Result<()> {
                 get_api(kube_client.clone(), namespace);
             let sko_schd_app_crd_api: Api<crd::sko_spark_application::ScheduledSparkApplication> = 
                 get_api(kube_client.clone(), namespace);
+            let hive_metastore_crd_api: Api<crd::hive_metastore::HiveMetastore> =
+                get_api(kube_client.clone(), namespace);
+            let history_server_crd_api: Api<crd::history_server::SparkHistoryServer> =
+                get_api(kube_client.clone(), namespace);
 
             // The controller comes from the `kube_runtime` crate and manages the reconciliation process.
             // It requires the following information:
@@ -253,12 +262,62 @@ async fn main() -> anyhow::Result<()> {
                     };
                 });
 
+            // the owned Deployments and Jobs trigger the reconciliation of their metastores
+            let hms_controller = Controller::new(hive_metastore_crd_api.clone(), Config::default())
+                .owns(
+                    get_api::<k8s_openapi::api::apps::v1::Deployment>(kube_client.clone(), namespace),
+                    Config::default(),
+                )
+                .owns(
+                    get_api::<k8s_openapi::api::batch::v1::Job>(kube_client.clone(), namespace),
+                    Config::default(),
+                )
+                .run(
+                    hive_metastore_controller::reconcile,
+                    hive_metastore_controller::on_error,
+                    Arc::new(ContextData::new(kube_client.clone())),
+                )
+                .map(|reconciliation_result| {
+                    match reconciliation_result {
+                        Ok(resource) => {
+                            tracing::info!("Reconciliation successful. Resource: {:?}", resource);
+                        }
+                        Err(reconciliation_err) => {
+                            tracing::error!("Reconciliation error: {:?}", reconciliation_err)
+                        }
+                    };
+                });
+
+            let shs_controller = Controller::new(history_server_crd_api.clone(), Config::default())
+                .owns(
+                    get_api::<k8s_openapi::api::apps::v1::Deployment>(kube_client.clone(), namespace),
+                    Config::default(),
+                )
+                .run(
+                    history_server_controller::reconcile,
+                    history_server_controller::on_error,
+                    Arc::new(ContextData::new(kube_client.clone())),
+                )
+                .map(|reconciliation_result| {
+                    match reconciliation_result {
+                        Ok(resource) => {
+                            tracing::info!("Reconciliation successful. Resource: {:?}", resource);
+                        }
+                        Err(reconciliation_err) => {
+                            tracing::error!("Reconciliation error: {:?}", reconciliation_err)
+                        }
+                    };
+                });
+
             futures::stream::select(
                 futures::stream::select(
                     futures::stream::select(scj_controller, ssj_controler),
                     scs_controller,
                 ),
-                futures::stream::select(sko_app_controller, sko_schd_app_controller),
+                futures::stream::select(
+                    futures::stream::select(sko_app_controller, sko_schd_app_controller),
+                    futures::stream::select(hms_controller, shs_controller),
+                ),
             )
             .collect::<()>()
             .await;
@@ -1,5 +1,5 @@
 use crd::spark_application::{SparkJob, SparkSession};
-use kube::{runtime::controller::Action, ResourceExt};
+use kube::{runtime::controller::Action, Resource, ResourceExt};
 use std::sync::Arc;
 use tokio::time::Duration;
 
@@ -12,6 +12,10 @@ pub async fn reconcile(
     context: Arc<ContextData>,
 ) -> Result<Action> {
     let name = resource.name_any();
+    // a deleted application, e.g. of a suspended session, must not overwrite the phase of its owner
+    if resource.meta().deletion_timestamp.is_some() {
+        return Ok(Action::await_change());
+    }
 
     match determine_spark_app::<SKOSparkApplication>(&resource, &name)? {
         OwnerType::SparkJob => {
@@ -67,7 +71,7 @@ fn status_json(resource: &SKOSparkApplication, res_name: &String) -> Result<serd
         .status
         .as_ref()
         .and_then(|s| s.app_state.as_ref());
-    let data = match state {
+    let mut data = match state {
         Some(state) => {
             if state.error_message.is_some() {
                 tracing::warn!(
@@ -97,5 +101,7 @@ fn status_json(resource: &SKOSparkApplication, res_name: &String) -> Result<serd
             }})
         }
     };
+    // expose the resolved template chain and the warnings of the job/session
+    crate::controller::annotated_status(resource.annotations(), &mut data);
     Ok(data)
 }
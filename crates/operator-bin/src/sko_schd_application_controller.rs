@@ -61,7 +61,7 @@ fn status_json(
         .status
         .as_ref()
         .and_then(|s| s.schedule_state.as_ref());
-    let data = match state {
+    let mut data = match state {
         Some(state) => {    
             serde_json::json!({
             "status": {
@@ -76,5 +76,6 @@ fn status_json(
             }})
         }
     };
+    crate::controller::annotated_status(resource.annotations(), &mut data);
     Ok(data)
 }
@@ -104,13 +104,17 @@ pub async fn deploy(
             })?;
     }
 
-    let appl = resource
-        .sko_application(&client, namespace)
+    let resources = resource
+        .sko_resources(&client, namespace)
         .await
         .map_err(|e| Error::FailedBuildSKOApplication {
             name: name.to_string(),
             source: e
         })?;
+    crate::controller::deploy_secrets(client.clone(), name, namespace, &resources.secrets).await?;
+    crate::controller::deploy_config_maps(client.clone(), name, namespace, &resources.config_maps)
+        .await?;
+    let appl = resources.application;
     // Create the deployment defined above
     let deployment_api: Api<crd::sko_spark_application::ScheduledSparkApplication> =
         Api::namespaced(client, namespace);
@@ -1,5 +1,7 @@
-use k8s_openapi::api::core::v1::Service;
-use kube::api::{DeleteParams, PostParams};
+use k8s_openapi::api::core::v1::{ConfigMap, Node, Pod, Service};
+use k8s_openapi::api::networking::v1::Ingress;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::{DeleteParams, ListParams, PostParams};
 use kube::api::{Patch, PatchParams};
 use kube::{client::Client, runtime::controller::Action, Api};
 use kube::{Resource, ResourceExt};
@@ -9,7 +11,9 @@ use std::sync::Arc;
 use crate::error::{Error, Result};
 use tokio::time::Duration;
 
-use crate::controller::{ContextData, SparkApplicationAction};
+use crate::controller::{apply_resource, delete_owned_resource, ContextData, SparkApplicationAction};
+use crd::gateway_api::HTTPRoute;
+use crd::idle::{self, IdleProbe};
 use crd::spark_application::SparkSession;
 
 pub async fn reconcile(resource: Arc<SparkSession>, context: Arc<ContextData>) -> Result<Action> {
@@ -36,7 +40,11 @@ pub async fn reconcile(resource: Arc<SparkSession>, context: Arc<ContextData>) -
         }
         // The resource is already in desired state, do nothing and re-check after 10 seconds
         // SparkApplicationAction::NoOp => Ok(Action::requeue(Duration::from_secs(10))),
-        SparkApplicationAction::NoOp => Ok(Action::await_change()),
+        SparkApplicationAction::NoOp => {
+            let node_port_conflicts = reconcile_services(client.clone(), &name, &namespace, &resource).await?;
+            reconcile_endpoints(client.clone(), &name, &namespace, &resource, node_port_conflicts).await?;
+            reconcile_idle(client, &name, &namespace, &resource).await
+        }
     };
 }
 
@@ -78,57 +86,100 @@ pub async fn deploy(
     namespace: &str,
     resource: &SparkSession,
 ) -> Result<crd::sko_spark_application::SparkApplication> {
-    let appl = resource
-        .sko_application(&client, namespace)
+    let resources = resource
+        .sko_resources(&client, namespace)
         .await
         .map_err(|e| Error::FailedBuildSKOApplication {
             name: name.to_string(),
             source: e
         })?;
+    crate::controller::deploy_secrets(client.clone(), name, namespace, &resources.secrets).await?;
+    crate::controller::deploy_config_maps(client.clone(), name, namespace, &resources.config_maps)
+        .await?;
+    let ret = create_application(client.clone(), name, namespace, &resources.application).await?;
 
-    // Create the deployment defined above
-    let deployment_api: Api<crd::sko_spark_application::SparkApplication> =
-        Api::namespaced(client.clone(), namespace);
-    let ret = deployment_api
-        .create(&PostParams::default(), &appl)
-        .await
-        .map_err(|e| {            
-            tracing::error!("Failed to create SKO resource: {:?}", e);
-            Error::FailedDeploySKOResource { name: name.to_string(),}
-        })?;
+    // create the services, the ones of conflicting NodePorts are created by the next reconciles
+    reconcile_services(client, name, namespace, resource).await?;
+    Ok(ret)
+}
 
-    // create the services
-    let svc_api: Api<Service> = Api::namespaced(client, namespace);
-    let svc = resource
-        .hive_server2_thrift_service()
-        .map_err(|_| Error::FailedResolveHS2ThriftService {
+/// Creates the missing thrift and UI Services of the session, unless their NodePorts are already allocated
+/// to other Services of the cluster, which are returned to be reported in status.
+async fn reconcile_services(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    resource: &SparkSession,
+) -> Result<Vec<String>> {
+    let services = [
+        resource.hive_server2_thrift_service().map_err(|_| Error::FailedResolveHS2ThriftService {
             name: name.to_string(),
-        })?;
-    let _hs2_thrift_svc = svc_api
-        .create(
-            &PostParams::default(),
-            &svc,
-        )
-        .await.map_err(|e| {
-            tracing::error!("Failed to create HS2 thrift service: {:?}", e);
-            Error::FailedCreateService { name: svc.name_any() }
-        })?;
-    let svc = resource
-        .hive_server2_ui_service()
-        .map_err(|_| Error::FailedResolveHS2UIService {
+        })?,
+        resource.hive_server2_ui_service().map_err(|_| Error::FailedResolveHS2UIService {
             name: name.to_string(),
+        })?,
+    ];
+    let svc_api: Api<Service> = Api::namespaced(client.clone(), namespace);
+    let mut conflicts = vec![];
+    // the Services of the cluster, only listed to name the conflicts once the API server rejects a NodePort
+    let mut others: Option<Vec<Service>> = None;
+    for svc in services {
+        let svc_name = svc.name_any();
+        let existing = svc_api.get_opt(&svc_name).await.map_err(|e| {
+            tracing::error!("Failed to get service {}: {:?}", svc_name, e);
+            Error::FailedCreateService { name: svc_name.clone() }
         })?;
+        if existing.is_some() {
+            continue;
+        }
+        match svc_api.create(&PostParams::default(), &svc).await {
+            Ok(_) => {}
+            Err(e) if crd::endpoints::is_node_port_allocated(&e) => {
+                let others = match others.as_mut() {
+                    Some(others) => others,
+                    None => {
+                        let list = Api::<Service>::all(client.clone())
+                            .list(&ListParams::default())
+                            .await
+                            .map_err(|e| {
+                                tracing::error!("Failed to list services: {:?}", e);
+                                Error::FailedCreateService { name: svc_name.clone() }
+                            })?;
+                        others.insert(list.items)
+                    }
+                };
+                let mut svc_conflicts = crd::endpoints::node_port_conflicts(&svc, namespace, others);
+                if svc_conflicts.is_empty() {
+                    // the other Service may be out of the list already, or not be visible to the operator
+                    svc_conflicts.push(format!("{svc_name}: {e}"));
+                }
+                tracing::warn!("NodePorts of service {} conflict: {:?}", svc_name, svc_conflicts);
+                conflicts.extend(svc_conflicts);
+            }
+            Err(e) => {
+                tracing::error!("Failed to create HS2 service: {:?}", e);
+                return Err(Error::FailedCreateService { name: svc_name });
+            }
+        }
+    }
+    Ok(conflicts)
+}
 
-    let _hs2_ui_svc = svc_api
-        .create(
-            &PostParams::default(),
-            &svc,
-        )
-        .await.map_err(|e| {
-            tracing::error!("Failed to create HS2 UI service: {:?}", e);
-            Error::FailedCreateService { name: svc.name_any() }
-        })?;
-    Ok(ret)
+async fn create_application(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    appl: &crd::sko_spark_application::SparkApplication,
+) -> Result<crd::sko_spark_application::SparkApplication> {
+    let deployment_api: Api<crd::sko_spark_application::SparkApplication> =
+        Api::namespaced(client, namespace);
+    deployment_api
+        .create(&PostParams::default(), appl)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create SKO resource: {:?}", e);
+            Error::FailedDeploySKOResource { name: name.to_string() }
+        })
 }
 
 /// Deletes an existing SparkApplication.
@@ -147,6 +198,286 @@ pub async fn delete(client: Client, name: &str, namespace: &str) -> Result<(), E
     Ok(())
 }
 
+/// Applies the Ingresses and HTTPRoutes of the UIs, deletes the removed ones, and returns their URLs.
+async fn reconcile_ui_exposures(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    resource: &SparkSession,
+) -> Result<[Option<String>; 2]> {
+    let [hs2_ui, spark_ui] = resource
+        .ui_exposures()
+        .map_err(|_| Error::FailedResolveHS2UIService {
+            name: name.to_string(),
+        })?;
+    let uid = resource.uid().unwrap_or_default();
+    for (suffix, exposure) in [
+        (crd::ui_exposure::HS2_UI_EXPOSURE_SUFFIX, &hs2_ui),
+        (crd::ui_exposure::SPARK_UI_EXPOSURE_SUFFIX, &spark_ui),
+    ] {
+        let exposure_name = common::utils::repair_resource_name(&format!("{name}{suffix}"));
+        match &exposure.ingress {
+            Some(ingress) => {
+                apply_resource(client.clone(), namespace, ingress).await?;
+            }
+            None => delete_owned_resource::<Ingress>(client.clone(), namespace, &exposure_name, &uid).await?,
+        }
+        match &exposure.http_route {
+            Some(route) => {
+                apply_resource(client.clone(), namespace, route).await?;
+            }
+            None => delete_owned_resource::<HTTPRoute>(client.clone(), namespace, &exposure_name, &uid).await?,
+        }
+    }
+    Ok([hs2_ui.url, spark_ui.url])
+}
+
+/// Reads back the thrift and UI Services of the session, and publishes its connection endpoints in status,
+/// and in the connection ConfigMap if enabled. The endpoints follow the authentication and TLS of the spec,
+/// the `node_port_conflicts` of [`reconcile_services`] are published along with them.
+async fn reconcile_endpoints(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    resource: &SparkSession,
+    node_port_conflicts: Vec<String>,
+) -> Result<()> {
+    let [ui_url, spark_ui_url] = reconcile_ui_exposures(client.clone(), name, namespace, resource).await?;
+    let thrift_service = get_service(client.clone(), namespace, resource.hive_server2_thrift_service()).await?;
+    let ui_service = get_service(client.clone(), namespace, resource.hive_server2_ui_service()).await?;
+    let node_address = driver_node_address(client.clone(), name, namespace).await;
+    let mut endpoints = resource
+        .endpoints(
+            thrift_service.as_ref(),
+            ui_service.as_ref(),
+            node_address.as_deref(),
+            ui_url,
+            spark_ui_url,
+        )
+        .map_err(|source| Error::CrdError { source })?;
+    endpoints.node_port_conflicts = Some(node_port_conflicts).filter(|c| !c.is_empty());
+
+    let connection_name = common::utils::repair_resource_name(&format!(
+        "{name}{}",
+        crd::endpoints::CONNECTION_CONFIG_MAP_SUFFIX
+    ));
+    if resource.spec.session.connection_config_map.unwrap_or(false) {
+        let config_map = resource
+            .connection_config_map(&endpoints)
+            .map_err(|source| Error::CrdError { source })?;
+        apply_resource(client.clone(), namespace, &config_map).await?;
+    } else {
+        let uid = resource.uid().unwrap_or_default();
+        delete_owned_resource::<ConfigMap>(client.clone(), namespace, &connection_name, &uid).await?;
+    }
+
+    // the status is patched once the phase is set, which is required
+    match &resource.status {
+        Some(status) if !endpoints.published_in(status) => {}
+        _ => return Ok(()),
+    }
+    patch_status(client, name, namespace, json!({ "status": endpoints })).await
+}
+
+/// Reads back the created `service`, none if it isn't created yet
+async fn get_service(
+    client: Client,
+    namespace: &str,
+    service: crd::Result<Service>,
+) -> Result<Option<Service>> {
+    let service = service.map_err(|source| Error::CrdError { source })?;
+    let name = service.name_any();
+    let api: Api<Service> = Api::namespaced(client, namespace);
+    api.get_opt(&name).await.map_err(|e| {
+        tracing::error!("Failed to get service {}: {:?}", name, e);
+        Error::FailedResolveHS2ThriftService { name }
+    })
+}
+
+/// The address of the node the driver of the session runs on, to reach the NodePorts by.
+/// None until the driver is scheduled, or if the node can't be read.
+async fn driver_node_address(client: Client, name: &str, namespace: &str) -> Option<String> {
+    let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
+    let driver = pods.get_opt(&format!("{name}-driver")).await.ok()??;
+    let node_name = driver.spec?.node_name?;
+    let nodes: Api<Node> = Api::all(client);
+    let node = nodes.get(&node_name).await.ok()?;
+    crd::endpoints::node_address(&node)
+}
+
+// ---------------------
+// idle timeout
+
+/// Suspends the session once idle for its `idleTimeout`, and resumes a suspended session
+/// annotated with `spark.bytenative.com/resume`.
+async fn reconcile_idle(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    resource: &SparkSession,
+) -> Result<Action> {
+    let phase = resource.status.as_ref().map(|s| s.phase.as_str());
+    if phase == Some(idle::SESSION_PHASE_SUSPENDED) {
+        if resource.annotations().contains_key(idle::SESSION_RESUME_ANNOTATION) {
+            resume(client, name, namespace, resource).await?;
+        }
+        return Ok(Action::await_change());
+    }
+    let timeout = match resource.spec.session.idle_timeout() {
+        Ok(Some(timeout)) => timeout,
+        Ok(None) => return Ok(Action::await_change()),
+        Err(e) => {
+            tracing::warn!("Ignored the idle timeout of the session [{name}]: {e}");
+            return Ok(Action::await_change());
+        }
+    };
+    let recheck = Action::requeue(timeout.min(Duration::from_secs(60)));
+    if phase == Some(idle::SESSION_PHASE_RESUMING) {
+        return Ok(recheck);
+    }
+
+    // the inactivity is unknown while the probe fails, e.g. the driver is still starting
+    let now = Utc::now();
+    let last_activity = match probe_last_activity(client.clone(), namespace, resource, now).await {
+        Ok(Some(last_activity)) => last_activity,
+        Ok(None) => return Ok(recheck),
+        Err(e) => {
+            tracing::debug!("Failed to probe the activity of the session [{name}]: {e:?}");
+            return Ok(recheck);
+        }
+    };
+    let idle_for = (now - last_activity).to_std().unwrap_or_default();
+    if idle_for >= timeout {
+        tracing::info!("Suspending the session [{name}] idle since {last_activity}");
+        suspend(client, name, namespace, last_activity).await?;
+        return Ok(Action::await_change());
+    }
+    Ok(recheck)
+}
+
+async fn probe_last_activity(
+    client: Client,
+    namespace: &str,
+    resource: &SparkSession,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>> {
+    match resource.spec.session.idle_probe.clone().unwrap_or_default() {
+        IdleProbe::SparkUi(probe) => {
+            let port = probe.port.unwrap_or(idle::SPARK_UI_PORT);
+            let service = format!("{}{}", resource.name_any(), idle::SPARK_UI_SERVICE_SUFFIX);
+            let get = |path: String| {
+                proxy_get(client.clone(), idle::service_proxy_path(namespace, &service, port, &path))
+            };
+            // a connected client keeps the session active, even when it doesn't run any jobs
+            let page = proxy_get_text(
+                client.clone(),
+                idle::service_proxy_path(namespace, &service, port, idle::SPARK_THRIFT_SERVER_UI_PATH),
+            )
+            .await?;
+            match idle::thrift_server_online_sessions(&page) {
+                Some(0) => {}
+                Some(_) => return Ok(Some(now)),
+                None => {
+                    tracing::debug!("No online sessions in the thrift server page of the session");
+                    return Ok(None);
+                }
+            }
+            let applications = get("api/v1/applications".to_string()).await?;
+            let Some(id) = idle::spark_application_id(&applications) else {
+                return Ok(None);
+            };
+            let jobs = get(format!("api/v1/applications/{id}/jobs")).await?;
+            Ok(idle::spark_last_activity(&applications, &jobs, now))
+        }
+        IdleProbe::Http(probe) => {
+            let path = idle::service_proxy_path(namespace, &probe.service, probe.port, &probe.path);
+            Ok(idle::http_last_activity(&proxy_get(client, path).await?))
+        }
+    }
+}
+
+async fn proxy_get(client: Client, path: String) -> Result<Value> {
+    let text = proxy_get_text(client, path.clone()).await?;
+    serde_json::from_str(&text).map_err(|_| Error::FailedProbeSession { path })
+}
+
+async fn proxy_get_text(client: Client, path: String) -> Result<String> {
+    let failed = || Error::FailedProbeSession { path: path.clone() };
+    let request = http::Request::get(&path).body(vec![]).map_err(|_| failed())?;
+    client.request_text(request).await.map_err(|_| failed())
+}
+
+/// Deletes the SKO application of the session, the services, config maps and secrets are kept for
+/// the resume.
+async fn suspend(client: Client, name: &str, namespace: &str, last_activity: DateTime<Utc>) -> Result<()> {
+    let api: Api<crd::sko_spark_application::SparkApplication> = Api::namespaced(client.clone(), namespace);
+    api.delete(name, &DeleteParams::default()).await.map_err(|e| {
+        tracing::error!("Failed to delete SKO resource: {:?}", e);
+        Error::FailedDeleteSKOResource { name: name.to_string() }
+    })?;
+    patch_status(
+        client,
+        name,
+        namespace,
+        json!({
+            "status": {
+                "phase": idle::SESSION_PHASE_SUSPENDED,
+                "lastActivityTime": last_activity.to_rfc3339(),
+            }
+        }),
+    )
+    .await
+}
+
+/// Re-creates the SKO application of a suspended session and removes the resume annotation.
+async fn resume(client: Client, name: &str, namespace: &str, resource: &SparkSession) -> Result<()> {
+    let resources = resource
+        .sko_resources(&client, namespace)
+        .await
+        .map_err(|e| Error::FailedBuildSKOApplication {
+            name: name.to_string(),
+            source: e,
+        })?;
+    // the config maps and secrets are owned by the session and kept while suspended, applied for the changes of the spec
+    for secret in &resources.secrets {
+        apply_resource(client.clone(), namespace, secret).await?;
+    }
+    for config_map in &resources.config_maps {
+        apply_resource(client.clone(), namespace, config_map).await?;
+    }
+    create_application(client.clone(), name, namespace, &resources.application).await?;
+
+    patch_status(
+        client.clone(),
+        name,
+        namespace,
+        json!({ "status": { "phase": idle::SESSION_PHASE_RESUMING } }),
+    )
+    .await?;
+    let api: Api<SparkSession> = Api::namespaced(client, namespace);
+    let annotation = json!({
+        "metadata": {
+            "annotations": { idle::SESSION_RESUME_ANNOTATION: null }
+        }
+    });
+    api.patch(name, &PatchParams::default(), &Patch::Merge(&annotation))
+        .await
+        .map_err(|_| Error::FailedPatchResource {
+            name: name.to_string(),
+        })?;
+    Ok(())
+}
+
+async fn patch_status(client: Client, name: &str, namespace: &str, status: Value) -> Result<()> {
+    let api: Api<SparkSession> = Api::namespaced(client, namespace);
+    api.patch_status(name, &PatchParams::default(), &Patch::Merge(&status))
+        .await
+        .map_err(|_| Error::FailedPatchResource {
+            name: name.to_string(),
+        })?;
+    Ok(())
+}
+
 // ---------------------
 // finalizers
 // TODO: move functions out of this module
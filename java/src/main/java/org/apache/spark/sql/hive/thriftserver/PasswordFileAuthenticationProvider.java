@@ -0,0 +1,70 @@
+package org.apache.spark.sql.hive.thriftserver;
+
+import java.io.IOException;
+import java.nio.charset.StandardCharsets;
+import java.nio.file.Files;
+import java.nio.file.Paths;
+import java.security.MessageDigest;
+import java.security.NoSuchAlgorithmException;
+import java.util.List;
+
+import javax.security.sasl.AuthenticationException;
+
+import org.apache.hadoop.conf.Configured;
+import org.apache.hive.service.auth.PasswdAuthenticationProvider;
+
+/**
+ * Authenticates the clients of the hive server2 with the users of the file of
+ * `hive.server2.custom.authentication.file`, one `user:password` or `user:sha256:hex digest` per line.
+ * The file is read on every authentication, so the changes of the mounted Secret apply without a restart.
+ */
+public class PasswordFileAuthenticationProvider extends Configured implements PasswdAuthenticationProvider {
+    static final String PASSWORD_FILE_CONF = "hive.server2.custom.authentication.file";
+    static final String SHA256_PREFIX = "sha256:";
+
+    @Override
+    public void Authenticate(String user, String password) throws AuthenticationException {
+        String file = getConf() == null ? null : getConf().get(PASSWORD_FILE_CONF);
+        if (file == null) {
+            throw new AuthenticationException(PASSWORD_FILE_CONF + " is not set");
+        }
+        List<String> lines;
+        try {
+            lines = Files.readAllLines(Paths.get(file), StandardCharsets.UTF_8);
+        } catch (IOException e) {
+            throw new AuthenticationException("Failed to read the password file " + file, e);
+        }
+        for (String line : lines) {
+            int i = line.indexOf(':');
+            if (i > 0 && line.substring(0, i).equals(user) && matches(line.substring(i + 1), password)) {
+                return;
+            }
+        }
+        throw new AuthenticationException("Invalid user or password of " + user);
+    }
+
+    private static boolean matches(String expected, String password) throws AuthenticationException {
+        byte[] actual = password == null ? new byte[0] : password.getBytes(StandardCharsets.UTF_8);
+        if (expected.startsWith(SHA256_PREFIX)) {
+            expected = expected.substring(SHA256_PREFIX.length()).toLowerCase();
+            actual = toHex(sha256(actual)).getBytes(StandardCharsets.UTF_8);
+        }
+        return MessageDigest.isEqual(expected.getBytes(StandardCharsets.UTF_8), actual);
+    }
+
+    private static byte[] sha256(byte[] value) throws AuthenticationException {
+        try {
+            return MessageDigest.getInstance("SHA-256").digest(value);
+        } catch (NoSuchAlgorithmException e) {
+            throw new AuthenticationException("SHA-256 is not supported", e);
+        }
+    }
+
+    private static String toHex(byte[] bytes) {
+        StringBuilder hex = new StringBuilder();
+        for (byte b : bytes) {
+            hex.append(String.format("%02x", b));
+        }
+        return hex.toString();
+    }
+}